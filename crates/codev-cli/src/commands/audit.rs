@@ -0,0 +1,58 @@
+//! `codev audit` - check and read the audit log
+
+use clap::Subcommand;
+use codev_core::audit::parse_since;
+use codev_core::{AuditLog, ConfigLoader};
+use std::path::PathBuf;
+
+#[derive(Subcommand)]
+pub enum AuditCommands {
+    Verify {
+        #[arg(long, help = "Log to check (default: security.audit.path)")]
+        file: Option<PathBuf>,
+    },
+    Show {
+        #[arg(long, help = "Only records since a time, date or age, e.g. 2025-06-01 or 12h")]
+        since: Option<String>,
+        #[arg(long, help = "Log to read (default: security.audit.path)")]
+        file: Option<PathBuf>,
+    },
+}
+
+pub async fn handle_audit_command(cmd: AuditCommands) -> anyhow::Result<()> {
+    match cmd {
+        AuditCommands::Verify { file } => {
+            let path = log_path(file)?;
+            let verified = AuditLog::verify(&path)?;
+            println!("✅ {} records, hash chain intact", verified.records);
+            println!("   Last hash: {}", verified.last_hash);
+        }
+        AuditCommands::Show { since, file } => {
+            let path = log_path(file)?;
+            let since = since.as_deref().map(parse_since).transpose()?;
+            for record in AuditLog::read(&path, since)? {
+                println!(
+                    "{:>6} {} {}",
+                    record.seq,
+                    record.time.format("%Y-%m-%d %H:%M:%S"),
+                    record.event
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn log_path(file: Option<PathBuf>) -> anyhow::Result<PathBuf> {
+    if let Some(file) = file {
+        return Ok(file);
+    }
+    let config = ConfigLoader::new().load()?.config;
+    let path = config
+        .audit_log_path()
+        .ok_or_else(|| anyhow::anyhow!("auditing is off (security.audit.enabled = false)"))?;
+    if !path.exists() {
+        anyhow::bail!("no audit log at {}", path.display());
+    }
+    Ok(path)
+}
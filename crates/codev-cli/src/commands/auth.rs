@@ -0,0 +1,43 @@
+//! `codev auth` - inspect where API keys come from
+
+use clap::Subcommand;
+use codev_core::{ConfigLoader, ProviderKind};
+
+#[derive(Subcommand)]
+pub enum AuthCommands {
+    Status,
+}
+
+pub async fn handle_auth_command(cmd: AuthCommands) -> anyhow::Result<()> {
+    match cmd {
+        AuthCommands::Status => {
+            let config = ConfigLoader::new().load()?.config;
+            let credentials = config.ai.load_credentials();
+
+            let mut providers: Vec<_> = config.ai.providers.iter().collect();
+            providers.sort_by(|a, b| a.0.cmp(b.0));
+            for (id, provider) in providers {
+                let disabled = if provider.enabled { "" } else { " (disabled)" };
+                match (credentials.get(id), provider.resolved_kind(id)) {
+                    (Some(credential), _) => println!("✅ {}{} - {}", id, disabled, credential.source),
+                    (None, Some(ProviderKind::Ollama)) => println!("➖ {}{} - no key needed", id, disabled),
+                    (None, _) => println!("❌ {}{} - no API key", id, disabled),
+                }
+            }
+
+            let mut unconfigured: Vec<_> = credentials
+                .iter()
+                .filter(|(id, _)| !config.ai.providers.contains_key(*id))
+                .collect();
+            unconfigured.sort_by(|a, b| a.0.cmp(b.0));
+            for (id, credential) in unconfigured {
+                println!("✅ {} (not configured) - {}", id, credential.source);
+            }
+
+            for problem in credentials.problems() {
+                println!("⚠️  {}", problem);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,121 @@
+//! `codev compare` - run one prompt against several providers
+
+use codev_core::ai::{CompareEvent, GenerationOptions, LlmManager, ProviderReport};
+use codev_core::ProviderId;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+
+/// Prints provider output in sections while all providers stream at once
+///
+/// The first provider to answer is streamed live; output from the others is
+/// buffered and flushed as its own section once the live one completes.
+#[derive(Default)]
+struct SectionPrinter {
+    order: Vec<ProviderId>,
+    buffers: HashMap<ProviderId, String>,
+    printed: HashSet<ProviderId>,
+    done: HashSet<ProviderId>,
+    live: Option<ProviderId>,
+}
+
+impl SectionPrinter {
+    fn chunk(&mut self, provider: ProviderId, text: &str) -> io::Result<()> {
+        if !self.order.contains(&provider) {
+            self.order.push(provider.clone());
+        }
+
+        if self.live.as_ref() == Some(&provider) {
+            print!("{}", text);
+            io::stdout().flush()?;
+        } else {
+            self.buffers.entry(provider).or_default().push_str(text);
+        }
+
+        self.advance()
+    }
+
+    fn finish(&mut self, provider: ProviderId) -> io::Result<()> {
+        self.done.insert(provider);
+        self.advance()
+    }
+
+    /// Move on to the next section once the live provider is done
+    fn advance(&mut self) -> io::Result<()> {
+        loop {
+            if let Some(live) = &self.live {
+                if !self.done.contains(live) {
+                    return Ok(());
+                }
+                println!();
+                self.live = None;
+            }
+
+            let Some(next) = self.order.iter().cloned().find(|p| !self.printed.contains(p)) else {
+                return Ok(());
+            };
+
+            println!("\n── {} ──", next);
+            print!("{}", self.buffers.remove(&next).unwrap_or_default());
+            io::stdout().flush()?;
+            self.printed.insert(next.clone());
+            self.live = Some(next);
+        }
+    }
+}
+
+pub async fn handle_compare_command(
+    prompt: &str,
+    providers: &[ProviderId],
+    manager: &LlmManager,
+) -> anyhow::Result<()> {
+    let mut events = manager.compare(prompt, providers, &GenerationOptions::default());
+    let mut printer = SectionPrinter::default();
+    let mut reports = Vec::new();
+    let mut failures = Vec::new();
+
+    while let Some(event) = events.recv().await {
+        match event {
+            CompareEvent::Chunk { provider, text } => printer.chunk(provider, &text)?,
+            CompareEvent::Finished(report) => {
+                printer.finish(report.provider.clone())?;
+                reports.push(report);
+            }
+            CompareEvent::Failed { provider, error } => {
+                printer.finish(provider.clone())?;
+                failures.push((provider, error));
+            }
+        }
+    }
+
+    print_summary(&reports, &failures);
+    Ok(())
+}
+
+fn print_summary(reports: &[ProviderReport], failures: &[(ProviderId, String)]) {
+    println!();
+    println!(
+        "{:<10} {:>12} {:>10} {:>16} {:>10}",
+        "provider", "first token", "total", "tokens in/out", "cost"
+    );
+
+    for report in reports {
+        let first_token = report
+            .time_to_first_token
+            .map(|d| format!("{:.2}s", d.as_secs_f64()))
+            .unwrap_or_else(|| "-".to_string());
+        let tokens = format!("{}/{}", report.usage.prompt_tokens, report.usage.completion_tokens);
+
+        println!(
+            "{:<10} {:>12} {:>9.2}s {:>16} {:>10}",
+            report.provider.to_string(),
+            first_token,
+            report.total_time.as_secs_f64(),
+            tokens,
+            format!("${:.4}", report.usage.estimated_cost.unwrap_or_default()),
+        );
+    }
+
+    for (provider, error) in failures {
+        println!("❌ {} - {}", provider, error);
+    }
+}
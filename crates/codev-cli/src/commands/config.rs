@@ -0,0 +1,139 @@
+//! `codev config` - inspect and check the effective configuration
+
+use clap::Subcommand;
+use codev_core::migration::migrate_file;
+use codev_core::{CodevConfig, ConfigLoader, LayeredConfig, CURRENT_CONFIG_VERSION};
+use std::path::PathBuf;
+
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    Show {
+        #[arg(long, help = "Show which layer set every value")]
+        origin: bool,
+        #[arg(long, help = "Profile to apply, overriding CODEV_PROFILE")]
+        profile: Option<String>,
+        #[arg(long = "set", value_name = "KEY=VALUE", help = "Override a value, e.g. ai.default_provider=claude")]
+        overrides: Vec<String>,
+    },
+    Validate {
+        #[arg(long, help = "Profile to apply, overriding CODEV_PROFILE")]
+        profile: Option<String>,
+        #[arg(long = "set", value_name = "KEY=VALUE", help = "Override a value before validating")]
+        overrides: Vec<String>,
+    },
+    Schema {
+        #[arg(long, help = "Write the schema to a file instead of stdout")]
+        output: Option<PathBuf>,
+    },
+    Migrate {
+        #[arg(long, help = "Rewrite the files instead of only listing the changes")]
+        write: bool,
+        #[arg(long, help = "File to migrate (default: every config file in use)")]
+        file: Option<PathBuf>,
+    },
+}
+
+pub async fn handle_config_command(cmd: ConfigCommands) -> anyhow::Result<()> {
+    match cmd {
+        ConfigCommands::Show { origin, profile, overrides } => {
+            let loader = with_overrides(loader(profile), &overrides)?;
+            let layered = loader.load()?;
+            print_warnings(&layered);
+
+            let mut profiles: Vec<&str> = layered.config.profiles.keys().map(String::as_str).collect();
+            profiles.sort_unstable();
+            match &layered.config.profile {
+                Some(active) => println!("# profile: {} (available: {})", active, profiles.join(", ")),
+                None if !profiles.is_empty() => println!("# profile: none (available: {})", profiles.join(", ")),
+                None => {}
+            }
+
+            if origin {
+                for (key, value, origin) in layered.entries() {
+                    println!("{} = {}  # {}", key, value, origin);
+                }
+            } else {
+                print!("{}", toml::to_string_pretty(&layered.config)?);
+            }
+        }
+        ConfigCommands::Validate { profile, overrides } => {
+            let loader = with_overrides(loader(profile), &overrides)?;
+            let layered = loader.load()?;
+            print_warnings(&layered);
+            let issues = layered.config.check();
+
+            for issue in &issues {
+                let icon = if issue.is_error() { "❌" } else { "⚠️ " };
+                println!("{} {}: {}", icon, issue.key, issue.message);
+            }
+
+            let errors = issues.iter().filter(|issue| issue.is_error()).count();
+            if errors > 0 {
+                anyhow::bail!("{} error(s), {} warning(s)", errors, issues.len() - errors);
+            }
+            println!("✅ Configuration is valid ({} warning(s))", issues.len());
+        }
+        ConfigCommands::Schema { output } => {
+            let schema = serde_json::to_string_pretty(&CodevConfig::json_schema())?;
+            match output {
+                Some(path) => {
+                    std::fs::write(&path, schema)?;
+                    println!("✅ Wrote schema to {}", path.display());
+                }
+                None => println!("{}", schema),
+            }
+        }
+        ConfigCommands::Migrate { write, file } => {
+            let files = match file {
+                Some(file) => vec![file],
+                None => ConfigLoader::new().files().into_iter().filter(|f| f.is_file()).collect(),
+            };
+
+            for path in files {
+                let changes = migrate_file(&path, write)?;
+                if changes.is_empty() {
+                    println!("✅ {} is up to date", path.display());
+                    continue;
+                }
+
+                println!("{}:", path.display());
+                for change in &changes {
+                    println!("  {}", change);
+                }
+                if write {
+                    println!("✅ Migrated to config_version {}", CURRENT_CONFIG_VERSION);
+                } else {
+                    println!("  Run with --write to update the file");
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_warnings(layered: &LayeredConfig) {
+    for warning in layered.warnings() {
+        eprintln!("⚠️  {}", warning);
+    }
+    if layered.needs_migration() {
+        eprintln!("   Run `codev config migrate --write` to update the files");
+    }
+}
+
+fn loader(profile: Option<String>) -> ConfigLoader {
+    match profile {
+        Some(profile) => ConfigLoader::new().with_profile(profile),
+        None => ConfigLoader::new(),
+    }
+}
+
+/// Apply `key=value` flags, typed like `CODEV__` environment variables
+pub fn with_overrides(mut loader: ConfigLoader, overrides: &[String]) -> anyhow::Result<ConfigLoader> {
+    for entry in overrides {
+        let (key, value) = entry
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("expected KEY=VALUE, got '{}'", entry))?;
+        loader = loader.with_override(key.trim(), value);
+    }
+    Ok(loader)
+}
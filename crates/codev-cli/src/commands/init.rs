@@ -0,0 +1,418 @@
+//! `codev init` - first-run setup
+//!
+//! Detects what is available on this machine (a running Ollama and the API
+//! keys in the environment), asks which providers to enable and how strict
+//! the sandbox should be, then writes a config file containing only the
+//! values that differ from the defaults.
+
+use clap::Args;
+use codev_core::ai::providers::build_client;
+use codev_core::ai::OllamaProvider;
+use codev_core::loader::REPOSITORY_CONFIG_FILE;
+use codev_core::{
+    AiConfig, CodevConfig, ConfigLoader, Credentials, HttpConfig, ProviderConfig, ProviderId, ProviderKind, SecurityLevel,
+    CURRENT_CONFIG_VERSION,
+};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use toml::{Table, Value};
+
+/// Providers offered by the wizard, in the order they are suggested
+const PROVIDERS: &[ProviderId] = &[
+    ProviderId::OLLAMA,
+    ProviderId::CLAUDE,
+    ProviderId::OPENAI,
+    ProviderId::MISTRAL,
+    ProviderId::GEMINI,
+];
+
+const SECURITY_LEVELS: &[SecurityLevel] = &[
+    SecurityLevel::Development,
+    SecurityLevel::Production,
+    SecurityLevel::Paranoid,
+];
+
+#[derive(Args)]
+pub struct InitArgs {
+    #[arg(long, help = "Take every answer from flags and defaults instead of prompting")]
+    non_interactive: bool,
+    #[arg(long, help = "Write .codev.toml in the current directory instead of the user config file")]
+    project: bool,
+    #[arg(long, help = "Overwrite an existing config file")]
+    force: bool,
+    #[arg(long, value_delimiter = ',', help = "Providers to enable; the first one becomes the default")]
+    providers: Vec<ProviderId>,
+    #[arg(long, value_parser = parse_security_level, help = "development, production or paranoid")]
+    security_level: Option<SecurityLevel>,
+    #[arg(long, help = "Workspace directory to create")]
+    workspace: Option<PathBuf>,
+    #[arg(long, help = "Ollama server to detect")]
+    ollama_endpoint: Option<String>,
+    #[arg(long, help = "Ollama model to use (default: an installed one)")]
+    ollama_model: Option<String>,
+}
+
+/// What was found on this machine
+struct Detected {
+    ollama_endpoint: String,
+    /// Installed models, or `None` when Ollama is not reachable
+    ollama_models: Option<Vec<String>>,
+    credentials: Credentials,
+}
+
+/// Answers to the wizard's questions
+struct Answers {
+    providers: Vec<ProviderId>,
+    ollama_model: Option<String>,
+    security_level: SecurityLevel,
+    workspace: PathBuf,
+}
+
+pub async fn handle_init_command(args: InitArgs) -> anyhow::Result<()> {
+    let mut prompt = Prompt::new(!args.non_interactive);
+
+    let project = args.project || prompt.confirm("Write a project config (.codev.toml) in the current directory?", false)?;
+    let target = if project {
+        std::env::current_dir()?.join(REPOSITORY_CONFIG_FILE)
+    } else {
+        ConfigLoader::default_user_file().ok_or_else(|| anyhow::anyhow!("No user config directory on this system"))?
+    };
+    if target.exists() && !args.force && !prompt.confirm(&format!("{} exists. Overwrite it?", target.display()), false)? {
+        anyhow::bail!("{} already exists (pass --force to overwrite it)", target.display());
+    }
+
+    let defaults = CodevConfig::default();
+    let detected = detect(args.ollama_endpoint.clone().unwrap_or(defaults.ai.ollama.endpoint.clone())).await;
+    print_detected(&detected);
+
+    let answers = ask(&args, &detected, &defaults, &mut prompt)?;
+    let config = build_config(&answers, &detected);
+
+    let issues = config.check_with_keys(&detected.credentials.clone().into_keys());
+    for issue in &issues {
+        let icon = if issue.is_error() { "❌" } else { "⚠️ " };
+        println!("{} {}: {}", icon, issue.key, issue.message);
+    }
+    if issues.iter().any(|issue| issue.is_error()) {
+        anyhow::bail!("The chosen settings are not valid; nothing was written");
+    }
+
+    std::fs::create_dir_all(&config.workspace.default_path)?;
+    println!("✅ Workspace ready at {}", config.workspace.default_path.display());
+
+    write_config(&target, &config, project)?;
+    println!("✅ Wrote {}", target.display());
+    println!("   Run `codev config show --origin` to see every effective value");
+    Ok(())
+}
+
+async fn detect(ollama_endpoint: String) -> Detected {
+    // A short timeout without retries: Ollama is either running or not
+    let ollama_models = match build_client(&HttpConfig::default()) {
+        Ok(client) => {
+            let ollama = OllamaProvider::with_config(
+                client,
+                ollama_endpoint.clone(),
+                String::new(),
+                Duration::from_secs(3),
+                0,
+            );
+            ollama
+                .installed_models()
+                .await
+                .ok()
+                .map(|models| models.into_iter().map(|model| model.name).collect())
+        }
+        Err(_) => None,
+    };
+
+    Detected {
+        ollama_endpoint,
+        ollama_models,
+        credentials: AiConfig::default().load_credentials(),
+    }
+}
+
+fn print_detected(detected: &Detected) {
+    println!("Detected:");
+    match &detected.ollama_models {
+        Some(models) if models.is_empty() => {
+            println!("  ⚠️  ollama - running at {} with no models installed", detected.ollama_endpoint)
+        }
+        Some(models) => println!(
+            "  ✅ ollama - running at {} ({} models)",
+            detected.ollama_endpoint,
+            models.len()
+        ),
+        None => println!("  ❌ ollama - not reachable at {}", detected.ollama_endpoint),
+    }
+    for id in PROVIDERS.iter().filter(|id| **id != ProviderId::OLLAMA) {
+        if let Some(credential) = detected.credentials.get(id) {
+            println!("  ✅ {} - API key from {}", id, credential.source);
+        } else {
+            println!("  ❌ {} - no API key", id);
+        }
+    }
+}
+
+fn ask(args: &InitArgs, detected: &Detected, defaults: &CodevConfig, prompt: &mut Prompt) -> anyhow::Result<Answers> {
+    let usable = |id: &ProviderId| match ProviderKind::default_for(id) {
+        Some(ProviderKind::Ollama) => detected.ollama_models.is_some(),
+        _ => detected.credentials.get(id).is_some(),
+    };
+
+    let mut providers = if args.providers.is_empty() {
+        let mut chosen = Vec::new();
+        for id in PROVIDERS {
+            if prompt.confirm(&format!("Enable {}?", id), usable(id))? {
+                chosen.push(id.clone());
+            }
+        }
+        chosen
+    } else {
+        args.providers.clone()
+    };
+    if providers.is_empty() {
+        // Local models need no key, so they are the fallback when nothing else is usable
+        providers.push(ProviderId::OLLAMA);
+    }
+
+    if providers.len() > 1 && args.providers.is_empty() {
+        let names: Vec<String> = providers.iter().map(ToString::to_string).collect();
+        let first_usable = providers.iter().position(usable).unwrap_or(0);
+        let default = prompt.choose("Default provider", &names, first_usable)?;
+        let chosen = providers.remove(default);
+        providers.insert(0, chosen);
+    }
+
+    let ollama_model = match (&args.ollama_model, &detected.ollama_models) {
+        _ if !providers.contains(&ProviderId::OLLAMA) => None,
+        (Some(model), _) => Some(model.clone()),
+        (None, Some(installed)) if !installed.is_empty() => {
+            let configured = &defaults.ai.ollama.models.code_generation;
+            let default = installed.iter().position(|name| name == configured).unwrap_or(0);
+            let index = prompt.choose("Ollama model", installed, default)?;
+            Some(installed[index].clone())
+        }
+        _ => None,
+    };
+
+    let security_level = match args.security_level {
+        Some(level) => level,
+        None => {
+            let names: Vec<String> = SECURITY_LEVELS.iter().map(|level| format!("{:?}", level)).collect();
+            let default = SECURITY_LEVELS
+                .iter()
+                .position(|level| *level == defaults.security.default_level)
+                .unwrap_or(0);
+            SECURITY_LEVELS[prompt.choose("Security level", &names, default)?]
+        }
+    };
+
+    let workspace = match &args.workspace {
+        Some(path) => path.clone(),
+        None => PathBuf::from(prompt.ask(
+            "Workspace directory",
+            &defaults.workspace.default_path.display().to_string(),
+        )?),
+    };
+
+    Ok(Answers {
+        providers,
+        ollama_model,
+        security_level,
+        workspace,
+    })
+}
+
+fn build_config(answers: &Answers, detected: &Detected) -> CodevConfig {
+    let mut config = CodevConfig::default();
+    let ai = &mut config.ai;
+
+    for id in PROVIDERS.iter().chain(&answers.providers) {
+        let enabled = answers.providers.contains(id);
+        match ai.providers.get_mut(id) {
+            Some(provider) => provider.enabled = enabled,
+            None if enabled => {
+                if let Some(provider) = builtin_provider(id) {
+                    ai.providers.insert(id.clone(), provider);
+                }
+            }
+            None => {}
+        }
+    }
+    ai.default_provider = answers.providers[0].clone();
+    ai.fallback_chain = answers.providers.clone();
+
+    if detected.ollama_endpoint != ai.ollama.endpoint {
+        ai.ollama.endpoint = detected.ollama_endpoint.clone();
+        if let Some(ollama) = ai.providers.get_mut(&ProviderId::OLLAMA) {
+            ollama.endpoint = Some(detected.ollama_endpoint.clone());
+        }
+    }
+    if let Some(model) = &answers.ollama_model {
+        if let Some(ollama) = ai.providers.get_mut(&ProviderId::OLLAMA) {
+            ollama.model = model.clone();
+        }
+        // Only replace task models that are not installed
+        let installed = detected.ollama_models.as_deref().unwrap_or_default();
+        for task_model in [
+            &mut ai.ollama.models.code_generation,
+            &mut ai.ollama.models.chat,
+            &mut ai.ollama.models.analysis,
+        ] {
+            if !installed.contains(&*task_model) {
+                *task_model = model.clone();
+            }
+        }
+        for target in ai.aliases.values_mut().flatten() {
+            if target.provider == ProviderId::OLLAMA && !installed.contains(&target.model) {
+                target.model = model.clone();
+            }
+        }
+    }
+
+    config.security.default_level = answers.security_level;
+    config.workspace.default_path = answers.workspace.clone();
+    config
+}
+
+/// Starting configuration for a built-in provider that is not in the defaults
+fn builtin_provider(id: &ProviderId) -> Option<ProviderConfig> {
+    let model = match id.as_str() {
+        "claude" => "claude-3-5-sonnet-latest",
+        "openai" => "gpt-4o",
+        "mistral" => "mistral-medium",
+        "gemini" => "gemini-1.5-pro",
+        _ => return None,
+    };
+    Some(ProviderConfig {
+        kind: ProviderKind::default_for(id),
+        enabled: true,
+        model: model.to_string(),
+        max_tokens: Some(4096),
+        temperature: Some(0.1),
+        endpoint: None,
+        endpoints: Vec::new(),
+        timeout_seconds: Some(60),
+        max_retries: Some(3),
+        api_key_env: None,
+        credential_command: None,
+        cost_per_token: None,
+    })
+}
+
+/// Write the values that differ from the defaults
+///
+/// API keys never reach the file: they are read from the environment. A
+/// project file gets no security settings, since the loader ignores them
+/// there.
+fn write_config(path: &Path, config: &CodevConfig, project: bool) -> anyhow::Result<()> {
+    let mut changes = diff(&to_table(config)?, &to_table(&CodevConfig::default())?);
+    if project && changes.remove("security").is_some() {
+        println!("⚠️  Security settings are not written: only the user and system config files can set them");
+    }
+    changes.insert("config_version".to_string(), Value::Integer(CURRENT_CONFIG_VERSION.into()));
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let content = format!(
+        "# Written by `codev init`. Values not listed here use the defaults.\n{}",
+        toml::to_string_pretty(&changes)?
+    );
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+fn to_table(config: &CodevConfig) -> anyhow::Result<Table> {
+    match Value::try_from(config)? {
+        Value::Table(table) => Ok(table),
+        _ => anyhow::bail!("configuration did not serialize to a table"),
+    }
+}
+
+/// Entries of `new` that differ from `old`
+fn diff(new: &Table, old: &Table) -> Table {
+    let mut changes = Table::new();
+    for (key, value) in new {
+        match (value, old.get(key)) {
+            (Value::Table(new), Some(Value::Table(old))) => {
+                let child = diff(new, old);
+                if !child.is_empty() {
+                    changes.insert(key.clone(), Value::Table(child));
+                }
+            }
+            (value, Some(old)) if value == old => {}
+            (value, _) => {
+                changes.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    changes
+}
+
+fn parse_security_level(s: &str) -> Result<SecurityLevel, String> {
+    SECURITY_LEVELS
+        .iter()
+        .copied()
+        .find(|level| format!("{:?}", level).eq_ignore_ascii_case(s))
+        .ok_or_else(|| format!("unknown security level '{}'", s))
+}
+
+/// Questions on stdin, or their defaults when not interactive
+struct Prompt {
+    interactive: bool,
+}
+
+impl Prompt {
+    fn new(interactive: bool) -> Self {
+        Self { interactive }
+    }
+
+    /// Read an answer, or `None` to take the default
+    fn read(&mut self, question: &str, hint: &str) -> io::Result<Option<String>> {
+        if !self.interactive {
+            return Ok(None);
+        }
+        print!("{} [{}]: ", question, hint);
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        io::stdin().lock().read_line(&mut line)?;
+        let answer = line.trim();
+        Ok((!answer.is_empty()).then(|| answer.to_string()))
+    }
+
+    fn ask(&mut self, question: &str, default: &str) -> io::Result<String> {
+        Ok(self.read(question, default)?.unwrap_or_else(|| default.to_string()))
+    }
+
+    fn confirm(&mut self, question: &str, default: bool) -> io::Result<bool> {
+        loop {
+            let answer = self.read(question, if default { "Y/n" } else { "y/N" })?;
+            match answer.map(|answer| answer.to_lowercase()).as_deref() {
+                None => return Ok(default),
+                Some("y" | "yes") => return Ok(true),
+                Some("n" | "no") => return Ok(false),
+                Some(_) => println!("Please answer y or n"),
+            }
+        }
+    }
+
+    fn choose(&mut self, question: &str, options: &[String], default: usize) -> io::Result<usize> {
+        if self.interactive {
+            for (index, option) in options.iter().enumerate() {
+                println!("  {}) {}", index + 1, option);
+            }
+        }
+        loop {
+            let answer = self.ask(question, &(default + 1).to_string())?;
+            match answer.parse::<usize>() {
+                Ok(choice) if (1..=options.len()).contains(&choice) => return Ok(choice - 1),
+                _ => println!("Please enter a number from 1 to {}", options.len()),
+            }
+        }
+    }
+}
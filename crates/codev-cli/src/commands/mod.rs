@@ -1,31 +1,104 @@
+mod audit;
+mod auth;
+mod compare;
+mod config;
+mod init;
+mod models;
+mod security;
+
+pub use audit::{handle_audit_command, AuditCommands};
+pub use auth::{handle_auth_command, AuthCommands};
+pub use compare::handle_compare_command;
+pub use config::{handle_config_command, ConfigCommands};
+pub use init::{handle_init_command, InitArgs};
+pub use models::{handle_models_command, ModelsCommands};
+pub use security::{handle_security_command, SecurityCommands};
+
+use codev_core::ai::{HealthStatus, LlmManager};
+use codev_core::ProviderId;
+
 #[derive(Subcommand)]
 enum Commands {
     Chat {
         message: String,
         #[arg(long, help = "LLM provider to use")]
-        llm: Option<String>,
+        llm: Option<ProviderId>,
+        #[arg(long, help = "Model or alias to use, e.g. smart or ollama/codellama:13b")]
+        model: Option<String>,
     },
 
     #[command(subcommand)]
     Llm(LlmCommands),
+
+    Compare {
+        #[arg(help = "Prompt to send to every provider")]
+        prompt: String,
+        #[arg(long, value_delimiter = ',', default_value = "ollama", help = "Providers to compare")]
+        providers: Vec<ProviderId>,
+    },
+
+    #[command(subcommand)]
+    Models(ModelsCommands),
+
+    #[command(subcommand)]
+    Config(ConfigCommands),
+
+    Init(InitArgs),
+
+    #[command(subcommand)]
+    Auth(AuthCommands),
+
+    #[command(subcommand)]
+    Security(SecurityCommands),
+
+    #[command(subcommand)]
+    Audit(AuditCommands),
 }
 
 #[derive(Subcommand)]
 enum LlmCommands {
     Status,
     List,
-    Switch { provider: String },
+    Switch { provider: ProviderId },
     Benchmark,
 }
 
-async fn handle_llm_command(cmd: LlmCommands, manager: &mut LLMManager) -> Result<()> {
+async fn handle_llm_command(cmd: LlmCommands, manager: &mut LlmManager) -> Result<()> {
     match cmd {
         LlmCommands::Status => {
             println!("Current provider: {}", manager.current_provider());
-            // Show health status of all providers
+
+            for (id, provider) in manager.providers() {
+                match provider.health_check().await {
+                    Ok(HealthStatus::Healthy) => println!("✅ {}", id),
+                    Ok(HealthStatus::Degraded { reason }) => println!("⚠️  {} - {}", id, reason),
+                    Ok(HealthStatus::Unhealthy { error }) => println!("❌ {} - {}", id, error),
+                    Err(e) => println!("❌ {} - {}", id, e),
+                }
+            }
+
+            if let Some(ollama) = manager.ollama() {
+                match ollama.loaded_models().await {
+                    Ok(models) if models.is_empty() => println!("No Ollama models loaded"),
+                    Ok(models) => {
+                        println!("Loaded Ollama models:");
+                        for model in models {
+                            println!(
+                                "  {} on {} - {:.1} GB ({:.1} GB VRAM), expires {}",
+                                model.name,
+                                model.endpoint,
+                                model.size as f64 / 1e9,
+                                model.size_vram as f64 / 1e9,
+                                model.expires_at.as_deref().unwrap_or("-"),
+                            );
+                        }
+                    }
+                    Err(e) => println!("Could not list loaded Ollama models: {}", e),
+                }
+            }
         }
         LlmCommands::Switch { provider } => {
-            manager.switch_provider(&provider).await?;
+            manager.switch_provider(provider).await?;
         }
         LlmCommands::List => {
             for (name, provider) in manager.providers() {
@@ -0,0 +1,91 @@
+//! `codev models` - local model management
+
+use clap::Subcommand;
+use codev_core::ai::recommend::{apply_recommendations, recommend, ModelCandidate};
+use codev_core::ai::{HardwareProfile, LlmManager};
+use codev_core::CodevConfig;
+use std::path::PathBuf;
+
+#[derive(Subcommand)]
+pub enum ModelsCommands {
+    Recommend {
+        #[arg(long, help = "Write the recommended models into the config file")]
+        write: bool,
+        #[arg(long, default_value = "codev.toml", help = "Config file to update")]
+        config: PathBuf,
+    },
+    Aliases,
+}
+
+pub async fn handle_models_command(cmd: ModelsCommands, manager: &LlmManager) -> anyhow::Result<()> {
+    match cmd {
+        ModelsCommands::Recommend { write, config } => {
+            // Ollama keeps its models in OLLAMA_MODELS or ~/.ollama/models
+            let models_dir = std::env::var_os("OLLAMA_MODELS")
+                .map(PathBuf::from)
+                .or_else(|| dirs::home_dir().map(|home| home.join(".ollama/models")))
+                .unwrap_or_default();
+            let hardware = HardwareProfile::detect(&models_dir);
+
+            println!(
+                "RAM: {:.1} GB ({:.1} GB available), CPU cores: {}, free disk: {:.1} GB",
+                hardware.total_memory as f64 / 1e9,
+                hardware.available_memory as f64 / 1e9,
+                hardware.cpu_cores,
+                hardware.free_disk as f64 / 1e9,
+            );
+
+            let installed = match manager.ollama() {
+                Some(ollama) => ollama.installed_models().await.unwrap_or_else(|e| {
+                    eprintln!("⚠️  Could not list installed models: {}", e);
+                    Vec::new()
+                }),
+                None => Vec::new(),
+            };
+
+            let recommendations = recommend(&hardware, &ModelCandidate::with_installed(installed));
+            if recommendations.is_empty() {
+                println!("No candidate model fits this machine");
+                return Ok(());
+            }
+
+            for recommendation in &recommendations {
+                println!(
+                    "{:<16} {:<24} ~{:.1} GB{}",
+                    format!("{:?}", recommendation.task),
+                    recommendation.model,
+                    recommendation.estimated_memory as f64 / 1e9,
+                    if recommendation.installed { "" } else { " (not installed)" },
+                );
+            }
+
+            if write {
+                let mut codev_config = if config.exists() {
+                    CodevConfig::load_from_file(&config)?
+                } else {
+                    CodevConfig::default()
+                };
+                apply_recommendations(&recommendations, &mut codev_config.ai.ollama.models);
+                codev_config.save_to_file(&config)?;
+                println!("✅ Updated Ollama models in {}", config.display());
+            }
+        }
+        ModelsCommands::Aliases => {
+            let mut aliases: Vec<_> = manager.aliases().iter().collect();
+            aliases.sort_by_key(|(name, _)| name.as_str());
+
+            for (name, targets) in aliases {
+                let targets: Vec<String> = targets.iter().map(|t| t.to_string()).collect();
+                let selected = match manager.resolve_model(name) {
+                    Ok(resolved) => resolved
+                        .first()
+                        .map(|r| format!("{}/{}", r.provider.id(), r.model.as_deref().unwrap_or_default()))
+                        .unwrap_or_default(),
+                    Err(e) => format!("❌ {}", e),
+                };
+                println!("{:<12} {} → {}", name, targets.join(", "), selected);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,141 @@
+//! `codev security` - inspect the effective security policy
+
+use super::config::with_overrides;
+use clap::Subcommand;
+use codev_core::{ConfigLoader, LayeredConfig, RedactionAction};
+use std::path::PathBuf;
+
+#[derive(Subcommand)]
+pub enum SecurityCommands {
+    Show {
+        #[arg(long, help = "Profile to apply, overriding CODEV_PROFILE")]
+        profile: Option<String>,
+        #[arg(long = "set", value_name = "KEY=VALUE", help = "Override a value, e.g. security.default_level=paranoid")]
+        overrides: Vec<String>,
+    },
+}
+
+pub async fn handle_security_command(cmd: SecurityCommands) -> anyhow::Result<()> {
+    match cmd {
+        SecurityCommands::Show { profile, overrides } => {
+            let loader = match profile {
+                Some(profile) => ConfigLoader::new().with_profile(profile),
+                None => ConfigLoader::new(),
+            };
+            let layered = with_overrides(loader, &overrides)?.load()?;
+            show(&layered);
+        }
+    }
+    Ok(())
+}
+
+fn show(layered: &LayeredConfig) {
+    let security = &layered.config.security;
+    let sandbox = &security.sandbox;
+    let line = |label: &str, value: String, keys: &[&str]| {
+        let mut origins: Vec<String> = Vec::new();
+        for key in keys {
+            if let Some(origin) = layered.origin(&format!("security.{}", key)).map(ToString::to_string) {
+                if !origins.contains(&origin) {
+                    origins.push(origin);
+                }
+            }
+        }
+        if origins.is_empty() {
+            println!("  {:<10} {}", label, value);
+        } else {
+            println!("  {:<10} {}  # {}", label, value, origins.join(", "));
+        }
+    };
+    let on_off = |on: bool| if on { "on" } else { "off" }.to_string();
+    let limit = |value: Option<String>| value.unwrap_or_else(|| "unlimited".to_string());
+    let paths = |paths: &[PathBuf]| -> String {
+        if paths.is_empty() {
+            return "none".to_string();
+        }
+        paths.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", ")
+    };
+
+    line("level", format!("{:?}", security.default_level), &["default_level"]);
+
+    println!("Commands:");
+    line("sandbox", on_off(security.sandbox_enabled), &["sandbox_enabled"]);
+    let network = if !security.sandbox_enabled {
+        "unrestricted (no sandbox)".to_string()
+    } else {
+        on_off(sandbox.network_access)
+    };
+    line("network", network, &["sandbox_enabled", "sandbox.network_access"]);
+    line(
+        "memory",
+        limit(sandbox.max_memory.map(|bytes| format!("{} MB", bytes / (1024 * 1024)))),
+        &["sandbox.max_memory"],
+    );
+    line(
+        "CPU time",
+        limit(sandbox.max_cpu_time.map(|seconds| format!("{} s", seconds))),
+        &["sandbox.max_cpu_time"],
+    );
+    line("allowed", security.allowed_commands.join(", "), &["allowed_commands"]);
+    let confirm = if security.approval.commands {
+        "every command".to_string()
+    } else {
+        let mut asks: Vec<String> = security
+            .commands
+            .iter()
+            .filter(|(program, _)| security.allowed_commands.contains(program))
+            .flat_map(|(program, rule)| rule.ask.iter().map(move |pattern| format!("{} {}", program, pattern)))
+            .collect();
+        asks.sort();
+        if asks.is_empty() {
+            "never".to_string()
+        } else {
+            asks.join(", ")
+        }
+    };
+    line("confirm", confirm, &["approval.commands"]);
+
+    println!("Files:");
+    let access = &security.file_access;
+    line("writable", paths(&access.write_allowed_paths), &["file_access.write_allowed_paths"]);
+    line("read-only", paths(&access.read_only_paths), &["file_access.read_only_paths"]);
+    line("forbidden", paths(&access.forbidden_paths), &["file_access.forbidden_paths"]);
+    line(
+        "max size",
+        format!("{} MB", access.max_file_size / (1024 * 1024)),
+        &["file_access.max_file_size"],
+    );
+    let confirm = if security.approval.file_writes { "every write" } else { "never" };
+    line("confirm", confirm.to_string(), &["approval.file_writes"]);
+
+    println!("AI providers:");
+    let mut providers: Vec<_> = layered.config.ai.providers.keys().collect();
+    providers.sort();
+    let (local, cloud): (Vec<_>, Vec<_>) = providers.into_iter().partition(|id| layered.config.ai.is_local(id));
+    let names = |ids: Vec<_>| ids.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+    let cloud = if security.cloud_providers {
+        format!("allowed ({})", names(cloud))
+    } else {
+        format!("refused ({} disabled)", names(cloud))
+    };
+    line("local", names(local), &[]);
+    line("cloud", cloud, &["cloud_providers"]);
+    let redaction = &security.redaction;
+    let secrets = match (redaction.enabled, redaction.action) {
+        (false, _) => "not scanned",
+        (true, RedactionAction::Redact) => "redacted for cloud providers",
+        (true, RedactionAction::Block) => "prompt refused",
+        (true, RedactionAction::Local) => "local providers only",
+    };
+    line("secrets", secrets.to_string(), &["redaction.enabled", "redaction.action"]);
+    let rules: Vec<String> = security
+        .residency
+        .iter()
+        .map(|rule| {
+            let matches: Vec<&str> = rule.paths.iter().chain(&rule.remotes).map(String::as_str).collect();
+            format!("{} -> {}", matches.join(", "), names(rule.providers.iter().collect()))
+        })
+        .collect();
+    let residency = if rules.is_empty() { "none".to_string() } else { rules.join("; ") };
+    line("residency", residency, &["residency"]);
+}
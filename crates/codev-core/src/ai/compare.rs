@@ -0,0 +1,252 @@
+//! Provider Comparison
+//!
+//! Fans a single prompt out to several providers at once so their output,
+//! latency and cost can be compared side by side. Each provider receives the
+//! prompt the outbound filter allows it, and providers the data residency
+//! rules do not allow for the prompt are reported as failed without a call.
+
+use crate::ai::{estimate_tokens, AiError, GenerationOptions, LlmManager, LlmProvider, Prompt, UsageStats};
+use crate::audit::{AuditEvent, AuditLog, Decision};
+use codev_shared::ProviderId;
+use futures::StreamExt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{debug, instrument};
+
+/// Event emitted while a comparison is running
+#[derive(Debug, Clone)]
+pub enum CompareEvent {
+    /// A chunk of generated text
+    Chunk { provider: ProviderId, text: String },
+
+    /// A provider finished its response
+    Finished(ProviderReport),
+
+    /// A provider failed before finishing its response
+    Failed { provider: ProviderId, error: String },
+}
+
+/// Latency, token and cost figures for one provider
+#[derive(Debug, Clone)]
+pub struct ProviderReport {
+    /// Provider that produced the response
+    pub provider: ProviderId,
+
+    /// Time until the first chunk arrived
+    pub time_to_first_token: Option<Duration>,
+
+    /// Time until the response was complete
+    pub total_time: Duration,
+
+    /// Estimated token usage and cost
+    pub usage: UsageStats,
+}
+
+impl LlmManager {
+    /// Run a prompt concurrently across several providers
+    ///
+    /// Events from all providers are interleaved on the returned channel,
+    /// which closes once every provider has finished or failed.
+    #[instrument(skip(self, prompt, options))]
+    pub fn compare(
+        &self,
+        prompt: &str,
+        providers: &[ProviderId],
+        options: &GenerationOptions,
+    ) -> mpsc::UnboundedReceiver<CompareEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let options = GenerationOptions {
+            stream: true,
+            ..options.clone()
+        };
+        let prompt = Prompt::from(prompt);
+        let screened = match self.screen(&prompt) {
+            Ok(screened) => screened,
+            Err(e) => {
+                for id in providers {
+                    let _ = tx.send(CompareEvent::Failed {
+                        provider: id.clone(),
+                        error: e.to_string(),
+                    });
+                }
+                return rx;
+            }
+        };
+
+        let restriction = self.residency().restriction(&prompt);
+
+        for id in providers {
+            if let Some(restriction) = restriction.as_ref().filter(|r| !r.allows(id)) {
+                if let Some(log) = self.audit_log() {
+                    log.record_or_warn(AuditEvent::PolicyDecision {
+                        action: format!("send prompt from {} to {}", restriction.matched, id),
+                        decision: Decision::Deny,
+                        rule: Some(restriction.rule.clone()),
+                    });
+                }
+                let _ = tx.send(CompareEvent::Failed {
+                    provider: id.clone(),
+                    error: restriction.refused(id).to_string(),
+                });
+                continue;
+            }
+
+            match self.provider(id) {
+                Some(provider) => match screened.for_provider(self.is_local(id)) {
+                    Ok(outbound) => {
+                        tokio::spawn(run_provider(
+                            provider,
+                            outbound.render(),
+                            options.clone(),
+                            tx.clone(),
+                            self.audit_log().cloned(),
+                        ));
+                    }
+                    Err(e) => {
+                        let _ = tx.send(CompareEvent::Failed {
+                            provider: id.clone(),
+                            error: e.to_string(),
+                        });
+                    }
+                },
+                None => {
+                    let _ = tx.send(CompareEvent::Failed {
+                        provider: id.clone(),
+                        error: AiError::ProviderNotAvailable(id.clone()).to_string(),
+                    });
+                }
+            }
+        }
+
+        rx
+    }
+}
+
+/// Stream one provider's response into the comparison channel
+async fn run_provider(
+    provider: Arc<dyn LlmProvider>,
+    prompt: String,
+    options: GenerationOptions,
+    tx: mpsc::UnboundedSender<CompareEvent>,
+    audit_log: Option<Arc<AuditLog>>,
+) {
+    let id = provider.id();
+    let started = Instant::now();
+    let model = options.model.clone().unwrap_or_else(|| provider.model().to_string());
+    let record = |completion: &str, error: Option<String>| {
+        if let Some(log) = &audit_log {
+            log.record_or_warn(AuditEvent::provider_call(
+                id.clone(),
+                &model,
+                &prompt,
+                estimate_tokens(&prompt),
+                estimate_tokens(completion),
+                error,
+            ));
+        }
+    };
+
+    let mut stream = match provider.stream_generate(&prompt, &options).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            record("", Some(e.to_string()));
+            let _ = tx.send(CompareEvent::Failed { provider: id, error: e.to_string() });
+            return;
+        }
+    };
+
+    let mut completion = String::new();
+    let mut time_to_first_token = None;
+
+    while let Some(chunk) = stream.next().await {
+        match chunk {
+            Ok(text) => {
+                time_to_first_token.get_or_insert_with(|| started.elapsed());
+                completion.push_str(&text);
+                let _ = tx.send(CompareEvent::Chunk { provider: id.clone(), text });
+            }
+            Err(e) => {
+                record(&completion, Some(e.to_string()));
+                let _ = tx.send(CompareEvent::Failed { provider: id.clone(), error: e.to_string() });
+                return;
+            }
+        }
+    }
+
+    let prompt_tokens = estimate_tokens(&prompt);
+    let completion_tokens = estimate_tokens(&completion);
+    let total_tokens = prompt_tokens + completion_tokens;
+    debug!("Provider {} completed comparison in {:?}", id, started.elapsed());
+    record(&completion, None);
+
+    let _ = tx.send(CompareEvent::Finished(ProviderReport {
+        provider: id,
+        time_to_first_token,
+        total_time: started.elapsed(),
+        usage: UsageStats {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens,
+            estimated_cost: Some(total_tokens as f64 * provider.cost_per_token()),
+            ..UsageStats::default()
+        },
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::test_support::StaticProvider;
+
+    async fn collect(mut rx: mpsc::UnboundedReceiver<CompareEvent>) -> Vec<CompareEvent> {
+        let mut events = Vec::new();
+        while let Some(event) = rx.recv().await {
+            events.push(event);
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn test_compare_reports_each_provider() {
+        let mut manager = LlmManager::new(ProviderId::OLLAMA, vec![]);
+        manager.register(Arc::new(StaticProvider::new(ProviderId::OLLAMA, &["fn ", "main"])));
+        manager.register(Arc::new(StaticProvider::new(ProviderId::CLAUDE, &["hello"]).with_cost(0.5)));
+
+        let events = collect(manager.compare(
+            "prompt",
+            &[ProviderId::OLLAMA, ProviderId::CLAUDE],
+            &GenerationOptions::default(),
+        )).await;
+
+        let ollama_text: String = events
+            .iter()
+            .filter_map(|e| match e {
+                CompareEvent::Chunk { provider, text } if *provider == ProviderId::OLLAMA => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ollama_text, "fn main");
+
+        let claude = events
+            .iter()
+            .find_map(|e| match e {
+                CompareEvent::Finished(report) if report.provider == ProviderId::CLAUDE => Some(report),
+                _ => None,
+            })
+            .expect("claude report");
+        assert!(claude.time_to_first_token.is_some());
+        assert_eq!(claude.usage.total_tokens, 4);
+        assert_eq!(claude.usage.estimated_cost, Some(2.0));
+    }
+
+    #[tokio::test]
+    async fn test_compare_unknown_provider_fails() {
+        let manager = LlmManager::new(ProviderId::OLLAMA, vec![]);
+
+        let events = collect(manager.compare("prompt", &[ProviderId::GEMINI], &GenerationOptions::default())).await;
+
+        assert_eq!(events.len(), 1);
+        assert!(matches!(&events[0], CompareEvent::Failed { provider, .. } if *provider == ProviderId::GEMINI));
+    }
+}
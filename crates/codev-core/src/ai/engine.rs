@@ -0,0 +1,551 @@
+//! AI Engine
+//!
+//! High-level entry point for AI features. Wraps the `LlmManager` and takes
+//! care of provider lifecycle, such as warming up local models. Prompts are
+//! screened for secrets and checked against the data residency rules before
+//! any provider is tried.
+
+use crate::ai::providers::ollama::render_transcript;
+use crate::ai::{
+    estimate_tokens, AiError, AiResponse, ChatMessage, GenerationOptions, LlmManager, LlmProvider, OutboundFilter,
+    Prompt, Residency, ResolvedModel, Screened,
+};
+use crate::audit::{AuditEvent, AuditLog};
+use crate::config::ConfigManager;
+use crate::engine::ComponentHealth;
+use codev_shared::{AiConfig, Credentials, OllamaModels, ProviderId, Result, SecurityConfig};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, instrument, warn};
+
+/// Coordinates LLM providers for the rest of the engine
+///
+/// The provider set can be replaced while running: each request works on
+/// the providers that were current when it started, so a reload never
+/// interrupts a request in flight.
+pub struct AiEngine {
+    state: RwLock<Arc<AiState>>,
+    idle_reaper: Mutex<Option<JoinHandle<()>>>,
+    audit_log: Option<Arc<AuditLog>>,
+}
+
+/// Providers and routing built from one `AiConfig`
+struct AiState {
+    manager: Arc<LlmManager>,
+    routing: OllamaModels,
+}
+
+impl AiEngine {
+    /// Create the AI engine from configuration
+    ///
+    /// Models of the Ollama routing table are preloaded in the background so
+    /// that engine start-up is not blocked on model loading. Prompts are
+    /// screened according to `security.redaction`, routed according to
+    /// `security.residency`, and provider calls are recorded in `audit_log`.
+    #[instrument(skip(config, security, credentials, _config_manager, audit_log))]
+    pub async fn new(
+        config: &AiConfig,
+        security: &SecurityConfig,
+        credentials: Credentials,
+        _config_manager: Arc<ConfigManager>,
+        audit_log: Option<Arc<AuditLog>>,
+    ) -> Result<Self> {
+        let (state, idle_reaper) = Self::build(config, security, credentials, audit_log.clone())?;
+        Ok(Self {
+            state: RwLock::new(Arc::new(state)),
+            idle_reaper: Mutex::new(idle_reaper),
+            audit_log,
+        })
+    }
+
+    /// Replace the provider set with one built from `config`
+    ///
+    /// Nothing changes if the providers cannot be built. Requests already
+    /// running finish on the previous providers.
+    #[instrument(skip(self, config, security, credentials))]
+    pub fn reconfigure(&self, config: &AiConfig, security: &SecurityConfig, credentials: Credentials) -> Result<()> {
+        let (state, idle_reaper) = Self::build(config, security, credentials, self.audit_log.clone())?;
+        let providers: Vec<String> = state.manager.providers().map(|(id, _)| id.to_string()).collect();
+
+        *self.state.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(state);
+        let previous = std::mem::replace(&mut *self.idle_reaper.lock().unwrap_or_else(|e| e.into_inner()), idle_reaper);
+        if let Some(previous) = previous {
+            previous.abort();
+        }
+
+        info!("AI providers reconfigured: {}", providers.join(", "));
+        Ok(())
+    }
+
+    /// Build the providers and start their background tasks
+    fn build(
+        config: &AiConfig,
+        security: &SecurityConfig,
+        credentials: Credentials,
+        audit_log: Option<Arc<AuditLog>>,
+    ) -> Result<(AiState, Option<JoinHandle<()>>)> {
+        let mut manager = LlmManager::with_credentials(config, credentials)?;
+        manager.set_audit_log(audit_log);
+        if security.redaction.enabled {
+            manager.set_outbound_filter(Some(OutboundFilter::new(&security.redaction)?));
+        }
+        manager.set_residency(Residency::new(&security.residency));
+        let mut idle_reaper = None;
+
+        if config.ollama.preload_models {
+            // The routing table belongs to the main Ollama provider; other
+            // Ollama-kind providers only warm up their own model.
+            let main = manager.ollama().map(|ollama| ollama.id());
+            for ollama in manager.ollama_providers() {
+                let ollama = ollama.clone();
+                let mut models: Vec<String> = if main.as_ref() == Some(&ollama.id()) {
+                    config
+                        .ollama
+                        .models
+                        .all()
+                        .into_iter()
+                        .filter(|m| !manager.is_qualified(m))
+                        .map(String::from)
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+                if !models.iter().any(|m| m == ollama.model()) {
+                    models.push(ollama.model().to_string());
+                }
+
+                tokio::spawn(async move {
+                    for model in models {
+                        if let Err(e) = ollama.preload_model(&model).await {
+                            warn!("Failed to preload model {}: {}", model, e);
+                        }
+                    }
+                });
+            }
+        }
+
+        if let (Some(ollama), Some(seconds)) = (manager.ollama(), config.ollama.idle_unload_seconds) {
+            let ollama = ollama.clone();
+            let idle_after = Duration::from_secs(seconds);
+
+            idle_reaper = Some(tokio::spawn(async move {
+                // The period must not be zero
+                let mut interval = tokio::time::interval((idle_after / 2).max(Duration::from_secs(1)));
+                loop {
+                    interval.tick().await;
+                    match ollama.unload_idle_models(idle_after).await {
+                        Ok(unloaded) if !unloaded.is_empty() => {
+                            info!("Unloaded idle models: {}", unloaded.join(", "));
+                        }
+                        Ok(_) => {}
+                        Err(e) => warn!("Failed to unload idle models: {}", e),
+                    }
+                }
+            }));
+        }
+
+        let state = AiState {
+            manager: Arc::new(manager),
+            routing: config.ollama.models.clone(),
+        };
+        Ok((state, idle_reaper))
+    }
+
+    /// Providers and routing current at the time of the call
+    fn state(&self) -> Arc<AiState> {
+        self.state.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Get the provider manager
+    pub fn manager(&self) -> Arc<LlmManager> {
+        self.state().manager.clone()
+    }
+
+    /// Generate code from a prompt
+    #[instrument(skip(self, prompt))]
+    pub async fn generate_code(&self, prompt: &str) -> Result<String> {
+        let state = self.state();
+        let candidates = state.route(&state.routing.code_generation);
+        let response = self
+            .generate(&state, &Prompt::from(prompt), GenerationOptions::default(), candidates)
+            .await?;
+        Ok(response.content)
+    }
+
+    /// Send a chat message
+    #[instrument(skip(self, message))]
+    pub async fn chat(&self, message: &str) -> Result<String> {
+        let state = self.state();
+        let candidates = state.route(&state.routing.chat);
+        let response = self
+            .generate(&state, &Prompt::from(message), GenerationOptions::default(), candidates)
+            .await?;
+        Ok(response.content)
+    }
+
+    /// Generate from an assembled prompt, reporting usage and cost
+    ///
+    /// Cache breakpoints in the prompt are honoured by providers with
+    /// prompt caching.
+    #[instrument(skip(self, prompt, options))]
+    pub async fn generate_response(&self, prompt: &Prompt, options: GenerationOptions) -> Result<AiResponse> {
+        let state = self.state();
+        let candidates = match options.model.clone() {
+            Some(model) => state.manager.resolve_model(&model)?,
+            None => state.route(&state.routing.code_generation),
+        };
+        self.generate(&state, prompt, options, candidates).await
+    }
+
+    /// Generate with a specific model or alias
+    ///
+    /// Only the targets the name resolves to are tried, in order.
+    #[instrument(skip(self, prompt, options))]
+    pub async fn generate_with_model(&self, model: &str, prompt: &str, options: GenerationOptions) -> Result<String> {
+        let state = self.state();
+        let candidates = state.manager.resolve_model(model)?;
+        let response = self.generate(&state, &Prompt::from(prompt), options, candidates).await?;
+        Ok(response.content)
+    }
+
+    /// Generate the next turn of a conversation on the Ollama provider,
+    /// reusing the context Ollama kept for the session
+    ///
+    /// The conversation is screened and checked against the residency rules
+    /// like any other prompt. When the Ollama provider would get a redacted
+    /// copy or may not receive it, the session's context is dropped and the
+    /// whole transcript is generated on the providers that may take it.
+    #[instrument(skip(self, history, message, options))]
+    pub async fn generate_in_session(
+        &self,
+        session: &str,
+        history: &[ChatMessage],
+        message: &str,
+        options: GenerationOptions,
+    ) -> Result<AiResponse> {
+        let state = self.state();
+        let manager = &state.manager;
+        let ollama = manager
+            .ollama()
+            .cloned()
+            .ok_or(AiError::ProviderNotAvailable(ProviderId::OLLAMA))?;
+
+        let prompt = Prompt::from(render_transcript(history, message).as_str());
+        let screened = manager.screen(&prompt)?;
+        let candidates = vec![ResolvedModel {
+            provider: ollama.clone(),
+            model: None,
+        }];
+        let candidates = manager.permitted(&prompt, candidates)?;
+
+        let local = manager.is_local(&ollama.id());
+        if screened.redacts_for(local) || !candidates.iter().any(|c| c.provider.id() == ollama.id()) {
+            ollama.invalidate_session(session);
+            return self.try_candidates(manager, &screened, options, candidates).await;
+        }
+        screened.for_provider(local)?;
+
+        let options = fit_to_context(ollama.max_content_length(), &prompt.render(), options)?;
+        let model = options.model.clone().unwrap_or_else(|| ollama.model().to_string());
+        let result = ollama.generate_in_session(session, history, message, options).await;
+        self.record_call(ollama.as_ref(), &model, &prompt.render(), &result);
+        result
+    }
+
+    /// Forget the context Ollama kept for a session, e.g. after its history
+    /// was edited
+    pub fn invalidate_session(&self, session: &str) {
+        if let Some(ollama) = self.state().manager.ollama() {
+            ollama.invalidate_session(session);
+        }
+    }
+
+    /// Generate within the provider's context window, falling back along
+    /// the candidates when a provider fails
+    ///
+    /// A prompt that may only stay on this machine goes to the local
+    /// providers when no candidate is local, and candidates the residency
+    /// rules do not allow for the prompt are never tried.
+    async fn generate(
+        &self,
+        state: &AiState,
+        prompt: &Prompt,
+        options: GenerationOptions,
+        mut candidates: Vec<ResolvedModel>,
+    ) -> Result<AiResponse> {
+        let manager = &state.manager;
+        let screened = manager.screen(prompt)?;
+        if screened.local_only() && !candidates.iter().any(|c| manager.is_local(&c.provider.id())) {
+            candidates.extend(
+                manager
+                    .local_providers()
+                    .into_iter()
+                    .map(|provider| ResolvedModel { provider, model: None }),
+            );
+        }
+        let candidates = manager.permitted(prompt, candidates)?;
+        self.try_candidates(manager, &screened, options, candidates).await
+    }
+
+    /// Try the candidates in order until one succeeds
+    ///
+    /// Each provider receives the prompt the outbound filter allows it.
+    async fn try_candidates(
+        &self,
+        manager: &LlmManager,
+        screened: &Screened<'_>,
+        options: GenerationOptions,
+        candidates: Vec<ResolvedModel>,
+    ) -> Result<AiResponse> {
+        let mut last_error = None;
+
+        for ResolvedModel { provider, model } in candidates {
+            let options = GenerationOptions {
+                model: model.or_else(|| options.model.clone()),
+                ..options.clone()
+            };
+            let result = match screened.for_provider(manager.is_local(&provider.id())) {
+                Ok(outbound) => {
+                    let rendered = outbound.render();
+                    match fit_to_context(provider.max_content_length(), &rendered, options) {
+                        Ok(options) => {
+                            let model = options.model.clone().unwrap_or_else(|| provider.model().to_string());
+                            let result = provider.generate_prompt(outbound, options).await;
+                            self.record_call(provider.as_ref(), &model, &rendered, &result);
+                            result
+                        }
+                        Err(e) => Err(e),
+                    }
+                }
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(response) => return Ok(response),
+                Err(e) => {
+                    warn!("Provider {} failed, trying next: {}", provider.id(), e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| AiError::NoProviderAvailable.into()))
+    }
+
+    /// Record a provider call in the audit log
+    fn record_call(&self, provider: &dyn LlmProvider, model: &str, prompt: &str, result: &Result<AiResponse>) {
+        let Some(log) = &self.audit_log else { return };
+        let event = match result {
+            Ok(response) => AuditEvent::provider_call(
+                response.provider.clone(),
+                &response.model,
+                prompt,
+                response.usage.prompt_tokens,
+                response.usage.completion_tokens,
+                None,
+            ),
+            Err(e) => AuditEvent::provider_call(provider.id(), model, prompt, 0, 0, Some(e.to_string())),
+        };
+        log.record_or_warn(event);
+    }
+
+    /// Check the health of the registered providers
+    pub async fn health_check(&self) -> ComponentHealth {
+        let mut healthy = 0;
+        let mut total = 0;
+
+        let manager = self.manager();
+        for (_, provider) in manager.providers() {
+            total += 1;
+            match provider.health_check().await {
+                Ok(status) if status.is_healthy() => healthy += 1,
+                Ok(_) => {}
+                Err(e) => warn!("Health check failed for {}: {}", provider.name(), e),
+            }
+        }
+
+        match healthy {
+            0 => ComponentHealth::Unhealthy,
+            n if n == total => ComponentHealth::Healthy,
+            _ => ComponentHealth::Degraded,
+        }
+    }
+
+    /// Stop background tasks
+    pub async fn shutdown(&self) -> Result<()> {
+        if let Some(reaper) = self.idle_reaper.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            reaper.abort();
+        }
+        Ok(())
+    }
+}
+
+impl AiState {
+    /// Candidates for a routing table entry, followed by the provider chain
+    ///
+    /// Plain model names in the routing table are Ollama models, so they
+    /// only apply while an Ollama provider is selected; aliases and
+    /// `provider/model` entries always apply.
+    fn route(&self, model: &str) -> Vec<ResolvedModel> {
+        let current = self.manager.current_provider();
+        let ollama_selected = self.manager.ollama_providers().any(|p| p.id() == *current);
+
+        let mut candidates = Vec::new();
+        if ollama_selected || self.manager.is_qualified(model) {
+            match self.manager.resolve_model(model) {
+                Ok(resolved) => candidates.extend(resolved),
+                Err(e) => warn!("Could not resolve model {}: {}", model, e),
+            }
+        }
+
+        candidates.extend(self.manager.provider_chain().into_iter().map(|provider| ResolvedModel {
+            provider,
+            model: None,
+        }));
+        candidates
+    }
+}
+
+/// Shrink the completion budget so prompt and completion fit the context
+fn fit_to_context(max_context: usize, prompt: &str, mut options: GenerationOptions) -> Result<GenerationOptions> {
+    let prompt_tokens = estimate_tokens(prompt);
+    if prompt_tokens >= max_context {
+        return Err(AiError::ContextTooLong {
+            tokens: prompt_tokens,
+            max_tokens: max_context,
+        }.into());
+    }
+
+    let available = max_context - prompt_tokens;
+    options.max_tokens = Some(options.max_tokens.map_or(available, |t| t.min(available)));
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::test_support::StaticProvider;
+    use crate::ai::MessageRole;
+    use codev_shared::{GitInfo, RedactionAction, RedactionConfig, ResidencyRule};
+
+    fn engine(manager: LlmManager) -> AiEngine {
+        let audit_log = manager.audit_log().cloned();
+        let state = AiState {
+            manager: Arc::new(manager),
+            routing: OllamaModels::default(),
+        };
+        AiEngine {
+            state: RwLock::new(Arc::new(state)),
+            idle_reaper: Mutex::new(None),
+            audit_log,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_session_turns_are_screened() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = Arc::new(AuditLog::open(dir.path().join("audit.jsonl")).unwrap());
+        let mut manager = LlmManager::from_config(&AiConfig::default()).unwrap();
+        manager.set_audit_log(Some(log.clone()));
+        let config = RedactionConfig {
+            action: RedactionAction::Block,
+            ..RedactionConfig::default()
+        };
+        manager.set_outbound_filter(Some(OutboundFilter::new(&config).unwrap()));
+        let engine = engine(manager);
+
+        // The secret sits in an earlier turn, which a reused context would
+        // otherwise never show to the filter
+        let history = [ChatMessage {
+            role: MessageRole::User,
+            content: "AWS_SECRET_ACCESS_KEY=wJalrXUtnFEMIK7MDENGbPxRfiCY9xQ2kL7pZ".to_string(),
+            timestamp: chrono::Utc::now(),
+            metadata: None,
+        }];
+        let error = engine
+            .generate_in_session("s1", &history, "Why does this fail?", GenerationOptions::default())
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("Prompt withheld"), "{}", error);
+
+        let records = AuditLog::read(log.path(), None).unwrap();
+        assert!(matches!(&records[..], [record] if matches!(record.event, AuditEvent::PromptScreened { .. })));
+    }
+
+    #[test]
+    fn test_fit_to_context_shrinks_completion() {
+        let prompt = "x".repeat(4000); // ~1000 tokens
+        let options = fit_to_context(4096, &prompt, GenerationOptions::default()).unwrap();
+        assert_eq!(options.max_tokens, Some(3096));
+
+        let options = fit_to_context(32768, &prompt, GenerationOptions::default()).unwrap();
+        assert_eq!(options.max_tokens, Some(4096));
+    }
+
+    #[test]
+    fn test_fit_to_context_rejects_oversized_prompt() {
+        let prompt = "x".repeat(20_000);
+        assert!(fit_to_context(4096, &prompt, GenerationOptions::default()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_residency_applies_to_untagged_prompts() {
+        let rules = [
+            ResidencyRule {
+                paths: vec!["/work/payments/*".to_string()],
+                remotes: Vec::new(),
+                providers: vec![ProviderId::OLLAMA],
+            },
+            ResidencyRule {
+                paths: Vec::new(),
+                remotes: vec!["*acme/ledger*".to_string()],
+                providers: vec![ProviderId::OLLAMA],
+            },
+        ];
+        let git = |remote_url: &str| GitInfo {
+            current_branch: "main".to_string(),
+            remote_url: Some(remote_url.to_string()),
+            is_dirty: false,
+            last_commit: None,
+        };
+        let manager = |residency: Residency, local: bool| {
+            let mut manager = LlmManager::new(ProviderId::CLAUDE, Vec::new());
+            manager.register(Arc::new(StaticProvider::new(ProviderId::CLAUDE, &["cloud"])));
+            if local {
+                manager.register(Arc::new(StaticProvider::new(ProviderId::OLLAMA, &["local"])));
+            }
+            manager.set_residency(residency);
+            manager
+        };
+
+        // Started in a restricted directory, chat never reaches the cloud
+        let payments = engine(manager(Residency::with_base(&rules, "/work/payments"), true));
+        assert_eq!(payments.chat("Review").await.unwrap(), "local");
+        let offline = engine(manager(Residency::with_base(&rules, "/work/payments"), false));
+        let error = offline.chat("Review").await.unwrap_err().to_string();
+        assert!(error.contains("/work/payments/ may only go to ollama"), "{}", error);
+
+        // The working directory's remote applies unless the prompt names one
+        let ledger_git = git("git@github.com:acme/ledger.git");
+        let residency = Residency::with_base(&rules, "/work/ledger").repository(&ledger_git);
+        let ledger = engine(manager(residency, true));
+        assert_eq!(ledger.generate_code("Review").await.unwrap(), "local");
+        let site = Prompt::from("Review")
+            .source("/work/site/index.html")
+            .repository(&git("https://github.com/acme/site.git"));
+        let response = ledger.generate_response(&site, GenerationOptions::default()).await.unwrap();
+        assert_eq!(response.content, "cloud");
+
+        // Comparing cannot fan a restricted prompt out to the cloud
+        let options = GenerationOptions::default();
+        let mut events = ledger.manager().compare("Review", &[ProviderId::CLAUDE], &options);
+        match events.recv().await {
+            Some(crate::ai::CompareEvent::Failed { provider, error }) => {
+                assert_eq!(provider, ProviderId::CLAUDE);
+                assert!(error.contains("may not be sent to claude"), "{}", error);
+            }
+            other => panic!("expected the comparison to be refused, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,501 @@
+//! LLM Provider Manager
+//!
+//! Owns the configured providers and keeps track of which one is currently
+//! selected. Providers are shared behind `Arc` so that requests can run on
+//! several of them concurrently. Prompts are screened for secrets and
+//! checked against the data residency rules here before any provider sees
+//! them.
+
+use crate::ai::providers::{build_client, create_provider, ConfiguredProvider, OllamaProvider};
+use crate::ai::redaction::{OutboundFilter, Screened};
+use crate::ai::residency::Residency;
+use crate::ai::{AiError, LlmProvider, Prompt};
+use crate::audit::{sha256_hex, AuditEvent, AuditLog, Decision};
+use codev_shared::{AiConfig, Credentials, ModelTarget, ProviderId, RedactionAction, Result};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tracing::{debug, info, instrument, warn};
+
+/// Registry of LLM providers
+pub struct LlmManager {
+    providers: HashMap<ProviderId, Arc<dyn LlmProvider>>,
+    current_provider: ProviderId,
+    fallback_chain: Vec<ProviderId>,
+    ollama: HashMap<ProviderId, Arc<OllamaProvider>>,
+    aliases: HashMap<String, Vec<ModelTarget>>,
+    local: HashSet<ProviderId>,
+    audit_log: Option<Arc<AuditLog>>,
+    outbound_filter: Option<OutboundFilter>,
+    residency: Residency,
+}
+
+/// A provider together with the model to request from it
+#[derive(Clone)]
+pub struct ResolvedModel {
+    pub provider: Arc<dyn LlmProvider>,
+
+    /// Model to request, `None` for the provider's default
+    pub model: Option<String>,
+}
+
+impl LlmManager {
+    /// Create an empty manager
+    pub fn new(default_provider: ProviderId, fallback_chain: Vec<ProviderId>) -> Self {
+        Self {
+            providers: HashMap::new(),
+            current_provider: default_provider,
+            fallback_chain,
+            ollama: HashMap::new(),
+            aliases: HashMap::new(),
+            local: HashSet::new(),
+            audit_log: None,
+            outbound_filter: None,
+            residency: Residency::default(),
+        }
+    }
+
+    /// Create a manager with every enabled provider from the configuration
+    pub fn from_config(config: &AiConfig) -> Result<Self> {
+        Self::with_credentials(config, config.load_credentials())
+    }
+
+    /// Create a manager from the configuration and credentials already loaded
+    pub fn with_credentials(config: &AiConfig, credentials: Credentials) -> Result<Self> {
+        let mut manager = Self::new(config.default_provider.clone(), config.fallback_chain.clone());
+        for problem in credentials.problems() {
+            warn!("Ignoring API key source {}", problem);
+        }
+        let mut api_keys = credentials.into_keys();
+        let client = build_client(&config.http)?;
+        manager.aliases = config.aliases.clone();
+
+        for (id, provider_config) in &config.providers {
+            if !provider_config.enabled {
+                continue;
+            }
+
+            match create_provider(&client, id, provider_config, config, api_keys.remove(id))? {
+                ConfiguredProvider::Ollama(provider) => {
+                    manager.ollama.insert(id.clone(), provider.clone());
+                    manager.register(provider);
+                }
+                ConfiguredProvider::Remote(provider) => manager.register(provider),
+            }
+            if config.is_local(id) {
+                manager.local.insert(id.clone());
+            }
+            debug!("Registered provider {}", id);
+        }
+
+        Ok(manager)
+    }
+
+    /// Register a provider, replacing any provider with the same id
+    pub fn register(&mut self, provider: Arc<dyn LlmProvider>) {
+        self.providers.insert(provider.id(), provider);
+    }
+
+    /// Record provider calls in `log`
+    pub fn set_audit_log(&mut self, log: Option<Arc<AuditLog>>) {
+        self.audit_log = log;
+    }
+
+    /// Log provider calls are recorded in
+    pub fn audit_log(&self) -> Option<&Arc<AuditLog>> {
+        self.audit_log.as_ref()
+    }
+
+    /// Scan prompts with `filter` before they are sent
+    pub fn set_outbound_filter(&mut self, filter: Option<OutboundFilter>) {
+        self.outbound_filter = filter;
+    }
+
+    /// Scan a prompt before it is sent, recording findings in the audit log
+    ///
+    /// Fails when the prompt is blocked or its findings cannot be recorded.
+    pub fn screen<'a>(&self, prompt: &'a Prompt) -> Result<Screened<'a>> {
+        let Some(filter) = &self.outbound_filter else {
+            return Ok(Screened::unscanned(prompt));
+        };
+        let screened = filter.screen(prompt);
+        if screened.findings().is_empty() {
+            return Ok(screened);
+        }
+
+        let event = AuditEvent::PromptScreened {
+            action: screened.action(),
+            findings: screened.findings().to_vec(),
+            prompt_sha256: sha256_hex(prompt.render().as_bytes()),
+        };
+        warn!("Found {}", event);
+        if let Some(log) = &self.audit_log {
+            log.record(event)?;
+        }
+        if screened.action() == RedactionAction::Block {
+            // A blocked prompt is refused even for local providers
+            screened.for_provider(true)?;
+        }
+        Ok(screened)
+    }
+
+    /// Limit where prompts from certain files or repositories may go
+    pub fn set_residency(&mut self, residency: Residency) {
+        self.residency = residency;
+    }
+
+    /// Data residency rules prompts are checked against
+    pub fn residency(&self) -> &Residency {
+        &self.residency
+    }
+
+    /// Candidates a prompt may be sent to under the data residency rules
+    ///
+    /// When a rule applies, candidates it does not allow are dropped. If
+    /// none remain, the allowed providers are tried with their default
+    /// models instead, and the prompt is refused when none is available.
+    pub fn permitted(&self, prompt: &Prompt, candidates: Vec<ResolvedModel>) -> Result<Vec<ResolvedModel>> {
+        let Some(restriction) = self.residency.restriction(prompt) else {
+            return Ok(candidates);
+        };
+
+        let mut permitted: Vec<ResolvedModel> = candidates
+            .into_iter()
+            .filter(|candidate| restriction.allows(&candidate.provider.id()))
+            .collect();
+        if permitted.is_empty() {
+            permitted = restriction
+                .providers
+                .iter()
+                .filter_map(|id| self.providers.get(id))
+                .filter(|provider| provider.is_available())
+                .map(|provider| ResolvedModel {
+                    provider: provider.clone(),
+                    model: None,
+                })
+                .collect();
+        }
+
+        if permitted.is_empty() {
+            if let Some(log) = &self.audit_log {
+                log.record_or_warn(AuditEvent::PolicyDecision {
+                    action: format!("send prompt from {}", restriction.matched),
+                    decision: Decision::Deny,
+                    rule: Some(restriction.rule.clone()),
+                });
+            }
+            return Err(restriction.withheld());
+        }
+        debug!(
+            "{} restricts the prompt to {}",
+            restriction.rule,
+            permitted.iter().map(|r| r.provider.id().to_string()).collect::<Vec<_>>().join(", ")
+        );
+        Ok(permitted)
+    }
+
+    /// Whether a provider runs on this machine
+    pub fn is_local(&self, id: &ProviderId) -> bool {
+        self.local.contains(id)
+    }
+
+    /// Available providers on this machine, for prompts that must stay here
+    pub fn local_providers(&self) -> Vec<Arc<dyn LlmProvider>> {
+        let mut local: Vec<_> = self
+            .providers
+            .iter()
+            .filter(|(id, provider)| self.local.contains(*id) && provider.is_available())
+            .map(|(_, provider)| provider.clone())
+            .collect();
+        local.sort_by_key(|provider| provider.id());
+        local
+    }
+
+    /// Get a registered provider
+    pub fn provider(&self, id: &ProviderId) -> Option<Arc<dyn LlmProvider>> {
+        self.providers.get(id).cloned()
+    }
+
+    /// Iterate over all registered providers
+    pub fn providers(&self) -> impl Iterator<Item = (&ProviderId, &Arc<dyn LlmProvider>)> {
+        self.providers.iter()
+    }
+
+    /// The Ollama provider, for model lifecycle operations
+    ///
+    /// Prefers the provider named `ollama` when several Ollama-kind
+    /// providers are configured.
+    pub fn ollama(&self) -> Option<&Arc<OllamaProvider>> {
+        self.ollama
+            .get(&ProviderId::OLLAMA)
+            .or_else(|| self.ollama.values().min_by_key(|p| p.id()))
+    }
+
+    /// Every provider of the Ollama kind
+    pub fn ollama_providers(&self) -> impl Iterator<Item = &Arc<OllamaProvider>> {
+        self.ollama.values()
+    }
+
+    /// Currently selected provider
+    pub fn current_provider(&self) -> &ProviderId {
+        &self.current_provider
+    }
+
+    /// Configured fallback chain
+    pub fn fallback_chain(&self) -> &[ProviderId] {
+        &self.fallback_chain
+    }
+
+    /// Configured model aliases
+    pub fn aliases(&self) -> &HashMap<String, Vec<ModelTarget>> {
+        &self.aliases
+    }
+
+    /// Whether a model name is an alias or names its provider explicitly
+    pub fn is_qualified(&self, name: &str) -> bool {
+        self.aliases.contains_key(name) || self.explicit_target(name).is_some()
+    }
+
+    /// `provider/model` for a registered provider
+    ///
+    /// Other names containing a slash are plain model names, such as
+    /// Ollama's `namespace/model`.
+    fn explicit_target(&self, name: &str) -> Option<ModelTarget> {
+        name.parse::<ModelTarget>()
+            .ok()
+            .filter(|target| self.providers.contains_key(&target.provider))
+    }
+
+    /// Resolve a model name or alias to the providers that should serve it
+    ///
+    /// Aliases expand to their available targets in order, `provider/model`
+    /// names a model of a specific provider, and any other name is a model
+    /// of the current provider.
+    pub fn resolve_model(&self, name: &str) -> Result<Vec<ResolvedModel>> {
+        let targets = match self.aliases.get(name) {
+            Some(targets) => targets.clone(),
+            None => vec![self.explicit_target(name).unwrap_or_else(|| ModelTarget {
+                provider: self.current_provider.clone(),
+                model: name.to_string(),
+            })],
+        };
+
+        let resolved: Vec<ResolvedModel> = targets
+            .iter()
+            .filter_map(|target| {
+                self.providers
+                    .get(&target.provider)
+                    .filter(|provider| provider.is_available())
+                    .map(|provider| ResolvedModel {
+                        provider: provider.clone(),
+                        model: Some(target.model.clone()),
+                    })
+            })
+            .collect();
+
+        if resolved.is_empty() {
+            return Err(match targets.as_slice() {
+                [target] => AiError::ProviderNotAvailable(target.provider.clone()),
+                _ => AiError::NoProviderAvailable,
+            }
+            .into());
+        }
+
+        debug!(
+            "Resolved {} to {}",
+            name,
+            resolved
+                .iter()
+                .map(|r| format!("{}/{}", r.provider.id(), r.model.as_deref().unwrap_or_default()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        Ok(resolved)
+    }
+
+    /// Switch to another provider after checking that it is reachable
+    #[instrument(skip(self))]
+    pub async fn switch_provider(&mut self, id: ProviderId) -> Result<()> {
+        let provider = self
+            .provider(&id)
+            .ok_or_else(|| AiError::ProviderNotAvailable(id.clone()))?;
+
+        if !provider.health_check().await?.is_available() {
+            return Err(AiError::ProviderNotAvailable(id).into());
+        }
+
+        info!("Switched to provider: {}", id);
+        self.current_provider = id;
+        Ok(())
+    }
+
+    /// Available providers in the order they should be tried
+    ///
+    /// Starts with the current provider, followed by the fallback chain.
+    pub fn provider_chain(&self) -> Vec<Arc<dyn LlmProvider>> {
+        let mut seen = HashSet::new();
+        std::iter::once(&self.current_provider)
+            .chain(self.fallback_chain.iter())
+            .filter(|id| seen.insert(*id))
+            .filter_map(|id| self.providers.get(id))
+            .filter(|provider| provider.is_available())
+            .cloned()
+            .collect()
+    }
+
+    /// Pick the provider for a request
+    pub fn select_provider(&self) -> Result<Arc<dyn LlmProvider>> {
+        self.provider_chain()
+            .into_iter()
+            .next()
+            .ok_or_else(|| AiError::NoProviderAvailable.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::test_support::StaticProvider;
+    use codev_shared::{ProviderConfig, ProviderKind, RedactionConfig, ResidencyRule};
+
+    fn custom_provider(kind: Option<ProviderKind>, endpoint: Option<&str>) -> ProviderConfig {
+        ProviderConfig {
+            kind,
+            endpoint: endpoint.map(String::from),
+            ..AiConfig::default().providers[&ProviderId::OLLAMA].clone()
+        }
+    }
+
+    #[test]
+    fn test_from_config_registers_custom_providers() {
+        let mut config = AiConfig::default();
+        let vllm = ProviderId::new("local-vllm");
+        config.providers.insert(
+            vllm.clone(),
+            custom_provider(Some(ProviderKind::OpenaiCompatible), Some("http://gpu-box:8000/v1")),
+        );
+        config.providers.insert(
+            ProviderId::new("lab-ollama"),
+            custom_provider(Some(ProviderKind::Ollama), Some("http://lab:11434")),
+        );
+
+        let manager = LlmManager::from_config(&config).unwrap();
+
+        assert_eq!(manager.provider(&vllm).unwrap().name(), "openai-compatible");
+        assert_eq!(manager.ollama_providers().count(), 2);
+        assert_eq!(manager.ollama().unwrap().id(), ProviderId::OLLAMA);
+    }
+
+    #[test]
+    fn test_from_config_requires_kind_for_custom_providers() {
+        let mut config = AiConfig::default();
+        config.providers.insert(ProviderId::new("mystery"), custom_provider(None, None));
+
+        assert!(LlmManager::from_config(&config).is_err());
+    }
+
+    fn manager_with_aliases() -> LlmManager {
+        let mut manager = LlmManager::new(ProviderId::OLLAMA, vec![]);
+        manager.register(Arc::new(StaticProvider::new(ProviderId::OLLAMA, &[])));
+        manager.register(Arc::new(StaticProvider::new(ProviderId::CLAUDE, &[])));
+        manager.aliases.insert(
+            "smart".to_string(),
+            vec![
+                "openai/gpt-4o".parse().unwrap(),
+                "claude/claude-3-5-sonnet-latest".parse().unwrap(),
+                "ollama/codellama:13b".parse().unwrap(),
+            ],
+        );
+        manager
+    }
+
+    fn targets(resolved: &[ResolvedModel]) -> Vec<String> {
+        resolved
+            .iter()
+            .map(|r| format!("{}/{}", r.provider.id(), r.model.as_deref().unwrap_or_default()))
+            .collect()
+    }
+
+    #[test]
+    fn test_resolve_alias_skips_unavailable_providers() {
+        let manager = manager_with_aliases();
+
+        assert_eq!(
+            targets(&manager.resolve_model("smart").unwrap()),
+            ["claude/claude-3-5-sonnet-latest", "ollama/codellama:13b"]
+        );
+    }
+
+    #[test]
+    fn test_resolve_plain_and_qualified_models() {
+        let manager = manager_with_aliases();
+
+        assert_eq!(targets(&manager.resolve_model("claude/claude-3-haiku").unwrap()), ["claude/claude-3-haiku"]);
+        // Unknown prefixes are part of an Ollama model name
+        assert_eq!(targets(&manager.resolve_model("library/phi").unwrap()), ["ollama/library/phi"]);
+        assert!(manager.is_qualified("smart"));
+        assert!(!manager.is_qualified("codellama:7b"));
+    }
+
+    #[test]
+    fn test_resolve_alias_without_available_target_fails() {
+        let mut manager = manager_with_aliases();
+        manager.aliases.insert("cloud".to_string(), vec!["openai/gpt-4o".parse().unwrap()]);
+
+        assert!(manager.resolve_model("cloud").is_err());
+    }
+
+    #[test]
+    fn test_screen_records_and_blocks_findings() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = Arc::new(AuditLog::open(dir.path().join("audit.jsonl")).unwrap());
+        let mut manager = LlmManager::new(ProviderId::OLLAMA, vec![]);
+        manager.set_audit_log(Some(log.clone()));
+        let config = RedactionConfig {
+            action: RedactionAction::Block,
+            ..RedactionConfig::default()
+        };
+        manager.set_outbound_filter(Some(OutboundFilter::new(&config).unwrap()));
+
+        assert!(manager.screen(&Prompt::from("Explain lifetimes")).unwrap().findings().is_empty());
+        let leaky = Prompt::from("AWS_SECRET_ACCESS_KEY=wJalrXUtnFEMIK7MDENGbPxRfiCY9xQ2kL7pZ");
+        let message = manager.screen(&leaky).unwrap_err().to_string();
+        assert!(message.contains("Prompt withheld: contains secret assignment on line 1"), "{}", message);
+
+        let records = AuditLog::read(log.path(), None).unwrap();
+        assert_eq!(records.len(), 1);
+        assert!(matches!(
+            &records[0].event,
+            AuditEvent::PromptScreened { action: RedactionAction::Block, findings, .. } if findings.len() == 1
+        ));
+    }
+
+    #[test]
+    fn test_residency_limits_candidates() {
+        let mut manager = manager_with_aliases();
+        manager.set_residency(Residency::with_base(
+            &[ResidencyRule {
+                paths: vec!["/work/payments/*".to_string()],
+                remotes: Vec::new(),
+                providers: vec![ProviderId::OLLAMA],
+            }],
+            "/",
+        ));
+        let restricted = Prompt::from("Review").source("/work/payments/api.rs");
+        let haiku = manager.resolve_model("claude/claude-3-haiku").unwrap();
+
+        // Unrestricted prompts keep their candidates
+        let candidates = manager.permitted(&Prompt::from("Review"), haiku.clone()).unwrap();
+        assert_eq!(targets(&candidates), ["claude/claude-3-haiku"]);
+
+        let candidates = manager.permitted(&restricted, manager.resolve_model("smart").unwrap()).unwrap();
+        assert_eq!(targets(&candidates), ["ollama/codellama:13b"]);
+        // With no allowed candidate the allowed provider's default model is used
+        let candidates = manager.permitted(&restricted, haiku.clone()).unwrap();
+        assert_eq!(targets(&candidates), ["ollama/"]);
+
+        manager.providers.remove(&ProviderId::OLLAMA);
+        let message = manager.permitted(&restricted, haiku).err().expect("prompt is withheld").to_string();
+        assert!(message.contains("/work/payments/api.rs may only go to ollama and none is available"), "{}", message);
+        assert!(message.contains("security.residency[0]"), "{}", message);
+    }
+}
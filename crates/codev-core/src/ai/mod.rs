@@ -2,20 +2,35 @@
 //!
 //! This module provides the core AI functionality for CoDev.rs including:
 //! - Abstract LLM Provider interface
-//! - Multiple provider implementations (Ollama, OpenAI, Claude, etc.)
+//! - Multiple provider implementations (Ollama, OpenAI-compatible, Anthropic)
 //! - Intelligent provider selection and fallback
+//! - Secret scanning of outgoing prompts
+//! - Data residency rules for prompts from restricted files
 //! - Streaming response handling
 //! - Cost optimization and routing
 
+pub mod compare;
 pub mod engine;
 pub mod manager;
+pub mod prompt;
 pub mod providers;
+pub mod recommend;
+pub mod redaction;
+pub mod residency;
 pub mod streaming;
 
+#[cfg(test)]
+pub(crate) mod test_support;
+
 // Re-export main types
+pub use compare::{CompareEvent, ProviderReport};
 pub use engine::AiEngine;
-pub use manager::LlmManager;
-pub use providers::{LlmProvider, ProviderType};
+pub use manager::{LlmManager, ResolvedModel};
+pub use prompt::{Prompt, PromptSegment, SegmentRole};
+pub use providers::OllamaProvider;
+pub use recommend::{HardwareProfile, Recommendation};
+pub use redaction::{Finding, OutboundFilter, Screened};
+pub use residency::{Residency, Restriction};
 pub use streaming::{StreamingResponse, TokenStream};
 
 use async_trait::async_trait;
@@ -23,7 +38,7 @@ use codev_shared::{CodevError, ProviderId, Result};
 use futures::Stream;
 use serde::{ Deserialize, Serialize};
 use std::pin::Pin;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 /// Abstract trait for all LLM providers
 #[async_trait]
@@ -34,6 +49,9 @@ pub trait LlmProvider: Send + Sync {
     /// Get the provider name
     fn name(&self) -> &str;
 
+    /// Model used when a request does not name one
+    fn model(&self) -> &str;
+
     /// Check if the provider is currently available
     fn is_available(&self) -> bool;
 
@@ -56,10 +74,45 @@ pub trait LlmProvider: Send + Sync {
     /// Get the cost per token (for optimization)
     fn cost_per_token(&self) -> f64;
 
+    /// Price of cached prompt tokens relative to `cost_per_token`
+    fn cache_pricing(&self) -> CachePricing {
+        CachePricing::default()
+    }
+
+    /// Generate from an assembled prompt, reporting usage and cost
+    ///
+    /// Providers with prompt caching override this to honour the prompt's
+    /// cache breakpoints; others receive the flattened prompt and usage is
+    /// estimated.
+    async fn generate_prompt(&self, prompt: &Prompt, options: GenerationOptions) -> Result<AiResponse> {
+        let started = Instant::now();
+        let text = prompt.render();
+        let model = options.model.clone().unwrap_or_else(|| self.model().to_string());
+        let content = self.generate(&text, options).await?;
+
+        let prompt_tokens = estimate_tokens(&text);
+        let completion_tokens = estimate_tokens(&content);
+        let usage = UsageStats {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+            ..UsageStats::default()
+        };
+        Ok(AiResponse::new(self, content, model, usage, started.elapsed(), None))
+    }
+
     /// Get supported capabilities
     fn capabilities(&self) -> ProviderCapabilities;
 }
 
+/// Rough token count for a piece of text
+///
+/// Providers do not all report usage for streamed responses, so this uses
+/// the usual ~4 characters per token heuristic.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(4)
+}
+
 /// Health status of a provider
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum HealthStatus {
@@ -100,7 +153,11 @@ pub struct GenerationOptions {
     pub stop: Option<Vec<String>>,
 
     /// Whether to stream the response
-    pub stream: bool
+    pub stream: bool,
+
+    /// Model to use instead of the provider's default
+    #[serde(default)]
+    pub model: Option<String>,
 }
 
 impl Default for GenerationOptions {
@@ -113,6 +170,7 @@ impl Default for GenerationOptions {
             presence_penalty: None,
             stop: None,
             stream: false,
+            model: None,
         }
     }
 }
@@ -191,6 +249,24 @@ pub enum TaskType {
     Refactoring,
 }
 
+impl TaskType {
+    /// Every task type, in declaration order
+    pub const ALL: [TaskType; 7] = [
+        TaskType::Chat,
+        TaskType::CodeGeneration,
+        TaskType::CodeAnalysis,
+        TaskType::CodeReview,
+        TaskType::Documentation,
+        TaskType::Debugging,
+        TaskType::Refactoring,
+    ];
+
+    /// Whether the task is mostly about reading or writing code
+    pub fn is_code_task(&self) -> bool {
+        !matches!(self, TaskType::Chat | TaskType::Documentation)
+    }
+}
+
 /// Project context information
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ProjectContext {
@@ -335,17 +411,124 @@ pub struct AiResponse {
     /// Usage statistics
     pub usage: UsageStats,
 
+    /// Cost of the request, split by token type
+    #[serde(default)]
+    pub cost: Option<CostBreakdown>,
+
     /// Response metadata
     pub metadata: ResponseMetadata,
 }
 
+impl AiResponse {
+    /// Build a response, pricing `usage` with the provider's rates
+    pub fn new<P: LlmProvider + ?Sized>(
+        provider: &P,
+        content: String,
+        model: String,
+        mut usage: UsageStats,
+        response_time: Duration,
+        finish_reason: Option<String>,
+    ) -> Self {
+        let cost = CostBreakdown::new(&usage, provider.cost_per_token(), provider.cache_pricing());
+        usage.estimated_cost = Some(cost.total());
+
+        Self {
+            content,
+            provider: provider.id(),
+            model,
+            usage,
+            cost: Some(cost),
+            metadata: ResponseMetadata {
+                response_time,
+                model_version: None,
+                finish_reason,
+                safety_filtered: false,
+            },
+        }
+    }
+}
+
+/// Price multipliers for cached prompt tokens
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct CachePricing {
+    /// Multiplier for prompt tokens read from the cache
+    pub read: f64,
+
+    /// Multiplier for prompt tokens written to the cache
+    pub write: f64,
+}
+
+impl Default for CachePricing {
+    fn default() -> Self {
+        Self { read: 1.0, write: 1.0 }
+    }
+}
+
+/// Cost of a response in USD
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct CostBreakdown {
+    /// Prompt tokens evaluated normally
+    pub prompt: f64,
+
+    /// Prompt tokens written to the cache
+    pub cache_write: f64,
+
+    /// Prompt tokens read from the cache
+    pub cached_prompt: f64,
+
+    /// Generated tokens
+    pub completion: f64,
+
+    /// What caching saved compared to evaluating the whole prompt
+    pub saved: f64,
+}
+
+impl CostBreakdown {
+    pub fn new(usage: &UsageStats, cost_per_token: f64, pricing: CachePricing) -> Self {
+        let uncached = usage
+            .prompt_tokens
+            .saturating_sub(usage.cached_prompt_tokens + usage.cache_write_tokens);
+        let cache_write = usage.cache_write_tokens as f64 * cost_per_token * pricing.write;
+        let cached_prompt = usage.cached_prompt_tokens as f64 * cost_per_token * pricing.read;
+        let prompt = uncached as f64 * cost_per_token;
+        let full_price = usage.prompt_tokens as f64 * cost_per_token;
+
+        Self {
+            prompt,
+            cache_write,
+            cached_prompt,
+            completion: usage.completion_tokens as f64 * cost_per_token,
+            saved: full_price - (prompt + cache_write + cached_prompt),
+        }
+    }
+
+    pub fn total(&self) -> f64 {
+        self.prompt + self.cache_write + self.cached_prompt + self.completion
+    }
+}
+
 /// Usage statistics for a response
-#[derive(Serialize, Deserialize, Debug, Clone)]
+///
+/// `prompt_tokens` counts the whole prompt, including the cached parts.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct UsageStats {
     pub prompt_tokens: usize,
     pub completion_tokens: usize,
     pub total_tokens: usize,
     pub estimated_cost: Option<f64>,
+
+    /// Tokens of earlier turns carried over as context instead of being
+    /// evaluated again
+    #[serde(default)]
+    pub context_tokens_reused: usize,
+
+    /// Prompt tokens served from the provider's prompt cache
+    #[serde(default)]
+    pub cached_prompt_tokens: usize,
+
+    /// Prompt tokens written to the provider's prompt cache
+    #[serde(default)]
+    pub cache_write_tokens: usize,
 }
 
 /// Metadata about the response
@@ -416,10 +599,39 @@ impl From<AiError> for CodevError {
                     message: "Provider not available".to_string(),
                 }
             }
+            AiError::RateLimited(provider) => CodevError::RateLimit { provider },
+            AiError::InvalidApiKey(provider) => CodevError::Authentication { provider },
+            AiError::ModelNotFound { ref provider, .. }
+            | AiError::NetworkTimeout(ref provider)
+            | AiError::ServerError { ref provider, .. } => CodevError::LlmProvider {
+                provider: provider.clone(),
+                message: error.to_string(),
+            },
             _ => CodevError::LlmProvider {
-                provider: ProviderId::Ollama, // Default fallback
+                provider: ProviderId::OLLAMA, // Default fallback
                 message: error.to_string(),
             },
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cost_breakdown_discounts_cached_tokens() {
+        let usage = UsageStats {
+            prompt_tokens: 1000,
+            completion_tokens: 100,
+            total_tokens: 1100,
+            cached_prompt_tokens: 800,
+            ..UsageStats::default()
+        };
+        let cost = CostBreakdown::new(&usage, 0.001, CachePricing { read: 0.1, write: 1.25 });
+
+        assert!((cost.prompt - 0.2).abs() < 1e-9);
+        assert!((cost.cached_prompt - 0.08).abs() < 1e-9);
+        assert!((cost.saved - 0.72).abs() < 1e-9);
+        assert!((cost.total() - 0.38).abs() < 1e-9);
+    }
+}
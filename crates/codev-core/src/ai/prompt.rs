@@ -0,0 +1,173 @@
+//! Prompt Assembly
+//!
+//! Prompts are built from segments, stable ones first (instructions, project
+//! context) and the turn-specific request last. A cache breakpoint after a
+//! segment marks everything up to it as a prefix worth caching, which
+//! providers with prompt caching use to bill repeated preambles at a
+//! discount. Prompts also remember the files and repository their content
+//! came from, which decides where they may be sent.
+
+use codev_shared::GitInfo;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Who a prompt segment speaks for
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentRole {
+    System,
+    User,
+}
+
+/// A piece of a prompt
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PromptSegment {
+    pub role: SegmentRole,
+    pub text: String,
+
+    /// Whether the prompt up to and including this segment should be cached
+    pub cache_breakpoint: bool,
+}
+
+/// A prompt assembled from segments
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Prompt {
+    segments: Vec<PromptSegment>,
+
+    /// Files the content was taken from
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    sources: Vec<PathBuf>,
+
+    /// Remote URL of the repository the content belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    repository: Option<String>,
+}
+
+impl Prompt {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add system instructions
+    pub fn system(self, text: impl Into<String>) -> Self {
+        self.push(SegmentRole::System, text)
+    }
+
+    /// Add user content, such as project context or the request itself
+    pub fn user(self, text: impl Into<String>) -> Self {
+        self.push(SegmentRole::User, text)
+    }
+
+    fn push(mut self, role: SegmentRole, text: impl Into<String>) -> Self {
+        self.segments.push(PromptSegment {
+            role,
+            text: text.into(),
+            cache_breakpoint: false,
+        });
+        self
+    }
+
+    /// Record that content was taken from `path`
+    pub fn source(mut self, path: impl Into<PathBuf>) -> Self {
+        self.sources.push(path.into());
+        self
+    }
+
+    /// Record the repository the content belongs to
+    pub fn repository(mut self, git: &GitInfo) -> Self {
+        self.repository = git.remote_url.clone();
+        self
+    }
+
+    /// Mark the prompt so far as a cacheable prefix
+    ///
+    /// Only content that is identical across requests benefits, so place
+    /// breakpoints after stable segments and before per-turn content.
+    pub fn cache_breakpoint(mut self) -> Self {
+        if let Some(last) = self.segments.last_mut() {
+            last.cache_breakpoint = true;
+        }
+        self
+    }
+
+    pub fn segments(&self) -> &[PromptSegment] {
+        &self.segments
+    }
+
+    /// Files the content was taken from
+    pub fn sources(&self) -> impl Iterator<Item = &Path> {
+        self.sources.iter().map(PathBuf::as_path)
+    }
+
+    /// Remote URL of the repository the content belongs to
+    pub fn remote_url(&self) -> Option<&str> {
+        self.repository.as_deref()
+    }
+
+    /// Whether any segment is marked as a cache breakpoint
+    pub fn has_cache_breakpoints(&self) -> bool {
+        self.segments.iter().any(|s| s.cache_breakpoint)
+    }
+
+    /// Text of the segments with `role`, in order
+    pub fn text_of(&self, role: SegmentRole) -> Vec<&str> {
+        self.segments
+            .iter()
+            .filter(|s| s.role == role)
+            .map(|s| s.text.as_str())
+            .collect()
+    }
+
+    /// The same segments with their text passed through `f`
+    pub fn map_text(&self, mut f: impl FnMut(&str) -> String) -> Prompt {
+        Prompt {
+            segments: self
+                .segments
+                .iter()
+                .map(|s| PromptSegment {
+                    text: f(&s.text),
+                    ..s.clone()
+                })
+                .collect(),
+            sources: self.sources.clone(),
+            repository: self.repository.clone(),
+        }
+    }
+
+    /// Flatten into a single prompt for providers without structured input
+    pub fn render(&self) -> String {
+        self.segments
+            .iter()
+            .map(|s| s.text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
+impl From<&str> for Prompt {
+    fn from(text: &str) -> Self {
+        Prompt::new().user(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_breakpoint_marks_last_segment() {
+        let prompt = Prompt::new()
+            .system("You are a Rust assistant")
+            .user("Project context")
+            .cache_breakpoint()
+            .user("Fix the bug");
+
+        let marked: Vec<bool> = prompt.segments().iter().map(|s| s.cache_breakpoint).collect();
+        assert_eq!(marked, [false, true, false]);
+        assert_eq!(prompt.render(), "You are a Rust assistant\n\nProject context\n\nFix the bug");
+    }
+
+    #[test]
+    fn test_cache_breakpoint_on_empty_prompt_is_ignored() {
+        assert!(!Prompt::new().cache_breakpoint().has_cache_breakpoints());
+    }
+}
@@ -0,0 +1,393 @@
+//! Anthropic Provider Implementation
+//!
+//! Talks to the Anthropic Messages API.
+
+use crate::ai::providers::openai::check_status;
+use crate::ai::providers::sse;
+use crate::ai::{
+    AiError, AiResponse, CachePricing, GenerationOptions, HealthStatus, LlmProvider, Prompt, ProviderCapabilities,
+    SegmentRole, UsageStats,
+};
+use async_trait::async_trait;
+use codev_shared::{ProviderId, Result};
+use futures::{Stream, StreamExt};
+use reqwest::{Client, RequestBuilder, Response};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tracing::instrument;
+
+/// API version sent with every request
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Most cache breakpoints the API accepts in one request
+const MAX_CACHE_BREAKPOINTS: usize = 4;
+
+/// Provider for the Anthropic Messages API
+pub struct AnthropicProvider {
+    id: ProviderId,
+    client: Client,
+    endpoint: String,
+    api_key: Option<SecretString>,
+    model: String,
+    available: AtomicBool,
+    cost_per_token: f64,
+    context_length: usize,
+    timeout: Duration,
+}
+
+/// Request payload for `/messages`
+#[derive(Serialize, Debug)]
+struct MessagesRequest<'a> {
+    model: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    system: Vec<TextBlock<'a>>,
+    messages: Vec<Message<'a>>,
+    max_tokens: usize,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_sequences: Option<&'a [String]>,
+}
+
+#[derive(Serialize, Debug)]
+struct Message<'a> {
+    role: &'a str,
+    content: Vec<TextBlock<'a>>,
+}
+
+/// A text content block, optionally ending a cached prefix
+#[derive(Serialize, Debug)]
+struct TextBlock<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    text: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache_control: Option<CacheControl>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct CacheControl {
+    #[serde(rename = "type")]
+    kind: &'static str,
+}
+
+impl<'a> TextBlock<'a> {
+    fn new(text: &'a str, cached: bool) -> Self {
+        Self {
+            kind: "text",
+            text,
+            cache_control: cached.then_some(CacheControl { kind: "ephemeral" }),
+        }
+    }
+}
+
+/// Complete response from `/messages`
+#[derive(Deserialize, Debug)]
+struct MessagesResponse {
+    content: Vec<ContentBlock>,
+    #[serde(default)]
+    usage: Option<Usage>,
+    #[serde(default)]
+    stop_reason: Option<String>,
+}
+
+/// Token usage; `input_tokens` excludes cached tokens
+#[derive(Deserialize, Debug)]
+struct Usage {
+    input_tokens: usize,
+    output_tokens: usize,
+    #[serde(default)]
+    cache_creation_input_tokens: usize,
+    #[serde(default)]
+    cache_read_input_tokens: usize,
+}
+
+#[derive(Deserialize, Debug)]
+struct ContentBlock {
+    #[serde(default)]
+    text: String,
+}
+
+/// One event of a streamed response
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamEvent {
+    ContentBlockDelta { delta: Delta },
+    Error { error: ErrorBody },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug)]
+struct Delta {
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorBody {
+    message: String,
+}
+
+impl AnthropicProvider {
+    pub const DEFAULT_ENDPOINT: &'static str = "https://api.anthropic.com/v1";
+
+    /// Create a provider for `endpoint`, the API base URL ending in `/v1`
+    pub fn new(id: ProviderId, client: Client, endpoint: String, model: String, api_key: Option<SecretString>) -> Self {
+        Self {
+            id,
+            client,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            api_key,
+            model,
+            available: AtomicBool::new(true),
+            cost_per_token: 0.0,
+            context_length: ProviderCapabilities::default().max_context_length,
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Bound control requests such as health checks; generation is bounded
+    /// by the client's read timeout
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_cost_per_token(mut self, cost_per_token: f64) -> Self {
+        self.cost_per_token = cost_per_token;
+        self
+    }
+
+    pub fn with_context_length(mut self, context_length: usize) -> Self {
+        self.context_length = context_length;
+        self
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        let request = request.header("anthropic-version", ANTHROPIC_VERSION);
+        match &self.api_key {
+            Some(key) => request.header("x-api-key", key.expose_secret()),
+            None => request,
+        }
+    }
+
+    async fn send_messages(&self, prompt: &Prompt, options: &GenerationOptions, stream: bool) -> Result<Response> {
+        let (system, content) = prompt_blocks(prompt);
+        let request = MessagesRequest {
+            model: options.model.as_deref().unwrap_or(&self.model),
+            system,
+            messages: vec![Message { role: "user", content }],
+            // Required by the API
+            max_tokens: options.max_tokens.unwrap_or(4096),
+            stream,
+            temperature: options.temperature,
+            top_p: options.top_p,
+            stop_sequences: options.stop.as_deref(),
+        };
+
+        let response = self
+            .authorize(self.client.post(format!("{}/messages", self.endpoint)))
+            .json(&request)
+            .send()
+            .await
+            .map_err(|_| AiError::NetworkTimeout(self.id.clone()))?;
+
+        check_status(&self.id, response).await
+    }
+}
+
+/// System and user blocks for a prompt, with `cache_control` on breakpoints
+///
+/// The API rejects more than four breakpoints, so only the last four are
+/// kept; they cover the longest prefixes.
+fn prompt_blocks(prompt: &Prompt) -> (Vec<TextBlock<'_>>, Vec<TextBlock<'_>>) {
+    let breakpoints = prompt.segments().iter().filter(|s| s.cache_breakpoint).count();
+    let mut skip = breakpoints.saturating_sub(MAX_CACHE_BREAKPOINTS);
+
+    let mut system = Vec::new();
+    let mut user = Vec::new();
+    for segment in prompt.segments() {
+        let mut cached = segment.cache_breakpoint;
+        if cached && skip > 0 {
+            skip -= 1;
+            cached = false;
+        }
+
+        let block = TextBlock::new(&segment.text, cached);
+        match segment.role {
+            SegmentRole::System => system.push(block),
+            SegmentRole::User => user.push(block),
+        }
+    }
+    (system, user)
+}
+
+#[async_trait]
+impl LlmProvider for AnthropicProvider {
+    fn id(&self) -> ProviderId {
+        self.id.clone()
+    }
+
+    fn name(&self) -> &str {
+        "anthropic"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn is_available(&self) -> bool {
+        self.available.load(Ordering::Relaxed)
+    }
+
+    #[instrument(skip(self))]
+    async fn health_check(&self) -> Result<HealthStatus> {
+        let status = match self
+            .authorize(self.client.get(format!("{}/models", self.endpoint)))
+            .timeout(self.timeout)
+            .send()
+            .await
+        {
+            Ok(response) => match check_status(&self.id, response).await {
+                Ok(_) => HealthStatus::Healthy,
+                Err(e) => HealthStatus::Unhealthy { error: e.to_string() },
+            },
+            Err(e) => HealthStatus::Unhealthy {
+                error: format!("{} is not reachable: {}", self.endpoint, e),
+            },
+        };
+
+        self.available.store(status.is_available(), Ordering::Relaxed);
+        Ok(status)
+    }
+
+    #[instrument(skip(self, prompt, options))]
+    async fn stream_generate(
+        &self,
+        prompt: &str,
+        options: &GenerationOptions,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<String>> + Send>>> {
+        let response = self.send_messages(&Prompt::from(prompt), options, true).await?;
+
+        let stream = sse::data_lines(response).filter_map(|data| {
+            let item = match data.and_then(|data| sse::parse_event::<StreamEvent>(&data)) {
+                Ok(StreamEvent::ContentBlockDelta { delta }) if !delta.text.is_empty() => Some(Ok(delta.text)),
+                Ok(StreamEvent::Error { error }) => Some(Err(AiError::StreamingError(error.message).into())),
+                Ok(_) => None,
+                Err(e) => Some(Err(e)),
+            };
+            futures::future::ready(item)
+        });
+
+        Ok(Box::pin(stream))
+    }
+
+    #[instrument(skip(self, prompt, options))]
+    async fn generate(&self, prompt: &str, options: GenerationOptions) -> Result<String> {
+        let response = self.send_messages(&Prompt::from(prompt), &options, false).await?;
+        let body: MessagesResponse = response
+            .json()
+            .await
+            .map_err(|e| AiError::StreamingError(format!("Failed to parse messages response: {}", e)))?;
+
+        Ok(body.content.into_iter().map(|block| block.text).collect())
+    }
+
+    fn max_content_length(&self) -> usize {
+        self.context_length
+    }
+
+    fn cost_per_token(&self) -> f64 {
+        self.cost_per_token
+    }
+
+    fn cache_pricing(&self) -> CachePricing {
+        CachePricing { read: 0.1, write: 1.25 }
+    }
+
+    #[instrument(skip(self, prompt, options))]
+    async fn generate_prompt(&self, prompt: &Prompt, options: GenerationOptions) -> Result<AiResponse> {
+        let started = Instant::now();
+        let model = options.model.clone().unwrap_or_else(|| self.model.clone());
+        let response = self.send_messages(prompt, &options, false).await?;
+        let body: MessagesResponse = response
+            .json()
+            .await
+            .map_err(|e| AiError::StreamingError(format!("Failed to parse messages response: {}", e)))?;
+
+        let usage = body
+            .usage
+            .map(|usage| {
+                let prompt_tokens =
+                    usage.input_tokens + usage.cache_creation_input_tokens + usage.cache_read_input_tokens;
+                UsageStats {
+                    prompt_tokens,
+                    completion_tokens: usage.output_tokens,
+                    total_tokens: prompt_tokens + usage.output_tokens,
+                    cached_prompt_tokens: usage.cache_read_input_tokens,
+                    cache_write_tokens: usage.cache_creation_input_tokens,
+                    ..UsageStats::default()
+                }
+            })
+            .unwrap_or_default();
+
+        let content = body.content.into_iter().map(|block| block.text).collect();
+        Ok(AiResponse::new(self, content, model, usage, started.elapsed(), body.stop_reason))
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            function_calling: true,
+            max_context_length: self.context_length,
+            ..ProviderCapabilities::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prompt_blocks_mark_breakpoints() {
+        let prompt = Prompt::new()
+            .system("Be concise")
+            .cache_breakpoint()
+            .user("Project context")
+            .cache_breakpoint()
+            .user("Explain main.rs");
+
+        let (system, user) = prompt_blocks(&prompt);
+        assert_eq!(system.len(), 1);
+        assert!(system[0].cache_control.is_some());
+        let cached: Vec<bool> = user.iter().map(|b| b.cache_control.is_some()).collect();
+        assert_eq!(cached, [true, false]);
+    }
+
+    #[test]
+    fn test_prompt_blocks_keep_last_four_breakpoints() {
+        let prompt = (0..6).fold(Prompt::new(), |prompt, i| prompt.user(i.to_string()).cache_breakpoint());
+
+        let (_, user) = prompt_blocks(&prompt);
+        let cached: Vec<bool> = user.iter().map(|b| b.cache_control.is_some()).collect();
+        assert_eq!(cached, [false, false, true, true, true, true]);
+    }
+
+    #[test]
+    fn test_parse_stream_events() {
+        let delta: StreamEvent =
+            sse::parse_event(r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"hi"}}"#)
+                .unwrap();
+        assert!(matches!(delta, StreamEvent::ContentBlockDelta { delta } if delta.text == "hi"));
+
+        let ping: StreamEvent = sse::parse_event(r#"{"type":"ping"}"#).unwrap();
+        assert!(matches!(ping, StreamEvent::Other));
+    }
+}
@@ -0,0 +1,123 @@
+//! LLM provider implementations
+//!
+//! Providers are configured by name in `AiConfig.providers`; the entry's
+//! `kind` decides which implementation serves it, so several providers can
+//! share one implementation (e.g. a local vLLM next to OpenAI).
+
+pub mod anthropic;
+pub mod ollama;
+pub mod openai;
+pub mod pool;
+mod sse;
+pub mod transport;
+
+pub use anthropic::AnthropicProvider;
+pub use ollama::{LoadedModel, ModelDetails, ModelInfo, ModelProfile, OllamaProvider};
+pub use openai::OpenAiCompatibleProvider;
+pub use pool::{Endpoint, EndpointPool};
+pub use transport::build_client;
+
+use crate::ai::LlmProvider;
+use codev_shared::{AiConfig, ConfigError, ProviderConfig, ProviderId, ProviderKind, Result};
+use reqwest::Client;
+use secrecy::SecretString;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A provider built from configuration
+pub enum ConfiguredProvider {
+    /// Ollama, kept concrete for model lifecycle operations
+    Ollama(Arc<OllamaProvider>),
+    Remote(Arc<dyn LlmProvider>),
+}
+
+impl ConfiguredProvider {
+    pub fn as_provider(&self) -> Arc<dyn LlmProvider> {
+        match self {
+            ConfiguredProvider::Ollama(provider) => provider.clone(),
+            ConfiguredProvider::Remote(provider) => provider.clone(),
+        }
+    }
+}
+
+/// Create the provider configured under `id`
+///
+/// Built-in names imply their kind and endpoint; custom names must set
+/// `kind`, and `endpoint` unless they are Ollama. All providers share
+/// `client`, built by [`build_client`].
+pub fn create_provider(
+    client: &Client,
+    id: &ProviderId,
+    provider_config: &ProviderConfig,
+    config: &AiConfig,
+    api_key: Option<SecretString>,
+) -> Result<ConfiguredProvider> {
+    let kind = provider_config.resolved_kind(id).ok_or_else(|| ConfigError::MissingRequired {
+        key: format!("ai.providers.{}.kind", id),
+    })?;
+    let timeout = Duration::from_secs(provider_config.timeout_seconds.unwrap_or(30));
+    let cost_per_token = provider_config.cost_per_token.unwrap_or(0.0);
+
+    let provider = match kind {
+        ProviderKind::Ollama => {
+            let endpoint = provider_config
+                .endpoint
+                .clone()
+                .unwrap_or_else(|| config.ollama.endpoint.clone());
+            let provider = OllamaProvider::with_config(
+                client.clone(),
+                endpoint,
+                provider_config.model.clone(),
+                timeout,
+                provider_config.max_retries.unwrap_or(3),
+            )
+            .with_id(id.clone())
+            .with_endpoints(provider_config.endpoints.clone())
+            .with_keep_alive(config.ollama.keep_alive)
+            .with_context_length(config.ollama.max_content_length);
+
+            ConfiguredProvider::Ollama(Arc::new(provider))
+        }
+        ProviderKind::OpenaiCompatible => {
+            let endpoint = endpoint_for(id, provider_config, OpenAiCompatibleProvider::default_endpoint(id))?;
+            let provider = OpenAiCompatibleProvider::new(
+                id.clone(),
+                client.clone(),
+                endpoint,
+                provider_config.model.clone(),
+                api_key,
+            )
+            .with_timeout(timeout)
+            .with_cost_per_token(cost_per_token);
+            ConfiguredProvider::Remote(Arc::new(provider))
+        }
+        ProviderKind::Anthropic => {
+            let endpoint = endpoint_for(id, provider_config, Some(AnthropicProvider::DEFAULT_ENDPOINT))?;
+            let provider = AnthropicProvider::new(
+                id.clone(),
+                client.clone(),
+                endpoint,
+                provider_config.model.clone(),
+                api_key,
+            )
+            .with_timeout(timeout)
+            .with_cost_per_token(cost_per_token);
+            ConfiguredProvider::Remote(Arc::new(provider))
+        }
+    };
+
+    Ok(provider)
+}
+
+fn endpoint_for(id: &ProviderId, provider_config: &ProviderConfig, default: Option<&str>) -> Result<String> {
+    provider_config
+        .endpoint
+        .clone()
+        .or_else(|| default.map(String::from))
+        .ok_or_else(|| {
+            ConfigError::MissingRequired {
+                key: format!("ai.providers.{}.endpoint", id),
+            }
+            .into()
+        })
+}
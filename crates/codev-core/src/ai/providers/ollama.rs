@@ -4,24 +4,56 @@
 //! This is the primary provider for CoDev.rs, offering privacy-first AI capabilities.
 
 use crate::ai:: {
-    AiError, GenerationOptions, HealthStatus, LlmProvider, ProviderCapabilities,
+    estimate_tokens, AiError, AiResponse, ChatMessage, GenerationOptions, HealthStatus, LlmProvider,
+    MessageRole, ProviderCapabilities, UsageStats,
 };
+use crate::ai::providers::pool::{Endpoint, EndpointPool, InFlight};
+use crate::ai::providers::sse::drain_lines;
+use crate::ai::providers::transport::build_client;
 use async_trait::async_trait;
-use codev_shared::{ProviderId, Result};
+use codev_shared::{CodevError, HttpConfig, ProviderId, Result};
 use futures::{Stream, StreamExt};
 use reqwest::{Client, Response};
 use serde::{ Serialize, Deserialize};
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::{Duration, Instant};
 use tracing::{debug, error, info, instrument, warn};
 
 /// Ollama provider for local LLM inference
 pub struct OllamaProvider {
+    id: ProviderId,
     client: Client,
-    endpoint: String,
+    pool: EndpointPool,
     model: String,
     timeout: Duration,
     max_retries: u32,
+    available: AtomicBool,
+    keep_alive: Option<u64>,
+    context_length: usize,
+    last_used: Mutex<HashMap<String, Instant>>,
+    profiles: RwLock<HashMap<String, ModelProfile>>,
+    sessions: Mutex<HashMap<String, SessionContext>>,
+}
+
+/// Sessions whose context is kept; the least recently used is dropped first
+const MAX_SESSIONS: usize = 32;
+
+/// How long an unused session context is kept
+const SESSION_TTL: Duration = Duration::from_secs(30 * 60);
+
+/// Context tokens Ollama returned for the last turn of a conversation
+#[derive(Debug, Clone)]
+struct SessionContext {
+    model: String,
+    tokens: Vec<i32>,
+
+    /// Fingerprint of the history the tokens encode
+    history: u64,
+    used: Instant,
 }
 
 /// Request payload for Ollama API
@@ -32,6 +64,10 @@ struct OllamaRequest {
     stream: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     options: Option<OllamaOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<u64>, // seconds, 0 unloads the model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context: Option<Vec<i32>>,
 }
 
 /// Options specific to Ollama
@@ -42,9 +78,11 @@ struct OllamaOptions {
     #[serde(skip_serializing_if = "Option::is_none")]
     top_p: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    num_predict: Option<f32>, // max_tokens equivalent
+    num_predict: Option<i32>, // max_tokens equivalent
     #[serde(skip_serializing_if = "Option::is_none")]
-    stop: Opttion<Vec<String>>,
+    stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_ctx: Option<usize>,
 }
 
 /// Response from Ollama API
@@ -55,6 +93,8 @@ struct OllamaResponse {
     #[serde(default)]
     done: bool,
     #[serde(default)]
+    done_reason: Option<String>,
+    #[serde(default)]
     context: Option<Vec<i32>>,
     #[serde(default)]
     total_duration: Option<u64>,
@@ -66,6 +106,26 @@ struct OllamaResponse {
     eval_count: Option<u32>,
 }
 
+/// Models currently loaded in memory, from `/api/ps`
+#[derive(Deserialize, Debug)]
+struct RunningModelsResponse {
+    models: Vec<LoadedModel>,
+}
+
+/// A model loaded in Ollama's memory
+#[derive(Deserialize, Debug, Clone)]
+pub struct LoadedModel {
+    pub name: String,
+    #[serde(skip)]
+    pub endpoint: String,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub size_vram: u64,
+    #[serde(default)]
+    pub expires_at: Option<String>,
+}
+
 /// Information about available models
 #[derive(Deserialize, Debug)]
 struct ModelsResponse {
@@ -73,84 +133,260 @@ struct ModelsResponse {
 }
 
 /// Information about a single model
-#[derive(Deserialize, Debug)]
-struct ModelInfo {
-    name: String,
-    size: u64,
-    digest: String,
+#[derive(Deserialize, Debug, Clone)]
+pub struct ModelInfo {
+    pub name: String,
+    pub size: u64,
+    pub digest: String,
     #[serde(default)]
-    details: ModelDetails,
+    pub details: ModelDetails,
 }
 
 /// Detailed model information
-#[derive(Deserialize, Debug, Default)]
-struct ModelDetails {
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ModelDetails {
     #[serde(default)]
-    format: String,
+    pub format: String,
     #[serde(default)]
-    family: String,
+    pub family: String,
     #[serde(default)]
-    families: Option<Vec<String>>,
+    pub families: Option<Vec<String>>,
     #[serde(default)]
-    parameter_size: String,
+    pub parameter_size: String,
     #[serde(default)]
-    quantization_level: String,
+    pub quantization_level: String,
 }
 
-impl OllamaProvider {
-    /// Create a new Ollama provider
-    pub fn new(endpoint: String, model: String) -> Self {
-        let client = Client::builder()
-            .timeout(Duration::from_secs(300)) // 5 minutes for model loading
-            .build()
-            .expect("Failed to create HTTP client");
+/// Response from `/api/show`
+#[derive(Deserialize, Debug)]
+struct ShowResponse {
+    #[serde(default)]
+    template: String,
+    #[serde(default)]
+    details: ModelDetails,
+    #[serde(default)]
+    model_info: HashMap<String, serde_json::Value>,
+    #[serde(default)]
+    capabilities: Vec<String>,
+}
+
+/// What a model can do, as reported by `/api/show`
+#[derive(Debug, Clone)]
+pub struct ModelProfile {
+    pub name: String,
+    pub details: ModelDetails,
+    pub context_length: Option<usize>,
+    pub template: String,
+    pub supports_tools: bool,
+}
+
+impl ModelProfile {
+    fn from_show(name: &str, show: ShowResponse) -> Self {
+        // Keys are prefixed with the architecture, e.g. `llama.context_length`
+        let architecture = show
+            .model_info
+            .get("general.architecture")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default();
+        let context_length = show
+            .model_info
+            .get(&format!("{}.context_length", architecture))
+            .or_else(|| {
+                show.model_info
+                    .iter()
+                    .find(|(key, _)| key.ends_with(".context_length"))
+                    .map(|(_, value)| value)
+            })
+            .and_then(|v| v.as_u64())
+            .map(|v| v as usize);
+
+        // Older servers do not report capabilities, but a template that
+        // renders `.Tools` means the model was trained for tool calls.
+        let supports_tools = show.capabilities.iter().any(|c| c == "tools")
+            || show.template.contains(".Tools");
 
         Self {
-            client,
-            endpoint: endpoint.trim_end_matches('/').to_string(),
-            model,
-            timeout: Duration::from_secs(30),
-            max_retries: 3,
+            name: name.to_string(),
+            details: show.details,
+            context_length,
+            template: show.template,
+            supports_tools,
         }
     }
+}
+
+impl OllamaProvider {
+    /// Create a new Ollama provider with the default transport
+    pub fn new(endpoint: String, model: String) -> Result<Self> {
+        let client = build_client(&HttpConfig::default())?;
+        Ok(Self::with_config(client, endpoint, model, Duration::from_secs(30), 3))
+    }
 
     /// Create with custom configuration
+    ///
+    /// `timeout` bounds control requests such as listing models;
+    /// generation is bounded by the client's read timeout.
     pub fn with_config(
+        client: Client,
         endpoint: String,
         model: String,
         timeout: Duration,
         max_retries: u32,
     ) -> Self {
-        let client = Client::builder()
-            .timeout(timeout)
-            .build()
-            .expect("Failed to create HTTP client");
-
         Self {
+            id: ProviderId::OLLAMA,
             client,
-            endpoint: endpoint.trim_end_matches('/').to_string(),
+            pool: EndpointPool::new([endpoint]),
             model,
             timeout,
             max_retries,
+            available: AtomicBool::new(true),
+            keep_alive: None,
+            context_length: ProviderCapabilities::default().max_context_length,
+            last_used: Mutex::new(HashMap::new()),
+            profiles: RwLock::new(HashMap::new()),
+            sessions: Mutex::new(HashMap::new()),
         }
     }
 
-    /// Check  if Ollama service is running
-    async fn is_service_running(&self) -> bool {
-        match self.client.get(&format!("{}/api/tags", self.endpoint)).send().await {
-            Ok(response) => response.status().is_success(),
-            Err(_) => false,
+    /// Serve under a custom provider name
+    pub fn with_id(mut self, id: ProviderId) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Add more servers to balance requests over
+    pub fn with_endpoints(mut self, endpoints: Vec<String>) -> Self {
+        let urls: Vec<String> = self
+            .pool
+            .endpoints()
+            .iter()
+            .map(|e| e.url().to_string())
+            .chain(endpoints)
+            .collect();
+        self.pool = EndpointPool::new(urls);
+        self
+    }
+
+    /// Servers this provider balances requests over
+    pub fn endpoints(&self) -> &[Arc<Endpoint>] {
+        self.pool.endpoints()
+    }
+
+    /// Keep models loaded for this many seconds after each request
+    pub fn with_keep_alive(mut self, keep_alive: Option<u64>) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Context window (`num_ctx`) requested from Ollama
+    pub fn with_context_length(mut self, context_length: usize) -> Self {
+        self.context_length = context_length;
+        self
+    }
+
+    /// Get a model's profile, querying `/api/show` on first use
+    #[instrument(skip(self))]
+    pub async fn model_profile(&self, model: &str) -> Result<ModelProfile> {
+        if let Some(profile) = self.profiles.read().unwrap().get(model) {
+            return Ok(profile.clone());
         }
+
+        let mut response = None;
+        for endpoint in self.pool.candidates(model) {
+            match self
+                .client
+                .post(&format!("{}/api/show", endpoint.url()))
+                .timeout(self.timeout)
+                .json(&serde_json::json!({ "model": model }))
+                .send()
+                .await
+            {
+                Ok(r) if r.status().is_success() => {
+                    response = Some(r);
+                    break;
+                }
+                Ok(_) => {}
+                Err(_) => endpoint.mark_healthy(false),
+            }
+        }
+
+        let response = response.ok_or_else(|| AiError::ModelNotFound {
+            provider: self.id(),
+            model: model.to_string(),
+        })?;
+
+        let show: ShowResponse = response
+            .json()
+            .await
+            .map_err(|e| AiError::StreamingError(format!("Failed to parse show response: {}", e)))?;
+
+        let profile = ModelProfile::from_show(model, show);
+        debug!(
+            "Discovered {}: context {:?}, {} {}, tools: {}",
+            model,
+            profile.context_length,
+            profile.details.parameter_size,
+            profile.details.quantization_level,
+            profile.supports_tools
+        );
+
+        self.profiles.write().unwrap().insert(model.to_string(), profile.clone());
+        Ok(profile)
     }
 
-    /// Get list of available models
-    async fn get_available_models(&self) -> Result<Vec<String>> {
+    /// Cached profile of the default model, if it was discovered already
+    fn cached_profile(&self) -> Option<ModelProfile> {
+        self.profiles.read().unwrap().get(&self.model).cloned()
+    }
+
+    /// Refresh health and model inventory of every endpoint
+    ///
+    /// Returns the number of endpoints that answered.
+    async fn refresh_endpoints(&self) -> usize {
+        let results = futures::future::join_all(self.pool.endpoints().iter().map(|endpoint| async move {
+            let healthy = self.fetch_tags(endpoint).await.is_ok() && self.fetch_ps(endpoint).await.is_ok();
+            endpoint.mark_healthy(healthy);
+            healthy
+        }))
+        .await;
+
+        results.into_iter().filter(|healthy| *healthy).count()
+    }
+
+    /// Get the models installed on any endpoint, with their details
+    pub async fn installed_models(&self) -> Result<Vec<ModelInfo>> {
+        let mut models: Vec<ModelInfo> = Vec::new();
+        let mut last_error = None;
+
+        for endpoint in self.pool.endpoints() {
+            match self.fetch_tags(endpoint).await {
+                Ok(installed) => {
+                    for model in installed {
+                        if !models.iter().any(|m| m.name == model.name) {
+                            models.push(model);
+                        }
+                    }
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        match last_error {
+            Some(e) if models.is_empty() => Err(e),
+            _ => Ok(models),
+        }
+    }
+
+    /// Get the models installed on one endpoint and update its inventory
+    async fn fetch_tags(&self, endpoint: &Endpoint) -> Result<Vec<ModelInfo>> {
         let response = self
             .client
-            .get(&format!("{}/api/models", self.endpoint))
+            .get(&format!("{}/api/tags", endpoint.url()))
+            .timeout(self.timeout)
             .send()
             .await
-            .map_err(|e| AiError::NetworkTimeout(self.id()))?;
+            .map_err(|_| AiError::NetworkTimeout(self.id()))?;
 
         if !response.status().is_success() {
             return Err(AiError::ServerError {
@@ -165,7 +401,13 @@ impl OllamaProvider {
             .await
             .map_err(|e| AiError::StreamingError(format!("Failed to parse models response: {}", e)))?;
 
-        Ok(models_response.into_iter().map(|m| m.name).collect())
+        endpoint.set_installed(models_response.models.iter().map(|m| m.name.clone()));
+        Ok(models_response.models)
+    }
+
+    /// Get list of available models
+    async fn get_available_models(&self) -> Result<Vec<String>> {
+        Ok(self.installed_models().await?.into_iter().map(|m| m.name).collect())
     }
 
     /// Check if the configured model is available
@@ -188,9 +430,16 @@ impl OllamaProvider {
             "name": self.model,
         });
 
+        let endpoint = self
+            .pool
+            .candidates(&self.model)
+            .into_iter()
+            .next()
+            .ok_or(AiError::ProviderNotAvailable(self.id()))?;
+
         let response = self
             .client
-            .post(&format!("{}/api/pull", self.endpoint))
+            .post(&format!("{}/api/pull", endpoint.url()))
             .json(&pull_request)
             .send()
             .await
@@ -222,6 +471,232 @@ impl OllamaProvider {
         Ok(())
     }
 
+    /// Load a model into memory so the first request does not pay for it
+    ///
+    /// The model is loaded on the endpoint that would serve it next.
+    #[instrument(skip(self))]
+    pub async fn preload_model(&self, model: &str) -> Result<()> {
+        info!("Preloading model {}", model);
+        self.send_generate(&keep_alive_request(model, self.keep_alive)).await?;
+        self.touch(model);
+
+        if let Err(e) = self.model_profile(model).await {
+            warn!("Could not discover capabilities of {}: {}", model, e);
+        }
+        Ok(())
+    }
+
+    /// Unload a model from every endpoint that has it loaded
+    #[instrument(skip(self))]
+    pub async fn unload_model(&self, model: &str) -> Result<()> {
+        info!("Unloading model {}", model);
+        let request = keep_alive_request(model, Some(0));
+
+        for endpoint in self.pool.endpoints().iter().filter(|e| e.has_loaded(model)) {
+            self.post_generate(endpoint, &request).await?;
+            endpoint.mark_unloaded(model);
+        }
+
+        self.last_used.lock().unwrap().remove(model);
+        Ok(())
+    }
+
+    /// List models currently loaded in memory on any endpoint
+    pub async fn loaded_models(&self) -> Result<Vec<LoadedModel>> {
+        let mut loaded = Vec::new();
+        let mut last_error = None;
+        let mut answered = false;
+
+        for endpoint in self.pool.endpoints() {
+            match self.fetch_ps(endpoint).await {
+                Ok(models) => {
+                    answered = true;
+                    loaded.extend(models);
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        match last_error {
+            Some(e) if !answered => Err(e),
+            _ => Ok(loaded),
+        }
+    }
+
+    /// Get the models loaded on one endpoint and update its inventory
+    async fn fetch_ps(&self, endpoint: &Endpoint) -> Result<Vec<LoadedModel>> {
+        let response = self
+            .client
+            .get(&format!("{}/api/ps", endpoint.url()))
+            .timeout(self.timeout)
+            .send()
+            .await
+            .map_err(|_| AiError::NetworkTimeout(self.id()))?;
+
+        if !response.status().is_success() {
+            return Err(AiError::ServerError {
+                provider: self.id(),
+                status: response.status().as_u16(),
+                message: "Failed to list loaded models".to_string(),
+            }.into());
+        }
+
+        let running: RunningModelsResponse = response
+            .json()
+            .await
+            .map_err(|e| AiError::StreamingError(format!("Failed to parse ps response: {}", e)))?;
+
+        let mut models = running.models;
+        for model in &mut models {
+            model.endpoint = endpoint.url().to_string();
+        }
+        endpoint.set_loaded(models.iter().map(|m| m.name.clone()));
+        Ok(models)
+    }
+
+    /// Unload models this provider used that have been idle for `idle_after`
+    ///
+    /// Models loaded by other clients of the same server are left alone.
+    #[instrument(skip(self))]
+    pub async fn unload_idle_models(&self, idle_after: Duration) -> Result<Vec<String>> {
+        let loaded = self.loaded_models().await?;
+        let mut idle: Vec<String> = {
+            let last_used = self.last_used.lock().unwrap();
+            loaded
+                .into_iter()
+                .filter(|m| last_used.get(&m.name).is_some_and(|t| t.elapsed() >= idle_after))
+                .map(|m| m.name)
+                .collect()
+        };
+        idle.sort_unstable();
+        idle.dedup();
+
+        for model in &idle {
+            self.unload_model(model).await?;
+        }
+
+        Ok(idle)
+    }
+
+    /// Generate the next turn of a conversation, reusing Ollama's context
+    ///
+    /// `history` holds the turns before `message`. While it matches what the
+    /// session's stored context encodes, only `message` is sent along with
+    /// that context, so Ollama does not evaluate the earlier turns again.
+    /// An edited history or a different model falls back to sending the
+    /// whole transcript and starts a new context.
+    ///
+    /// Callers go through `AiEngine::generate_in_session`, which screens
+    /// the conversation and records the call.
+    #[instrument(skip(self, history, message, options))]
+    pub(crate) async fn generate_in_session(
+        &self,
+        session: &str,
+        history: &[ChatMessage],
+        message: &str,
+        options: GenerationOptions,
+    ) -> Result<AiResponse> {
+        let started = Instant::now();
+        let mut request = self.build_request(message, &options, false);
+
+        match self.take_context(session, &request.model, history) {
+            Some(tokens) => request.context = Some(tokens),
+            None => request.prompt = render_transcript(history, message),
+        }
+        let context_tokens_reused = request.context.as_ref().map_or(0, Vec::len);
+
+        let (response, _in_flight) = self.request_with_retries(|| self.send_generate(&request)).await?;
+        let body: OllamaResponse = response
+            .json()
+            .await
+            .map_err(|e| AiError::StreamingError(format!("Failed to parse generate response: {}", e)))?;
+
+        if let Some(tokens) = body.context {
+            let turns = history
+                .iter()
+                .map(|m| (&m.role, m.content.as_str()))
+                .chain([(&MessageRole::User, message), (&MessageRole::Assistant, body.response.as_str())]);
+            self.store_context(session, &request.model, tokens, fingerprint(turns));
+        }
+
+        let prompt_tokens = body
+            .prompt_eval_count
+            .map(|c| c as usize)
+            .unwrap_or_else(|| estimate_tokens(&request.prompt));
+        let completion_tokens = body
+            .eval_count
+            .map(|c| c as usize)
+            .unwrap_or_else(|| estimate_tokens(&body.response));
+        debug!(
+            "Session {}: evaluated {} prompt tokens, reused {} context tokens",
+            session, prompt_tokens, context_tokens_reused
+        );
+
+        let usage = UsageStats {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+            context_tokens_reused,
+            ..UsageStats::default()
+        };
+        Ok(AiResponse::new(
+            self,
+            body.response,
+            request.model,
+            usage,
+            started.elapsed(),
+            body.done_reason,
+        ))
+    }
+
+    /// Forget the stored context of a session, e.g. after its history was edited
+    pub(crate) fn invalidate_session(&self, session: &str) {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner()).remove(session);
+    }
+
+    /// Stored context of a session, if it still encodes `history` for `model`
+    ///
+    /// A stale or expired context is dropped.
+    fn take_context(&self, session: &str, model: &str, history: &[ChatMessage]) -> Option<Vec<i32>> {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        let context = sessions.get(session)?;
+
+        let expected = fingerprint(history.iter().map(|m| (&m.role, m.content.as_str())));
+        if context.model == model && context.history == expected && context.used.elapsed() < SESSION_TTL {
+            return Some(context.tokens.clone());
+        }
+
+        debug!("History of session {} changed, rebuilding its context", session);
+        sessions.remove(session);
+        None
+    }
+
+    /// Keep the context of a session, dropping expired and surplus ones
+    fn store_context(&self, session: &str, model: &str, tokens: Vec<i32>, history: u64) {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        sessions.retain(|_, context| context.used.elapsed() < SESSION_TTL);
+        if !sessions.contains_key(session) && sessions.len() >= MAX_SESSIONS {
+            let oldest = sessions.iter().min_by_key(|(_, context)| context.used).map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                sessions.remove(&oldest);
+            }
+        }
+        sessions.insert(
+            session.to_string(),
+            SessionContext {
+                model: model.to_string(),
+                tokens,
+                history,
+                used: Instant::now(),
+            },
+        );
+    }
+
+    /// Record that a model was just used
+    fn touch(&self, model: &str) {
+        self.last_used.lock().unwrap().insert(model.to_string(), Instant::now());
+    }
+
     /// Convert generation options to Ollama format
     fn convert_options(&self, options: &GenerationOptions) -> OllamaOptions {
         OllamaOptions {
@@ -229,6 +704,7 @@ impl OllamaProvider {
             top_p: options.top_p,
             num_predict: options.max_tokens.map(|t| t as i32),
             stop: options.stop.clone(),
+            num_ctx: Some(self.max_content_length()),
         }
     }
 
@@ -256,4 +732,359 @@ impl OllamaProvider {
 
         Err(last_error.unwrap())
     }
-}
\ No newline at end of file
+
+    /// Build the request payload for `/api/generate`
+    fn build_request(&self, prompt: &str, options: &GenerationOptions, stream: bool) -> OllamaRequest {
+        let model = options.model.clone().unwrap_or_else(|| self.model.clone());
+        self.touch(&model);
+        OllamaRequest {
+            model,
+            prompt: prompt.to_string(),
+            stream,
+            options: Some(self.convert_options(options)),
+            keep_alive: self.keep_alive,
+            context: None,
+        }
+    }
+
+    /// Send a generation request, failing over between endpoints
+    ///
+    /// The returned guard keeps the request counted as outstanding on the
+    /// endpoint until the response has been consumed.
+    async fn send_generate(&self, request: &OllamaRequest) -> Result<(Response, InFlight)> {
+        let mut last_error = None;
+
+        for endpoint in self.pool.candidates(&request.model) {
+            let in_flight = EndpointPool::acquire(&endpoint);
+            match self.post_generate(&endpoint, request).await {
+                Ok(response) => {
+                    endpoint.mark_loaded(&request.model);
+                    return Ok((response, in_flight));
+                }
+                Err(e) => {
+                    warn!("Ollama endpoint {} failed: {}", endpoint.url(), e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| AiError::ProviderNotAvailable(self.id()).into()))
+    }
+
+    /// Send a generation request to one endpoint and check the response status
+    async fn post_generate(&self, endpoint: &Endpoint, request: &OllamaRequest) -> Result<Response> {
+        let response = match self
+            .client
+            .post(&format!("{}/api/generate", endpoint.url()))
+            .json(request)
+            .send()
+            .await
+        {
+            Ok(response) => response,
+            Err(_) => {
+                endpoint.mark_healthy(false);
+                return Err(AiError::NetworkTimeout(self.id()).into());
+            }
+        };
+        endpoint.mark_healthy(true);
+
+        if !response.status().is_success() {
+            let status = response.status().as_u16();
+            let message = response.text().await.unwrap_or_default();
+            return Err(AiError::ServerError {
+                provider: self.id(),
+                status,
+                message,
+            }.into());
+        }
+
+        Ok(response)
+    }
+}
+
+/// An empty generate request, which only (un)loads the model
+fn keep_alive_request(model: &str, keep_alive: Option<u64>) -> OllamaRequest {
+    OllamaRequest {
+        model: model.to_string(),
+        prompt: String::new(),
+        stream: false,
+        options: None,
+        keep_alive,
+        context: None,
+    }
+}
+
+/// Fingerprint of a conversation, to detect edited histories
+fn fingerprint<'a>(turns: impl IntoIterator<Item = (&'a MessageRole, &'a str)>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for (role, content) in turns {
+        let role: u8 = match role {
+            MessageRole::User => 0,
+            MessageRole::Assistant => 1,
+            MessageRole::System => 2,
+        };
+        role.hash(&mut hasher);
+        content.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Prompt replaying a whole conversation when no context can be reused
+pub(crate) fn render_transcript(history: &[ChatMessage], message: &str) -> String {
+    if history.is_empty() {
+        return message.to_string();
+    }
+
+    let mut transcript = String::new();
+    for turn in history {
+        let role = match turn.role {
+            MessageRole::User => "User",
+            MessageRole::Assistant => "Assistant",
+            MessageRole::System => "System",
+        };
+        transcript.push_str(&format!("{}: {}\n\n", role, turn.content));
+    }
+    transcript.push_str(&format!("User: {}\n\nAssistant:", message));
+    transcript
+}
+
+/// Parse one line of Ollama's newline-delimited JSON stream
+fn parse_stream_line(line: &str) -> Option<Result<String>> {
+    let line = line.trim();
+    if line.is_empty() {
+        return None;
+    }
+
+    match serde_json::from_str::<OllamaResponse>(line) {
+        Ok(response) if response.response.is_empty() => None,
+        Ok(response) => Some(Ok(response.response)),
+        Err(e) => Some(Err(AiError::StreamingError(format!("Invalid stream chunk: {}", e)).into())),
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OllamaProvider {
+    fn id(&self) -> ProviderId {
+        self.id.clone()
+    }
+
+    fn name(&self) -> &str {
+        "ollama"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn is_available(&self) -> bool {
+        self.available.load(Ordering::Relaxed)
+    }
+
+    #[instrument(skip(self))]
+    async fn health_check(&self) -> Result<HealthStatus> {
+        let total = self.pool.endpoints().len();
+        let reachable = self.refresh_endpoints().await;
+        let has_model = self
+            .pool
+            .endpoints()
+            .iter()
+            .any(|e| e.is_healthy() && e.has_installed(&self.model));
+
+        let status = if reachable == 0 {
+            let urls: Vec<&str> = self.pool.endpoints().iter().map(|e| e.url()).collect();
+            HealthStatus::Unhealthy {
+                error: format!("Ollama is not reachable at {}", urls.join(", ")),
+            }
+        } else if !has_model {
+            HealthStatus::Degraded {
+                reason: format!("Model {} is not installed", self.model),
+            }
+        } else {
+            if let Err(e) = self.model_profile(&self.model).await {
+                warn!("Could not discover capabilities of {}: {}", self.model, e);
+            }
+
+            if reachable < total {
+                HealthStatus::Degraded {
+                    reason: format!("{} of {} endpoints reachable", reachable, total),
+                }
+            } else {
+                HealthStatus::Healthy
+            }
+        };
+
+        self.available.store(status.is_available(), Ordering::Relaxed);
+        Ok(status)
+    }
+
+    #[instrument(skip(self, prompt, options))]
+    async fn stream_generate(
+        &self,
+        prompt: &str,
+        options: &GenerationOptions,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<String>> + Send>>> {
+        let request = self.build_request(prompt, options, true);
+        let (response, in_flight) = self.request_with_retries(|| self.send_generate(&request)).await?;
+
+        // Chunks do not necessarily end on a line boundary, so buffer until a
+        // full JSON object is available. The in-flight guard lives as long as
+        // the stream.
+        let stream = response
+            .bytes_stream()
+            .scan((Vec::new(), in_flight), |(buffer, _in_flight), chunk| {
+                let items: Vec<Result<String>> = match chunk {
+                    Ok(bytes) => {
+                        buffer.extend_from_slice(&bytes);
+                        drain_lines(buffer).iter().filter_map(|line| parse_stream_line(line)).collect()
+                    }
+                    Err(e) => vec![Err(CodevError::from(e))],
+                };
+                futures::future::ready(Some(futures::stream::iter(items)))
+            })
+            .flatten();
+
+        Ok(Box::pin(stream))
+    }
+
+    #[instrument(skip(self, prompt, options))]
+    async fn generate(&self, prompt: &str, options: GenerationOptions) -> Result<String> {
+        let request = self.build_request(prompt, &options, false);
+        let started = Instant::now();
+
+        let (response, _in_flight) = self.request_with_retries(|| self.send_generate(&request)).await?;
+        let body: OllamaResponse = response
+            .json()
+            .await
+            .map_err(|e| AiError::StreamingError(format!("Failed to parse generate response: {}", e)))?;
+
+        debug!(
+            "Ollama generated {} tokens in {:?}",
+            body.eval_count.unwrap_or_default(),
+            started.elapsed()
+        );
+        Ok(body.response)
+    }
+
+    /// Configured context length, capped to what the model supports
+    fn max_content_length(&self) -> usize {
+        match self.cached_profile().and_then(|p| p.context_length) {
+            Some(model_max) => self.context_length.min(model_max),
+            None => self.context_length,
+        }
+    }
+
+    fn cost_per_token(&self) -> f64 {
+        // Local inference has no per-token cost
+        0.0
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            function_calling: self.cached_profile().is_some_and(|p| p.supports_tools),
+            max_context_length: self.max_content_length(),
+            ..ProviderCapabilities::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn show(json: serde_json::Value) -> ShowResponse {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_profile_reads_architecture_context_length() {
+        let profile = ModelProfile::from_show("qwen2.5-coder:7b", show(serde_json::json!({
+            "template": "{{ .System }}",
+            "details": { "parameter_size": "7.6B", "quantization_level": "Q4_K_M" },
+            "model_info": {
+                "general.architecture": "qwen2",
+                "qwen2.context_length": 32768
+            },
+            "capabilities": ["completion", "tools"]
+        })));
+
+        assert_eq!(profile.context_length, Some(32768));
+        assert_eq!(profile.details.parameter_size, "7.6B");
+        assert_eq!(profile.details.quantization_level, "Q4_K_M");
+        assert!(profile.supports_tools);
+    }
+
+    #[test]
+    fn test_profile_tool_support_from_template() {
+        let profile = ModelProfile::from_show("llama3.1:8b", show(serde_json::json!({
+            "template": "{{ if .Tools }}tools{{ end }}",
+            "model_info": { "llama.context_length": 131072 }
+        })));
+
+        assert_eq!(profile.context_length, Some(131072));
+        assert!(profile.supports_tools);
+    }
+
+    fn turn(role: MessageRole, content: &str) -> ChatMessage {
+        ChatMessage {
+            role,
+            content: content.to_string(),
+            timestamp: chrono::Utc::now(),
+            metadata: None,
+        }
+    }
+
+    fn conversation_fingerprint(history: &[ChatMessage]) -> u64 {
+        fingerprint(history.iter().map(|m| (&m.role, m.content.as_str())))
+    }
+
+    #[test]
+    fn test_session_context_reused_until_history_changes() {
+        let provider = OllamaProvider::new("http://localhost:11434".to_string(), "llama2:7b".to_string()).unwrap();
+        let mut history = vec![turn(MessageRole::User, "hi"), turn(MessageRole::Assistant, "hello")];
+        provider.store_context("s1", "llama2:7b", vec![1, 2, 3], conversation_fingerprint(&history));
+
+        assert_eq!(provider.take_context("s1", "llama2:7b", &history), Some(vec![1, 2, 3]));
+        assert_eq!(provider.take_context("s1", "codellama:7b", &history), None);
+
+        provider.store_context("s1", "llama2:7b", vec![1, 2, 3], conversation_fingerprint(&history));
+        history[1].content = "edited".to_string();
+        assert_eq!(provider.take_context("s1", "llama2:7b", &history), None);
+        assert!(provider.sessions.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_drain_lines_keeps_characters_split_across_chunks() {
+        let line = "{\"response\":\"café\"}\n".as_bytes();
+        let split = line.iter().position(|&b| b == 0xc3).unwrap() + 1;
+
+        let mut buffer = line[..split].to_vec();
+        assert!(drain_lines(&mut buffer).is_empty());
+        buffer.extend_from_slice(&line[split..]);
+        let lines = drain_lines(&mut buffer);
+
+        assert_eq!(lines.len(), 1);
+        assert_eq!(parse_stream_line(&lines[0]).unwrap().unwrap(), "café");
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_session_contexts_are_capped() {
+        let provider = OllamaProvider::new("http://localhost:11434".to_string(), "llama2:7b".to_string()).unwrap();
+        for session in 0..=MAX_SESSIONS {
+            provider.store_context(&session.to_string(), "llama2:7b", vec![1], 0);
+        }
+
+        let sessions = provider.sessions.lock().unwrap();
+        assert_eq!(sessions.len(), MAX_SESSIONS);
+        assert!(sessions.contains_key(&MAX_SESSIONS.to_string()));
+    }
+
+    #[test]
+    fn test_render_transcript() {
+        assert_eq!(render_transcript(&[], "hi"), "hi");
+        assert_eq!(
+            render_transcript(&[turn(MessageRole::User, "hi"), turn(MessageRole::Assistant, "hello")], "bye"),
+            "User: hi\n\nAssistant: hello\n\nUser: bye\n\nAssistant:"
+        );
+    }
+}
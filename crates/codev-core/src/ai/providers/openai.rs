@@ -0,0 +1,388 @@
+//! OpenAI-compatible Provider Implementation
+//!
+//! Talks to the `/chat/completions` API, which OpenAI, Mistral, Gemini and
+//! most self-hosted gateways (vLLM, LM Studio, LiteLLM) implement.
+
+use crate::ai::providers::sse;
+use crate::ai::{
+    AiError, AiResponse, CachePricing, GenerationOptions, HealthStatus, LlmProvider, Prompt, ProviderCapabilities,
+    SegmentRole, UsageStats,
+};
+use async_trait::async_trait;
+use codev_shared::{ProviderId, Result};
+use futures::{Stream, StreamExt};
+use reqwest::{Client, RequestBuilder, Response};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tracing::{debug, instrument};
+
+/// Provider for any OpenAI-compatible chat completions API
+pub struct OpenAiCompatibleProvider {
+    id: ProviderId,
+    client: Client,
+    endpoint: String,
+    api_key: Option<SecretString>,
+    model: String,
+    available: AtomicBool,
+    cost_per_token: f64,
+    context_length: usize,
+    timeout: Duration,
+}
+
+/// Request payload for `/chat/completions`
+#[derive(Serialize, Debug)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: Vec<ChatMessage<'a>>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop: Option<&'a [String]>,
+}
+
+#[derive(Serialize, Debug)]
+struct ChatMessage<'a> {
+    role: &'a str,
+    content: String,
+}
+
+/// Complete response from `/chat/completions`
+#[derive(Deserialize, Debug)]
+struct ChatResponse {
+    choices: Vec<Choice>,
+    #[serde(default)]
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Usage {
+    prompt_tokens: usize,
+    completion_tokens: usize,
+    #[serde(default)]
+    prompt_tokens_details: Option<PromptTokensDetails>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PromptTokensDetails {
+    #[serde(default)]
+    cached_tokens: usize,
+}
+
+#[derive(Deserialize, Debug)]
+struct Choice {
+    #[serde(default)]
+    message: Option<ResponseMessage>,
+    #[serde(default)]
+    delta: Option<ResponseMessage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ResponseMessage {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+impl OpenAiCompatibleProvider {
+    /// Create a provider for `endpoint`, the API base URL ending in `/v1`
+    pub fn new(id: ProviderId, client: Client, endpoint: String, model: String, api_key: Option<SecretString>) -> Self {
+        Self {
+            id,
+            client,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            api_key,
+            model,
+            available: AtomicBool::new(true),
+            cost_per_token: 0.0,
+            context_length: ProviderCapabilities::default().max_context_length,
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Bound control requests such as health checks; generation is bounded
+    /// by the client's read timeout
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// API base URL of a built-in provider
+    pub fn default_endpoint(id: &ProviderId) -> Option<&'static str> {
+        match id.as_str() {
+            "openai" => Some("https://api.openai.com/v1"),
+            "mistral" => Some("https://api.mistral.ai/v1"),
+            "gemini" => Some("https://generativelanguage.googleapis.com/v1beta/openai"),
+            _ => None,
+        }
+    }
+
+    pub fn with_cost_per_token(mut self, cost_per_token: f64) -> Self {
+        self.cost_per_token = cost_per_token;
+        self
+    }
+
+    pub fn with_context_length(mut self, context_length: usize) -> Self {
+        self.context_length = context_length;
+        self
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match &self.api_key {
+            Some(key) => request.bearer_auth(key.expose_secret()),
+            None => request,
+        }
+    }
+
+    async fn send_chat(
+        &self,
+        messages: Vec<ChatMessage<'_>>,
+        options: &GenerationOptions,
+        stream: bool,
+    ) -> Result<Response> {
+        let request = ChatRequest {
+            model: options.model.as_deref().unwrap_or(&self.model),
+            messages,
+            stream,
+            max_tokens: options.max_tokens,
+            temperature: options.temperature,
+            top_p: options.top_p,
+            frequency_penalty: options.frequency_penalty,
+            presence_penalty: options.presence_penalty,
+            stop: options.stop.as_deref(),
+        };
+
+        let response = self
+            .authorize(self.client.post(format!("{}/chat/completions", self.endpoint)))
+            .json(&request)
+            .send()
+            .await
+            .map_err(|_| AiError::NetworkTimeout(self.id.clone()))?;
+
+        check_status(&self.id, response).await
+    }
+}
+
+/// Chat messages for a single-turn prompt
+fn user_message(prompt: &str) -> Vec<ChatMessage<'static>> {
+    vec![ChatMessage {
+        role: "user",
+        content: prompt.to_string(),
+    }]
+}
+
+/// Chat messages for an assembled prompt
+///
+/// OpenAI caches long prompt prefixes automatically, so rather than sending
+/// markers the stable segments are kept first and byte-identical: all system
+/// segments form the system message, user segments follow in order.
+fn prompt_messages(prompt: &Prompt) -> Vec<ChatMessage<'static>> {
+    [("system", SegmentRole::System), ("user", SegmentRole::User)]
+        .into_iter()
+        .filter_map(|(role, segment_role)| {
+            let text = prompt.text_of(segment_role);
+            (!text.is_empty()).then(|| ChatMessage {
+                role,
+                content: text.join("\n\n"),
+            })
+        })
+        .collect()
+}
+
+/// Map HTTP error statuses to `AiError`
+pub(crate) async fn check_status(id: &ProviderId, response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    Err(match status.as_u16() {
+        401 | 403 => AiError::InvalidApiKey(id.clone()),
+        429 => AiError::RateLimited(id.clone()),
+        code => AiError::ServerError {
+            provider: id.clone(),
+            status: code,
+            message: response.text().await.unwrap_or_default(),
+        },
+    }
+    .into())
+}
+
+#[async_trait]
+impl LlmProvider for OpenAiCompatibleProvider {
+    fn id(&self) -> ProviderId {
+        self.id.clone()
+    }
+
+    fn name(&self) -> &str {
+        "openai-compatible"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn is_available(&self) -> bool {
+        self.available.load(Ordering::Relaxed)
+    }
+
+    #[instrument(skip(self))]
+    async fn health_check(&self) -> Result<HealthStatus> {
+        let status = match self
+            .authorize(self.client.get(format!("{}/models", self.endpoint)))
+            .timeout(self.timeout)
+            .send()
+            .await
+        {
+            Ok(response) => match check_status(&self.id, response).await {
+                Ok(_) => HealthStatus::Healthy,
+                Err(e) => HealthStatus::Unhealthy { error: e.to_string() },
+            },
+            Err(e) => HealthStatus::Unhealthy {
+                error: format!("{} is not reachable: {}", self.endpoint, e),
+            },
+        };
+
+        self.available.store(status.is_available(), Ordering::Relaxed);
+        Ok(status)
+    }
+
+    #[instrument(skip(self, prompt, options))]
+    async fn stream_generate(
+        &self,
+        prompt: &str,
+        options: &GenerationOptions,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<String>> + Send>>> {
+        let response = self.send_chat(user_message(prompt), options, true).await?;
+
+        let stream = sse::data_lines(response).filter_map(|data| {
+            let text = data.and_then(|data| sse::parse_event::<ChatResponse>(&data)).map(|event| {
+                event
+                    .choices
+                    .into_iter()
+                    .filter_map(|choice| choice.delta.and_then(|d| d.content))
+                    .collect::<String>()
+            });
+            futures::future::ready(match text {
+                Ok(text) if text.is_empty() => None,
+                other => Some(other),
+            })
+        });
+
+        Ok(Box::pin(stream))
+    }
+
+    #[instrument(skip(self, prompt, options))]
+    async fn generate(&self, prompt: &str, options: GenerationOptions) -> Result<String> {
+        let response = self.send_chat(user_message(prompt), &options, false).await?;
+        let body: ChatResponse = response
+            .json()
+            .await
+            .map_err(|e| AiError::StreamingError(format!("Failed to parse chat response: {}", e)))?;
+
+        debug!("{} answered with {} choices", self.id, body.choices.len());
+        Ok(body
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.message.and_then(|m| m.content))
+            .unwrap_or_default())
+    }
+
+    fn max_content_length(&self) -> usize {
+        self.context_length
+    }
+
+    fn cost_per_token(&self) -> f64 {
+        self.cost_per_token
+    }
+
+    fn cache_pricing(&self) -> CachePricing {
+        // Cached input is billed at half price
+        CachePricing { read: 0.5, write: 1.0 }
+    }
+
+    #[instrument(skip(self, prompt, options))]
+    async fn generate_prompt(&self, prompt: &Prompt, options: GenerationOptions) -> Result<AiResponse> {
+        let started = Instant::now();
+        let model = options.model.clone().unwrap_or_else(|| self.model.clone());
+        let response = self.send_chat(prompt_messages(prompt), &options, false).await?;
+        let body: ChatResponse = response
+            .json()
+            .await
+            .map_err(|e| AiError::StreamingError(format!("Failed to parse chat response: {}", e)))?;
+
+        let usage = body
+            .usage
+            .map(|usage| UsageStats {
+                prompt_tokens: usage.prompt_tokens,
+                completion_tokens: usage.completion_tokens,
+                total_tokens: usage.prompt_tokens + usage.completion_tokens,
+                cached_prompt_tokens: usage.prompt_tokens_details.map_or(0, |d| d.cached_tokens),
+                ..UsageStats::default()
+            })
+            .unwrap_or_default();
+        debug!("{} served {} cached prompt tokens", self.id, usage.cached_prompt_tokens);
+
+        let content = body
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.message.and_then(|m| m.content))
+            .unwrap_or_default();
+        Ok(AiResponse::new(self, content, model, usage, started.elapsed(), None))
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            function_calling: true,
+            max_context_length: self.context_length,
+            ..ProviderCapabilities::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prompt_messages_keep_stable_prefix_first() {
+        let prompt = Prompt::new()
+            .system("Be concise")
+            .user("Project context")
+            .cache_breakpoint()
+            .user("Explain main.rs");
+
+        let messages = prompt_messages(&prompt);
+        assert_eq!(messages.len(), 2);
+        assert_eq!((messages[0].role, messages[0].content.as_str()), ("system", "Be concise"));
+        assert_eq!(messages[1].content, "Project context\n\nExplain main.rs");
+    }
+
+    #[test]
+    fn test_parse_cached_usage() {
+        let body: ChatResponse = serde_json::from_str(
+            r#"{"choices":[],"usage":{"prompt_tokens":2006,"completion_tokens":300,"prompt_tokens_details":{"cached_tokens":1920}}}"#,
+        )
+        .unwrap();
+        assert_eq!(body.usage.unwrap().prompt_tokens_details.unwrap().cached_tokens, 1920);
+    }
+
+    #[test]
+    fn test_parse_stream_delta() {
+        let event: ChatResponse =
+            sse::parse_event(r#"{"choices":[{"index":0,"delta":{"content":"fn main"}}]}"#).unwrap();
+        assert_eq!(event.choices[0].delta.as_ref().unwrap().content.as_deref(), Some("fn main"));
+    }
+}
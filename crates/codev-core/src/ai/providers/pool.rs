@@ -0,0 +1,189 @@
+//! Endpoint Pool
+//!
+//! Spreads the requests of one provider over several servers. Every endpoint
+//! tracks which models it has installed and loaded, whether it answered the
+//! last time it was contacted, and how many requests it is serving.
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// One server of a pool
+#[derive(Debug)]
+pub struct Endpoint {
+    url: String,
+    healthy: AtomicBool,
+    outstanding: AtomicUsize,
+    installed: RwLock<HashSet<String>>,
+    loaded: RwLock<HashSet<String>>,
+}
+
+impl Endpoint {
+    fn new(url: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            healthy: AtomicBool::new(true),
+            outstanding: AtomicUsize::new(0),
+            installed: RwLock::new(HashSet::new()),
+            loaded: RwLock::new(HashSet::new()),
+        }
+    }
+
+    /// Base URL without trailing slash
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Whether the endpoint answered the last time it was contacted
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    /// Number of requests currently in flight
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.load(Ordering::Relaxed)
+    }
+
+    pub fn has_installed(&self, model: &str) -> bool {
+        self.installed.read().unwrap().contains(model)
+    }
+
+    pub fn has_loaded(&self, model: &str) -> bool {
+        self.loaded.read().unwrap().contains(model)
+    }
+
+    pub fn mark_healthy(&self, healthy: bool) {
+        self.healthy.store(healthy, Ordering::Relaxed);
+    }
+
+    /// Replace the list of installed models
+    pub fn set_installed(&self, models: impl IntoIterator<Item = String>) {
+        *self.installed.write().unwrap() = models.into_iter().collect();
+    }
+
+    /// Replace the list of loaded models
+    pub fn set_loaded(&self, models: impl IntoIterator<Item = String>) {
+        *self.loaded.write().unwrap() = models.into_iter().collect();
+    }
+
+    /// Record that a model was loaded by a request
+    pub fn mark_loaded(&self, model: &str) {
+        self.installed.write().unwrap().insert(model.to_string());
+        self.loaded.write().unwrap().insert(model.to_string());
+    }
+
+    /// Record that a model was unloaded
+    pub fn mark_unloaded(&self, model: &str) {
+        self.loaded.write().unwrap().remove(model);
+    }
+}
+
+/// Counts a request as in flight on an endpoint until dropped
+#[derive(Debug)]
+pub struct InFlight {
+    endpoint: Arc<Endpoint>,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.endpoint.outstanding.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Set of endpoints serving the same provider
+#[derive(Debug)]
+pub struct EndpointPool {
+    endpoints: Vec<Arc<Endpoint>>,
+}
+
+impl EndpointPool {
+    /// Create a pool, ignoring duplicate URLs
+    pub fn new<I, S>(urls: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut endpoints: Vec<Arc<Endpoint>> = Vec::new();
+        for url in urls {
+            let endpoint = Endpoint::new(url.as_ref());
+            if !endpoints.iter().any(|e| e.url == endpoint.url) {
+                endpoints.push(Arc::new(endpoint));
+            }
+        }
+        Self { endpoints }
+    }
+
+    pub fn endpoints(&self) -> &[Arc<Endpoint>] {
+        &self.endpoints
+    }
+
+    /// Endpoints to try for a model, best first
+    ///
+    /// Healthy endpoints come first, preferring those that already have the
+    /// model loaded, then installed, then the fewest outstanding requests.
+    /// Unhealthy endpoints stay at the end so they are retried when all
+    /// others fail.
+    pub fn candidates(&self, model: &str) -> Vec<Arc<Endpoint>> {
+        let mut candidates = self.endpoints.clone();
+        candidates.sort_by_key(|e| {
+            (
+                !e.is_healthy(),
+                !e.has_loaded(model),
+                !e.has_installed(model),
+                e.outstanding(),
+            )
+        });
+        candidates
+    }
+
+    /// Count a request as in flight on `endpoint`
+    pub fn acquire(endpoint: &Arc<Endpoint>) -> InFlight {
+        endpoint.outstanding.fetch_add(1, Ordering::Relaxed);
+        InFlight { endpoint: endpoint.clone() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(candidates: &[Arc<Endpoint>]) -> Vec<&str> {
+        candidates.iter().map(|e| e.url()).collect()
+    }
+
+    #[test]
+    fn test_prefers_endpoint_with_model_loaded() {
+        let pool = EndpointPool::new(["http://a:11434", "http://b:11434/", "http://c:11434"]);
+        pool.endpoints()[1].set_installed(["codellama:7b".to_string()]);
+        pool.endpoints()[2].mark_loaded("codellama:7b");
+
+        assert_eq!(
+            urls(&pool.candidates("codellama:7b")),
+            ["http://c:11434", "http://b:11434", "http://a:11434"]
+        );
+    }
+
+    #[test]
+    fn test_least_outstanding_and_unhealthy_last() {
+        let pool = EndpointPool::new(["http://a:11434", "http://b:11434", "http://c:11434"]);
+        for endpoint in pool.endpoints() {
+            endpoint.mark_loaded("llama2:7b");
+        }
+        pool.endpoints()[2].mark_healthy(false);
+
+        let busy = EndpointPool::acquire(&pool.endpoints()[0]);
+        assert_eq!(
+            urls(&pool.candidates("llama2:7b")),
+            ["http://b:11434", "http://a:11434", "http://c:11434"]
+        );
+
+        drop(busy);
+        assert_eq!(pool.endpoints()[0].outstanding(), 0);
+    }
+
+    #[test]
+    fn test_duplicate_urls_are_ignored() {
+        let pool = EndpointPool::new(["http://a:11434", "http://a:11434/"]);
+        assert_eq!(pool.endpoints().len(), 1);
+    }
+}
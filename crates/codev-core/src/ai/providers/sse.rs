@@ -0,0 +1,83 @@
+//! Server-Sent Events
+//!
+//! OpenAI-compatible and Anthropic APIs stream responses as SSE. This only
+//! extracts the `data:` payloads; each provider parses its own event format.
+
+use crate::ai::AiError;
+use codev_shared::{CodevError, Result};
+use futures::{Stream, StreamExt};
+use reqwest::Response;
+
+/// Payloads of the `data:` lines of an SSE response, up to `[DONE]`
+pub(crate) fn data_lines(response: Response) -> impl Stream<Item = Result<String>> + Send {
+    // Events do not necessarily end on a chunk boundary, so buffer until a
+    // full line is available.
+    response
+        .bytes_stream()
+        .scan(Vec::new(), |buffer, chunk| {
+            let items: Vec<Result<String>> = match chunk {
+                Ok(bytes) => {
+                    buffer.extend_from_slice(&bytes);
+                    drain_lines(buffer).iter().filter_map(|line| parse_data_line(line)).map(Ok).collect()
+                }
+                Err(e) => vec![Err(CodevError::from(e))],
+            };
+            futures::future::ready(Some(futures::stream::iter(items)))
+        })
+        .flatten()
+}
+
+/// Complete lines at the start of a byte buffer, removed from it
+///
+/// Lines are split on raw bytes and decoded whole, so a multi-byte character
+/// split across two chunks is not mangled.
+pub(crate) fn drain_lines(buffer: &mut Vec<u8>) -> Vec<String> {
+    let mut lines = Vec::new();
+    while let Some(pos) = buffer.iter().position(|&b| b == b'\n') {
+        let line: Vec<u8> = buffer.drain(..=pos).collect();
+        lines.push(String::from_utf8_lossy(&line).into_owned());
+    }
+    lines
+}
+
+/// Payload of one SSE line, if it is a data line other than `[DONE]`
+fn parse_data_line(line: &str) -> Option<String> {
+    let data = line.trim_end().strip_prefix("data:")?.trim_start();
+    if data.is_empty() || data == "[DONE]" {
+        return None;
+    }
+    Some(data.to_string())
+}
+
+/// Parse a data payload as JSON
+pub(crate) fn parse_event<T: serde::de::DeserializeOwned>(data: &str) -> Result<T> {
+    serde_json::from_str(data)
+        .map_err(|e| AiError::StreamingError(format!("Invalid stream event: {}", e)).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_data_line() {
+        assert_eq!(parse_data_line("data: {\"a\":1}\n"), Some("{\"a\":1}".to_string()));
+        assert_eq!(parse_data_line("data:[DONE]\n"), None);
+        assert_eq!(parse_data_line("event: message_start\n"), None);
+        assert_eq!(parse_data_line("\n"), None);
+    }
+
+    #[test]
+    fn test_drain_lines_keeps_characters_split_across_chunks() {
+        let event = "data: {\"text\":\"naïve 日本\"}\n\n".as_bytes();
+        let split = event.iter().position(|&b| b == 0xe6).unwrap() + 2;
+
+        let mut buffer = event[..split].to_vec();
+        assert!(drain_lines(&mut buffer).is_empty());
+        buffer.extend_from_slice(&event[split..]);
+        let data: Vec<String> = drain_lines(&mut buffer).iter().filter_map(|line| parse_data_line(line)).collect();
+
+        assert_eq!(data, ["{\"text\":\"naïve 日本\"}"]);
+        assert!(buffer.is_empty());
+    }
+}
@@ -0,0 +1,105 @@
+//! HTTP Transport
+//!
+//! Builds the single `reqwest::Client` shared by every provider, so proxy,
+//! TLS and timeout settings apply uniformly and connections are pooled.
+
+use codev_shared::{ConfigError, HttpConfig, Result};
+use reqwest::{Certificate, Client, Identity, NoProxy, Proxy};
+use std::path::Path;
+use std::time::Duration;
+
+/// Build the HTTP client described by the configuration
+///
+/// Invalid settings are reported as configuration errors naming the key.
+pub fn build_client(config: &HttpConfig) -> Result<Client> {
+    let mut builder = Client::builder()
+        .connect_timeout(Duration::from_secs(config.connect_timeout_seconds))
+        .read_timeout(Duration::from_secs(config.read_timeout_seconds));
+
+    // Without an explicit proxy reqwest uses HTTP(S)_PROXY and NO_PROXY
+    if let Some(url) = &config.proxy {
+        let no_proxy = match &config.no_proxy {
+            Some(hosts) => NoProxy::from_string(hosts),
+            None => NoProxy::from_env(),
+        };
+        let proxy = Proxy::all(url)
+            .map_err(|e| invalid("ai.http.proxy", url, e))?
+            .no_proxy(no_proxy);
+        builder = builder.proxy(proxy);
+    }
+
+    if let Some(path) = &config.ca_bundle {
+        let pem = read_pem("ai.http.ca_bundle", path)?;
+        let certificates =
+            Certificate::from_pem_bundle(&pem).map_err(|e| invalid("ai.http.ca_bundle", &path.display(), e))?;
+        for certificate in certificates {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    match (&config.client_cert, &config.client_key) {
+        (Some(cert), Some(key)) => {
+            let mut pem = read_pem("ai.http.client_cert", cert)?;
+            pem.extend(read_pem("ai.http.client_key", key)?);
+            let identity = Identity::from_pem(&pem).map_err(|e| invalid("ai.http.client_cert", &cert.display(), e))?;
+            builder = builder.identity(identity);
+        }
+        (Some(_), None) => return Err(missing("ai.http.client_key")),
+        (None, Some(_)) => return Err(missing("ai.http.client_cert")),
+        (None, None) => {}
+    }
+
+    builder
+        .build()
+        .map_err(|e| ConfigError::InvalidFormat {
+            message: format!("cannot build HTTP client: {}", e),
+        }
+        .into())
+}
+
+fn read_pem(key: &str, path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|_| {
+        ConfigError::FileNotFound {
+            path: format!("{} ({})", path.display(), key),
+        }
+        .into()
+    })
+}
+
+fn invalid(key: &str, value: &dyn std::fmt::Display, error: reqwest::Error) -> codev_shared::CodevError {
+    ConfigError::InvalidValue {
+        key: key.to_string(),
+        value: format!("{} ({})", value, error),
+    }
+    .into()
+}
+
+fn missing(key: &str) -> codev_shared::CodevError {
+    ConfigError::MissingRequired { key: key.to_string() }.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codev_shared::CodevError;
+
+    #[test]
+    fn test_default_config_builds() {
+        assert!(build_client(&HttpConfig::default()).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_settings_are_config_errors() {
+        let config = HttpConfig {
+            proxy: Some("not a url".to_string()),
+            ..HttpConfig::default()
+        };
+        assert!(matches!(build_client(&config), Err(CodevError::Config { message }) if message.contains("ai.http.proxy")));
+
+        let config = HttpConfig {
+            client_cert: Some("/nonexistent/cert.pem".into()),
+            ..HttpConfig::default()
+        };
+        assert!(matches!(build_client(&config), Err(CodevError::Config { message }) if message.contains("client_key")));
+    }
+}
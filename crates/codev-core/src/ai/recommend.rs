@@ -0,0 +1,299 @@
+//! Hardware-aware Model Recommendation
+//!
+//! Estimates the memory and disk footprint of candidate Ollama models from
+//! their parameter size and quantization, and proposes the largest model
+//! that fits this machine for each task type.
+
+use crate::ai::providers::{ModelDetails, ModelInfo};
+use crate::ai::TaskType;
+use codev_shared::OllamaModels;
+use std::cmp::Ordering;
+use std::path::Path;
+use sysinfo::{Disks, System};
+
+/// Share of total memory a model may take, leaving room for the OS and editor
+const MEMORY_BUDGET_RATIO: f64 = 0.75;
+
+/// Memory needed on top of the weights for the KV cache and runtime
+const RUNTIME_OVERHEAD: f64 = 1.2;
+
+/// Billions of parameters per CPU core that still generate at a usable speed
+const PARAMS_PER_CORE: f64 = 2.0;
+
+/// Resources available on this machine
+#[derive(Debug, Clone)]
+pub struct HardwareProfile {
+    /// Total RAM in bytes
+    pub total_memory: u64,
+
+    /// Currently available RAM in bytes
+    pub available_memory: u64,
+
+    /// Number of logical CPU cores
+    pub cpu_cores: usize,
+
+    /// Free disk space where models are stored, in bytes
+    pub free_disk: u64,
+}
+
+impl HardwareProfile {
+    /// Detect resources, measuring free disk space for `models_dir`
+    pub fn detect(models_dir: &Path) -> Self {
+        let mut system = System::new();
+        system.refresh_memory();
+
+        // The disk holding the models is the one with the longest mount point
+        // that is a prefix of the models directory.
+        let disks = Disks::new_with_refreshed_list();
+        let free_disk = disks
+            .iter()
+            .filter(|disk| models_dir.starts_with(disk.mount_point()))
+            .max_by_key(|disk| disk.mount_point().as_os_str().len())
+            .map(|disk| disk.available_space())
+            .unwrap_or(0);
+
+        Self {
+            total_memory: system.total_memory(),
+            available_memory: system.available_memory(),
+            cpu_cores: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            free_disk,
+        }
+    }
+
+    fn memory_budget(&self) -> u64 {
+        (self.total_memory as f64 * MEMORY_BUDGET_RATIO) as u64
+    }
+
+    fn max_parameters(&self) -> f64 {
+        self.cpu_cores as f64 * PARAMS_PER_CORE
+    }
+}
+
+/// A model that could serve a task
+#[derive(Debug, Clone)]
+pub struct ModelCandidate {
+    pub name: String,
+    pub details: ModelDetails,
+
+    /// Size on disk when the model is already installed
+    pub installed_size: Option<u64>,
+}
+
+impl ModelCandidate {
+    /// Well-known models that can be pulled, as used by `scripts/ollama-init.sh`
+    pub fn catalog() -> Vec<Self> {
+        [
+            ("codellama:7b", "7B"),
+            ("codellama:13b", "13B"),
+            ("llama2:7b", "7B"),
+            ("deepseek-coder:6.7b", "6.7B"),
+            ("phi:latest", "2.7B"),
+            ("gemma:7b", "7B"),
+        ]
+        .into_iter()
+        .map(|(name, parameter_size)| Self {
+            name: name.to_string(),
+            details: ModelDetails {
+                parameter_size: parameter_size.to_string(),
+                quantization_level: "Q4_0".to_string(), // Default Ollama tags
+                ..ModelDetails::default()
+            },
+            installed_size: None,
+        })
+        .collect()
+    }
+
+    /// The catalog plus installed models, installed entries taking precedence
+    pub fn with_installed(installed: Vec<ModelInfo>) -> Vec<Self> {
+        let mut candidates = Self::catalog();
+        candidates.retain(|c| !installed.iter().any(|m| m.name == c.name));
+        candidates.extend(installed.into_iter().map(|m| Self {
+            name: m.name,
+            details: m.details,
+            installed_size: Some(m.size),
+        }));
+        candidates
+    }
+
+    /// Number of parameters in billions
+    pub fn parameters(&self) -> Option<f64> {
+        parse_parameter_size(&self.details.parameter_size)
+    }
+
+    /// Estimated size of the weights in bytes
+    pub fn estimated_size(&self) -> Option<u64> {
+        self.installed_size.or_else(|| {
+            self.parameters()
+                .map(|p| (p * 1e9 * bits_per_weight(&self.details.quantization_level) / 8.0) as u64)
+        })
+    }
+
+    /// Estimated memory needed to serve the model in bytes
+    pub fn estimated_memory(&self) -> Option<u64> {
+        self.estimated_size().map(|size| (size as f64 * RUNTIME_OVERHEAD) as u64)
+    }
+
+    /// Whether the model is trained specifically for code
+    pub fn is_code_model(&self) -> bool {
+        let name = self.name.to_lowercase();
+        ["code", "coder", "starcoder", "codestral"]
+            .iter()
+            .any(|marker| name.contains(marker))
+    }
+
+    fn fits(&self, hardware: &HardwareProfile) -> bool {
+        let (Some(parameters), Some(memory), Some(size)) =
+            (self.parameters(), self.estimated_memory(), self.estimated_size())
+        else {
+            return false;
+        };
+
+        memory <= hardware.memory_budget()
+            && parameters <= hardware.max_parameters()
+            && (self.installed_size.is_some() || size <= hardware.free_disk)
+    }
+}
+
+/// Model proposed for a task type
+#[derive(Debug, Clone)]
+pub struct Recommendation {
+    pub task: TaskType,
+    pub model: String,
+    pub estimated_memory: u64,
+    pub installed: bool,
+}
+
+/// Propose the best-fitting model for every task type
+///
+/// A model specialised for the task beats a bigger generalist; among equally
+/// suited models the one with the most parameters wins, then installed ones,
+/// then the one listed first.
+pub fn recommend(hardware: &HardwareProfile, candidates: &[ModelCandidate]) -> Vec<Recommendation> {
+    TaskType::ALL
+        .iter()
+        .filter_map(|task| {
+            let suited = |c: &ModelCandidate| c.is_code_model() == task.is_code_task();
+
+            // `max_by` keeps the last of equal elements, so walk backwards
+            candidates
+                .iter()
+                .rev()
+                .filter(|c| c.fits(hardware))
+                .max_by(|a, b| {
+                    suited(a)
+                        .cmp(&suited(b))
+                        .then(a.parameters().partial_cmp(&b.parameters()).unwrap_or(Ordering::Equal))
+                        .then(a.installed_size.is_some().cmp(&b.installed_size.is_some()))
+                })
+                .map(|c| Recommendation {
+                    task: task.clone(),
+                    model: c.name.clone(),
+                    estimated_memory: c.estimated_memory().unwrap_or_default(),
+                    installed: c.installed_size.is_some(),
+                })
+        })
+        .collect()
+}
+
+/// Write recommendations into the Ollama routing table
+pub fn apply_recommendations(recommendations: &[Recommendation], models: &mut OllamaModels) {
+    for recommendation in recommendations {
+        let slot = match recommendation.task {
+            TaskType::CodeGeneration => &mut models.code_generation,
+            TaskType::Chat => &mut models.chat,
+            TaskType::CodeAnalysis => &mut models.analysis,
+            _ => continue,
+        };
+        *slot = recommendation.model.clone();
+    }
+}
+
+/// Parse Ollama's parameter size ("7B", "6.7B", "770M") into billions
+fn parse_parameter_size(size: &str) -> Option<f64> {
+    let size = size.trim().to_uppercase();
+    let (number, divisor) = match size.chars().last()? {
+        'B' => (&size[..size.len() - 1], 1.0),
+        'M' => (&size[..size.len() - 1], 1e3),
+        'K' => (&size[..size.len() - 1], 1e6),
+        _ => (size.as_str(), 1e9),
+    };
+    number.parse::<f64>().ok().map(|n| n / divisor)
+}
+
+/// Approximate bits per weight for a quantization level ("Q4_0", "Q4_K_M", "F16")
+fn bits_per_weight(quantization: &str) -> f64 {
+    let quantization = quantization.to_uppercase();
+    match quantization.as_str() {
+        "F32" => 32.0,
+        "F16" | "BF16" => 16.0,
+        q => q
+            .strip_prefix('Q')
+            .and_then(|rest| rest.chars().next())
+            .and_then(|bits| bits.to_digit(10))
+            // Block scales add roughly half a bit per weight
+            .map(|bits| bits as f64 + 0.5)
+            .unwrap_or(4.5),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GB: u64 = 1_000_000_000;
+
+    fn hardware(memory_gb: u64, cores: usize) -> HardwareProfile {
+        HardwareProfile {
+            total_memory: memory_gb * GB,
+            available_memory: memory_gb * GB,
+            cpu_cores: cores,
+            free_disk: 100 * GB,
+        }
+    }
+
+    fn model_for(recommendations: &[Recommendation], task: TaskType) -> &str {
+        &recommendations.iter().find(|r| r.task == task).unwrap().model
+    }
+
+    #[test]
+    fn test_parse_parameter_size() {
+        assert_eq!(parse_parameter_size("7B"), Some(7.0));
+        assert_eq!(parse_parameter_size("6.7B"), Some(6.7));
+        assert_eq!(parse_parameter_size("770M"), Some(0.77));
+        assert_eq!(parse_parameter_size(""), None);
+    }
+
+    #[test]
+    fn test_bits_per_weight() {
+        assert_eq!(bits_per_weight("Q4_0"), 4.5);
+        assert_eq!(bits_per_weight("Q8_0"), 8.5);
+        assert_eq!(bits_per_weight("F16"), 16.0);
+        assert_eq!(bits_per_weight("unknown"), 4.5);
+    }
+
+    #[test]
+    fn test_recommend_prefers_larger_specialised_models() {
+        let recommendations = recommend(&hardware(16, 8), &ModelCandidate::catalog());
+
+        assert_eq!(model_for(&recommendations, TaskType::CodeGeneration), "codellama:13b");
+        assert_eq!(model_for(&recommendations, TaskType::Chat), "llama2:7b");
+    }
+
+    #[test]
+    fn test_recommend_respects_memory_budget() {
+        let recommendations = recommend(&hardware(8, 8), &ModelCandidate::catalog());
+
+        assert_eq!(model_for(&recommendations, TaskType::CodeGeneration), "codellama:7b");
+    }
+
+    #[test]
+    fn test_apply_recommendations() {
+        let recommendations = recommend(&hardware(8, 8), &ModelCandidate::catalog());
+        let mut models = OllamaModels::default();
+
+        apply_recommendations(&recommendations, &mut models);
+
+        assert_eq!(models.code_generation, "codellama:7b");
+        assert_eq!(models.analysis, "codellama:7b");
+    }
+}
@@ -0,0 +1,418 @@
+//! Data Residency
+//!
+//! `security.residency` rules limit where prompts built from certain files
+//! or repositories may go. A rule applies when one of the prompt's source
+//! files matches its `paths` or the prompt's repository remote matches its
+//! `remotes`, and the prompt may then only go to the providers it lists.
+//!
+//! A prompt that names no source files is taken to come from the working
+//! directory, and one that names no repository from the repository the
+//! working directory belongs to, so untagged prompts cannot slip past a rule.
+
+use crate::ai::Prompt;
+use crate::security::wildcard_match;
+use codev_shared::{CodevError, GitInfo, ProviderId, ResidencyRule, SecurityError};
+use std::path::{Component, Path, PathBuf};
+
+const KEY: &str = "security.residency";
+
+/// Residency rules with their path patterns made absolute
+#[derive(Debug, Clone, Default)]
+pub struct Residency {
+    rules: Vec<Rule>,
+    base: PathBuf,
+
+    /// Remote URL of the repository `base` belongs to
+    remote: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    key: String,
+    paths: Vec<String>,
+    remotes: Vec<String>,
+    providers: Vec<ProviderId>,
+}
+
+/// Where a prompt may go under the rules that apply to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Restriction {
+    /// Providers every applying rule allows, in the order of the first
+    pub providers: Vec<ProviderId>,
+
+    /// Key of the first rule that applies
+    pub rule: String,
+
+    /// What made it apply, a file or `remote <url>`
+    pub matched: String,
+}
+
+impl Residency {
+    /// Rules resolving relative paths against the current directory, whose
+    /// repository remote is read from its git configuration
+    pub fn new(rules: &[ResidencyRule]) -> Self {
+        let base = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+        let remote = origin_url(&base);
+        Self {
+            remote,
+            ..Self::with_base(rules, base)
+        }
+    }
+
+    /// Rules resolving relative paths against `base`
+    ///
+    /// Symlinks in `base` and in the part of each path pattern before its
+    /// first wildcard are resolved, as they are for source files.
+    pub fn with_base(rules: &[ResidencyRule], base: impl Into<PathBuf>) -> Self {
+        let base = canonical(&base.into());
+        let rules = rules
+            .iter()
+            .enumerate()
+            .map(|(index, rule)| Rule {
+                key: format!("{}[{}]", KEY, index),
+                paths: rule.paths.iter().map(|pattern| absolute_pattern(&base, pattern)).collect(),
+                remotes: rule.remotes.clone(),
+                providers: rule.providers.clone(),
+            })
+            .collect();
+        Self {
+            rules,
+            base,
+            remote: None,
+        }
+    }
+
+    /// Use the remote of `git` for prompts that name no repository
+    pub fn repository(mut self, git: &GitInfo) -> Self {
+        self.remote = git.remote_url.clone();
+        self
+    }
+
+    /// Restriction for a prompt, `None` when no rule applies
+    pub fn restriction(&self, prompt: &Prompt) -> Option<Restriction> {
+        let mut sources: Vec<String> = prompt.sources().map(|path| self.resolve(path)).collect();
+        if sources.is_empty() {
+            // The trailing separator lets `dir/*` match the directory itself
+            let mut dir = self.resolve(Path::new(""));
+            if !dir.ends_with(std::path::MAIN_SEPARATOR) {
+                dir.push(std::path::MAIN_SEPARATOR);
+            }
+            sources.push(dir);
+        }
+        let remote = prompt.remote_url().or(self.remote.as_deref());
+        let mut restriction: Option<Restriction> = None;
+
+        for rule in &self.rules {
+            let matched = sources
+                .iter()
+                .find(|source| rule.paths.iter().any(|pattern| wildcard_match(pattern, source)))
+                .cloned()
+                .or_else(|| {
+                    remote
+                        .filter(|url| rule.remotes.iter().any(|pattern| wildcard_match(pattern, url)))
+                        .map(|url| format!("remote {}", url))
+                });
+            let Some(matched) = matched else { continue };
+
+            match &mut restriction {
+                Some(restriction) => restriction.providers.retain(|id| rule.providers.contains(id)),
+                None => {
+                    restriction = Some(Restriction {
+                        providers: rule.providers.clone(),
+                        rule: rule.key.clone(),
+                        matched,
+                    })
+                }
+            }
+        }
+        restriction
+    }
+
+    /// Absolute path of a source file, with symlinks resolved
+    fn resolve(&self, path: &Path) -> String {
+        canonical(&self.base.join(path)).display().to_string()
+    }
+}
+
+impl Restriction {
+    /// Whether the prompt may go to a provider
+    pub fn allows(&self, id: &ProviderId) -> bool {
+        self.providers.contains(id)
+    }
+
+    /// Error for a prompt sent to a provider the rules do not allow
+    pub fn refused(&self, id: &ProviderId) -> CodevError {
+        SecurityError::PromptWithheld {
+            reason: format!("{} may not be sent to {}", self.matched, id),
+            rule: self.rule.clone(),
+        }
+        .into()
+    }
+
+    /// Error for a prompt none of the allowed providers can take
+    pub fn withheld(&self) -> CodevError {
+        let reason = if self.providers.is_empty() {
+            format!("{} may not be sent to any provider", self.matched)
+        } else {
+            let allowed: Vec<String> = self.providers.iter().map(ToString::to_string).collect();
+            format!("{} may only go to {} and none is available", self.matched, allowed.join(" or "))
+        };
+        SecurityError::PromptWithheld {
+            reason,
+            rule: self.rule.clone(),
+        }
+        .into()
+    }
+}
+
+/// URL of the `origin` remote, or else the first remote, of the repository
+/// containing `dir`
+///
+/// Read from the git configuration directly, so no `git` process is run.
+fn origin_url(dir: &Path) -> Option<String> {
+    let dot_git = dir.ancestors().map(|d| d.join(".git")).find(|p| p.exists())?;
+    let mut git_dir = if dot_git.is_file() {
+        // Worktrees and submodules point to their git directory
+        let pointer = std::fs::read_to_string(&dot_git).ok()?;
+        let target = pointer.trim().strip_prefix("gitdir:")?.trim();
+        dot_git.parent()?.join(target)
+    } else {
+        dot_git
+    };
+    if let Ok(common) = std::fs::read_to_string(git_dir.join("commondir")) {
+        git_dir = git_dir.join(common.trim());
+    }
+    remote_url(&std::fs::read_to_string(git_dir.join("config")).ok()?)
+}
+
+/// Remote URL from the text of a git configuration file
+fn remote_url(config: &str) -> Option<String> {
+    let mut remote: Option<&str> = None;
+    let mut first = None;
+    for line in config.lines().map(str::trim) {
+        if line.starts_with('[') {
+            remote = line
+                .strip_prefix("[remote \"")
+                .and_then(|rest| rest.strip_suffix("\"]"));
+        } else if let (Some(name), Some((key, value))) = (remote, line.split_once('=')) {
+            if key.trim() == "url" {
+                let url = value.trim().trim_matches('"').to_string();
+                if name == "origin" {
+                    return Some(url);
+                }
+                first.get_or_insert(url);
+            }
+        }
+    }
+    first
+}
+
+/// Make a path pattern absolute, with symlinks resolved up to its first
+/// wildcard; patterns starting with `*` match anywhere
+fn absolute_pattern(base: &Path, pattern: &str) -> String {
+    if pattern.starts_with('*') {
+        return pattern.to_string();
+    }
+    let absolute = match pattern.strip_prefix("~/").zip(dirs::home_dir()) {
+        Some((rest, home)) => home.join(rest),
+        None => base.join(pattern.trim_start_matches("./")),
+    };
+
+    let mut literal = PathBuf::new();
+    let mut wild = PathBuf::new();
+    for component in absolute.components() {
+        let wildcard = component.as_os_str().to_string_lossy().contains(['*', '?']);
+        if wild.as_os_str().is_empty() && !wildcard {
+            literal.push(component);
+        } else {
+            wild.push(component);
+        }
+    }
+    let literal = canonical(&literal);
+    if wild.as_os_str().is_empty() {
+        return literal.display().to_string();
+    }
+    literal.join(wild).display().to_string()
+}
+
+/// `path` with `.` and `..` removed and the symlinks in the part of it that
+/// exists resolved
+fn canonical(path: &Path) -> PathBuf {
+    if let Ok(resolved) = path.canonicalize() {
+        return resolved;
+    }
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normal.pop();
+            }
+            component => normal.push(component),
+        }
+    }
+    // Resolve the deepest ancestor that exists and keep the rest as is
+    let mut missing = Vec::new();
+    let mut existing = normal.as_path();
+    while let Some(parent) = existing.parent() {
+        missing.push(existing.file_name().unwrap_or_default().to_owned());
+        existing = parent;
+        if let Ok(resolved) = existing.canonicalize() {
+            return missing.iter().rev().fold(resolved, |path, name| path.join(name));
+        }
+    }
+    normal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codev_shared::GitInfo;
+
+    fn rule(paths: &[&str], remotes: &[&str], providers: &[ProviderId]) -> ResidencyRule {
+        ResidencyRule {
+            paths: paths.iter().map(|p| p.to_string()).collect(),
+            remotes: remotes.iter().map(|r| r.to_string()).collect(),
+            providers: providers.to_vec(),
+        }
+    }
+
+    fn repository(remote_url: &str) -> GitInfo {
+        GitInfo {
+            current_branch: "main".to_string(),
+            remote_url: Some(remote_url.to_string()),
+            is_dirty: false,
+            last_commit: None,
+        }
+    }
+
+    #[test]
+    fn test_paths_and_remotes() {
+        let residency = Residency::with_base(
+            &[
+                rule(&["payments/*"], &[], &[ProviderId::OLLAMA]),
+                rule(&["*.pem"], &["*github.com?acme/ledger*"], &[ProviderId::OLLAMA]),
+            ],
+            "/work",
+        );
+
+        let restriction = residency.restriction(&Prompt::from("Review").source("./payments/src/../api.rs")).unwrap();
+        assert_eq!(restriction.matched, "/work/payments/api.rs");
+        assert_eq!(restriction.rule, "security.residency[0]");
+
+        let prompt = Prompt::from("Review").repository(&repository("git@github.com:acme/ledger.git"));
+        let restriction = residency.restriction(&prompt).unwrap();
+        assert_eq!(restriction.matched, "remote git@github.com:acme/ledger.git");
+        assert_eq!(restriction.rule, "security.residency[1]");
+        assert!(restriction.allows(&ProviderId::OLLAMA));
+        assert!(!restriction.allows(&ProviderId::CLAUDE));
+
+        assert!(residency.restriction(&Prompt::from("Review").source("/tmp/certs/server.pem")).is_some());
+        let unrelated = Prompt::from("Review")
+            .source("/work/payroll/main.rs")
+            .repository(&repository("https://github.com/acme/site.git"));
+        assert!(residency.restriction(&unrelated).is_none());
+    }
+
+    #[test]
+    fn test_every_applying_rule_narrows_providers() {
+        let lab = ProviderId::new("lab-ollama");
+        let residency = Residency::with_base(
+            &[
+                rule(&["/work/*"], &[], &[ProviderId::OLLAMA, lab.clone()]),
+                rule(&["/work/secret/*"], &[], std::slice::from_ref(&lab)),
+                rule(&["/work/secret/keys/*"], &[], &[]),
+            ],
+            "/",
+        );
+
+        let restriction = residency.restriction(&Prompt::from("Review").source("/work/secret/a.rs")).unwrap();
+        assert_eq!(restriction.providers, [lab]);
+        assert_eq!(restriction.rule, "security.residency[0]");
+
+        let restriction = residency.restriction(&Prompt::from("Review").source("/work/secret/keys/k")).unwrap();
+        assert!(restriction.providers.is_empty());
+        let message = restriction.withheld().to_string();
+        assert!(message.contains("/work/secret/keys/k may not be sent to any provider"), "{}", message);
+    }
+
+    #[test]
+    fn test_untagged_prompts_come_from_the_working_directory() {
+        let rules = [
+            rule(&["/work/payments/*"], &[], &[ProviderId::OLLAMA]),
+            rule(&[], &["*acme/ledger*"], &[ProviderId::OLLAMA]),
+        ];
+
+        let restriction = Residency::with_base(&rules, "/work/payments")
+            .restriction(&Prompt::from("Review"))
+            .unwrap();
+        assert_eq!(restriction.matched, "/work/payments/");
+        // Naming the files puts them in place of the working directory
+        let elsewhere = Prompt::from("Review").source("/work/site/index.html");
+        assert!(Residency::with_base(&rules, "/work/payments").restriction(&elsewhere).is_none());
+
+        let ledger = repository("git@github.com:acme/ledger.git");
+        let residency = Residency::with_base(&rules, "/work/site").repository(&ledger);
+        let restriction = residency.restriction(&Prompt::from("Review")).unwrap();
+        assert_eq!(restriction.matched, "remote git@github.com:acme/ledger.git");
+        let site = repository("https://github.com/acme/site.git");
+        let other = Prompt::from("Review").repository(&site);
+        assert!(residency.restriction(&other).is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinked_directories_match() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("real/client/src")).unwrap();
+        std::fs::write(dir.path().join("real/client/src/lib.rs"), "").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("real"), dir.path().join("work")).unwrap();
+        let work = dir.path().join("work");
+
+        // A rule through the link covers the files it points to
+        let through_link = [rule(&["client/*"], &[], &[ProviderId::OLLAMA])];
+        let residency = Residency::with_base(&through_link, &work);
+        let real_file = Prompt::from("Review").source(dir.path().join("real/client/src/lib.rs"));
+        assert!(residency.restriction(&real_file).is_some());
+        let new_file = Prompt::from("Review").source(work.join("client/src/new.rs"));
+        assert!(residency.restriction(&new_file).is_some());
+        let other = Prompt::from("Review").source(dir.path().join("real/other.rs"));
+        assert!(residency.restriction(&other).is_none());
+
+        // And a rule on the real path covers files reached through the link
+        let real = dir.path().join("real/client/*").display().to_string();
+        let rules = [rule(&[&real], &[], &[ProviderId::OLLAMA])];
+        let residency = Residency::with_base(&rules, "/");
+        assert!(residency.restriction(&new_file).is_some());
+        let untagged = Residency::with_base(&rules, work.join("client"));
+        assert!(untagged.restriction(&Prompt::from("Review")).is_some());
+    }
+
+    #[test]
+    fn test_origin_url_is_read_from_git_config() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join(".git")).unwrap();
+        std::fs::create_dir_all(dir.path().join("src/nested")).unwrap();
+        std::fs::write(
+            dir.path().join(".git/config"),
+            concat!(
+                "[core]\n\tbare = false\n",
+                "[remote \"upstream\"]\n\turl = https://github.com/acme/fork.git\n",
+                "[remote \"origin\"]\n\turl = git@github.com:acme/ledger.git\n",
+                "\tfetch = +refs/heads/*\n",
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            origin_url(&dir.path().join("src/nested")).as_deref(),
+            Some("git@github.com:acme/ledger.git")
+        );
+
+        assert_eq!(
+            remote_url("[remote \"upstream\"]\n  url = https://github.com/acme/fork.git\n")
+                .as_deref(),
+            Some("https://github.com/acme/fork.git")
+        );
+        assert_eq!(remote_url("[core]\n  url = nothing\n"), None);
+    }
+}
@@ -0,0 +1,77 @@
+//! Test doubles for the AI module
+
+use crate::ai::{GenerationOptions, HealthStatus, LlmProvider, ProviderCapabilities};
+use async_trait::async_trait;
+use codev_shared::{ProviderId, Result};
+use futures::Stream;
+use std::pin::Pin;
+
+/// Provider that always answers with the same chunks
+pub(crate) struct StaticProvider {
+    id: ProviderId,
+    chunks: Vec<String>,
+    cost_per_token: f64,
+}
+
+impl StaticProvider {
+    pub(crate) fn new(id: ProviderId, chunks: &[&str]) -> Self {
+        Self {
+            id,
+            chunks: chunks.iter().map(|c| c.to_string()).collect(),
+            cost_per_token: 0.0,
+        }
+    }
+
+    pub(crate) fn with_cost(mut self, cost_per_token: f64) -> Self {
+        self.cost_per_token = cost_per_token;
+        self
+    }
+}
+
+#[async_trait]
+impl LlmProvider for StaticProvider {
+    fn id(&self) -> ProviderId {
+        self.id.clone()
+    }
+
+    fn name(&self) -> &str {
+        "static"
+    }
+
+    fn model(&self) -> &str {
+        "static"
+    }
+
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    async fn health_check(&self) -> Result<HealthStatus> {
+        Ok(HealthStatus::Healthy)
+    }
+
+    async fn stream_generate(
+        &self,
+        _prompt: &str,
+        _options: &GenerationOptions,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<String>> + Send>>> {
+        let chunks: Vec<Result<String>> = self.chunks.iter().cloned().map(Ok).collect();
+        Ok(Box::pin(futures::stream::iter(chunks)))
+    }
+
+    async fn generate(&self, _prompt: &str, _options: GenerationOptions) -> Result<String> {
+        Ok(self.chunks.concat())
+    }
+
+    fn max_content_length(&self) -> usize {
+        ProviderCapabilities::default().max_context_length
+    }
+
+    fn cost_per_token(&self) -> f64 {
+        self.cost_per_token
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities::default()
+    }
+}
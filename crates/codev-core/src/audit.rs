@@ -0,0 +1,561 @@
+//! Audit log
+//!
+//! Commands, file writes, provider calls, policy decisions and secrets
+//! found in prompts are appended to a JSON Lines file. Every record carries
+//! the SHA-256 hash of its own line and the hash of the record before it, so
+//! editing, reordering or removing a record breaks the chain and `verify`
+//! reports where.
+//! Removing records from the end can only be noticed against a copy of the
+//! last hash, which `verify` returns for that purpose.
+//!
+//! Several processes may share one log: each append holds an exclusive lock
+//! on the file and chains to whatever record is last at that moment.
+//!
+//! Prompts and the secrets found in them are never stored, only the
+//! prompt's hash and where in it each secret was.
+
+use crate::ai::redaction::Finding;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use codev_shared::{ProviderId, RedactionAction, Result, SecurityError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::warn;
+
+/// Hash the first record is chained to
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Something the assistant did or decided
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// A command that was run
+    Command {
+        program: String,
+        args: Vec<String>,
+        working_dir: PathBuf,
+        /// Exit code, `None` when the command could not run to completion
+        exit_code: Option<i32>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// A file that was written
+    FileWrite { path: PathBuf, bytes: u64, sha256: String },
+    /// A request sent to an LLM provider
+    ProviderCall {
+        provider: ProviderId,
+        model: String,
+        prompt_tokens: usize,
+        completion_tokens: usize,
+        prompt_sha256: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// What the security policy decided about an action
+    PolicyDecision {
+        action: String,
+        decision: Decision,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rule: Option<String>,
+    },
+    /// Secrets found in a prompt before it was sent
+    PromptScreened {
+        action: RedactionAction,
+        findings: Vec<Finding>,
+        prompt_sha256: String,
+    },
+}
+
+impl AuditEvent {
+    /// A provider call, with the prompt reduced to its hash
+    pub fn provider_call(
+        provider: ProviderId,
+        model: &str,
+        prompt: &str,
+        prompt_tokens: usize,
+        completion_tokens: usize,
+        error: Option<String>,
+    ) -> Self {
+        AuditEvent::ProviderCall {
+            provider,
+            model: model.to_string(),
+            prompt_tokens,
+            completion_tokens,
+            prompt_sha256: sha256_hex(prompt.as_bytes()),
+            error,
+        }
+    }
+}
+
+/// Outcome of a policy check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    Allow,
+    /// Allowed after the user confirmed
+    Confirmed,
+    Deny,
+}
+
+/// One line of the audit log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub seq: u64,
+    pub time: DateTime<Utc>,
+    pub prev_hash: String,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+    pub hash: String,
+}
+
+/// The part of a record its hash is computed over
+#[derive(Serialize)]
+struct Unsigned<'a> {
+    seq: u64,
+    time: DateTime<Utc>,
+    prev_hash: &'a str,
+    #[serde(flatten)]
+    event: &'a AuditEvent,
+}
+
+/// Result of checking the hash chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verified {
+    pub records: u64,
+    /// Hash of the last record, to compare with a copy kept elsewhere
+    pub last_hash: String,
+}
+
+/// Append-only audit log file
+pub struct AuditLog {
+    path: PathBuf,
+    /// Sequence number and hash of the last record this handle saw
+    head: Mutex<(u64, String)>,
+}
+
+impl AuditLog {
+    /// Open the log at `path`, creating it if needed
+    ///
+    /// The chain continues from the last record in the file. A log that
+    /// fails verification is refused, so new records never extend a chain
+    /// that was tampered with.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let head = if path.exists() {
+            let verified = Self::verify(&path)?;
+            (verified.records, verified.last_hash)
+        } else {
+            (0, GENESIS_HASH.to_string())
+        };
+        Ok(Self {
+            path,
+            head: Mutex::new(head),
+        })
+    }
+
+    /// File the log is written to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append a record
+    ///
+    /// Records other handles appended since are chained to. Fails with
+    /// `SecurityError::AuditLogTampered` if records this handle saw are gone.
+    pub fn record(&self, event: AuditEvent) -> Result<()> {
+        let mut head = self.head.lock().unwrap_or_else(|e| e.into_inner());
+
+        let mut options = OpenOptions::new();
+        options.create(true).read(true).append(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&self.path)?;
+        // Released when the file is closed
+        file.lock()?;
+
+        if let Some(line) = last_line(&mut file)? {
+            let last = check_hash(0, &line)
+                .ok()
+                .filter(|last| last.seq > head.0 || (last.seq == head.0 && last.hash == head.1));
+            let Some(last) = last else {
+                // Only a damaged log is read in full, to say where
+                let number = count_lines(&mut file)?;
+                check_hash(number, &line)?;
+                return Err(SecurityError::AuditLogTampered {
+                    line: number,
+                    reason: format!("record {} was removed or changed", head.0),
+                }
+                .into());
+            };
+            *head = (last.seq, last.hash);
+        } else if head.0 > 0 {
+            return Err(SecurityError::AuditLogTampered {
+                line: 1,
+                reason: "every record was removed".to_string(),
+            }
+            .into());
+        }
+
+        let seq = head.0 + 1;
+        let unsigned = Unsigned {
+            seq,
+            time: Utc::now(),
+            prev_hash: &head.1,
+            event: &event,
+        };
+        let body = serde_json::to_string(&unsigned)?;
+        let hash = sha256_hex(body.as_bytes());
+        // `body` is a JSON object; the hash goes in as its last field
+        let line = format!("{}{}\n", &body[..body.len() - 1], hash_field(&hash));
+
+        file.write_all(line.as_bytes())?;
+        file.sync_data()?;
+
+        *head = (seq, hash);
+        Ok(())
+    }
+
+    /// Append a record of something that already happened
+    ///
+    /// Failures are logged instead of returned, since the action can no
+    /// longer be refused.
+    pub fn record_or_warn(&self, event: AuditEvent) {
+        if let Err(e) = self.record(event) {
+            warn!("Could not write audit log {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// Records at or after `since`, oldest first
+    pub fn read(path: &Path, since: Option<DateTime<Utc>>) -> Result<Vec<AuditRecord>> {
+        let mut records = Vec::new();
+        for (index, line) in BufReader::new(std::fs::File::open(path)?).lines().enumerate() {
+            let record = parse(index + 1, &line?)?;
+            if since.is_none_or(|since| record.time >= since) {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+
+    /// Check every record's hash and its link to the one before
+    ///
+    /// Fails with `SecurityError::AuditLogTampered` at the first record
+    /// that does not match.
+    pub fn verify(path: &Path) -> Result<Verified> {
+        let mut expected = Verified {
+            records: 0,
+            last_hash: GENESIS_HASH.to_string(),
+        };
+
+        for (index, line) in BufReader::new(std::fs::File::open(path)?).lines().enumerate() {
+            let number = index + 1;
+            let line = line?;
+            let record = parse(number, &line)?;
+            let tampered = |reason: String| SecurityError::AuditLogTampered { line: number, reason };
+
+            if record.seq != expected.records + 1 {
+                return Err(tampered(format!("expected record {}, found {}", expected.records + 1, record.seq)).into());
+            }
+            if record.prev_hash != expected.last_hash {
+                return Err(tampered("does not follow the previous record".to_string()).into());
+            }
+            check_hash(number, &line)?;
+
+            expected = Verified {
+                records: record.seq,
+                last_hash: record.hash,
+            };
+        }
+        Ok(expected)
+    }
+}
+
+/// Parse `--since` values: an RFC 3339 time, a date, or an age such as
+/// `30m`, `12h` or `7d`
+pub fn parse_since(value: &str) -> Result<DateTime<Utc>> {
+    let value = value.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        if let Some(time) = date.and_hms_opt(0, 0, 0) {
+            return Ok(time.and_utc());
+        }
+    }
+
+    let since = value
+        .char_indices()
+        .last()
+        .and_then(|(at, unit)| Some((value[..at].parse::<i64>().ok()?, unit)))
+        .and_then(|(amount, unit)| match unit {
+            's' => Duration::try_seconds(amount),
+            'm' => Duration::try_minutes(amount),
+            'h' => Duration::try_hours(amount),
+            'd' => Duration::try_days(amount),
+            'w' => Duration::try_weeks(amount),
+            _ => None,
+        })
+        .and_then(|age| Utc::now().checked_sub_signed(age));
+    match since {
+        Some(since) => Ok(since),
+        None => Err(codev_shared::CodevError::InvalidInput {
+            message: format!("'{}' is not a time, date or age such as 12h", value),
+        }),
+    }
+}
+
+/// Hex SHA-256 of `data`, as stored in the log
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// How the hash ends a record's line
+fn hash_field(hash: &str) -> String {
+    format!(",\"hash\":\"{}\"}}", hash)
+}
+
+/// Parse a record and check it against its own hash
+fn check_hash(number: usize, line: &str) -> Result<AuditRecord> {
+    let record = parse(number, line)?;
+    let body = line
+        .strip_suffix(&hash_field(&record.hash))
+        .map(|body| format!("{}}}", body));
+    if body.is_none_or(|body| sha256_hex(body.as_bytes()) != record.hash) {
+        return Err(SecurityError::AuditLogTampered {
+            line: number,
+            reason: "contents do not match its hash".to_string(),
+        }
+        .into());
+    }
+    Ok(record)
+}
+
+/// Last line of the file, read backwards from its end
+fn last_line(file: &mut std::fs::File) -> Result<Option<String>> {
+    let mut tail: Vec<u8> = Vec::new();
+    let mut position = file.seek(SeekFrom::End(0))?;
+    while position > 0 {
+        let size = position.min(4096);
+        position -= size;
+        let mut chunk = vec![0; size as usize];
+        file.seek(SeekFrom::Start(position))?;
+        file.read_exact(&mut chunk)?;
+        chunk.extend_from_slice(&tail);
+        tail = chunk;
+        if tail.strip_suffix(b"\n").unwrap_or(&tail).contains(&b'\n') {
+            break;
+        }
+    }
+    if tail.is_empty() {
+        return Ok(None);
+    }
+
+    let body = tail.strip_suffix(b"\n").unwrap_or(&tail);
+    let line = body.iter().rposition(|byte| *byte == b'\n').map_or(body, |at| &body[at + 1..]);
+    match String::from_utf8(line.to_vec()) {
+        Ok(line) => Ok(Some(line)),
+        Err(e) => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e).into()),
+    }
+}
+
+/// Number of lines in the file
+fn count_lines(file: &mut std::fs::File) -> Result<usize> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut count = 0;
+    for line in BufReader::new(&*file).split(b'\n') {
+        line?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn parse(number: usize, line: &str) -> Result<AuditRecord> {
+    serde_json::from_str(line).map_err(|e| {
+        SecurityError::AuditLogTampered {
+            line: number,
+            reason: format!("not a record ({})", e),
+        }
+        .into()
+    })
+}
+
+impl fmt::Display for AuditEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditEvent::Command {
+                program,
+                args,
+                working_dir,
+                exit_code,
+                error,
+            } => {
+                write!(f, "command `{}", program)?;
+                for arg in args {
+                    write!(f, " {}", arg)?;
+                }
+                write!(f, "` in {}", working_dir.display())?;
+                match (exit_code, error) {
+                    (_, Some(error)) => write!(f, " failed: {}", error),
+                    (Some(code), None) => write!(f, " exited {}", code),
+                    (None, None) => Ok(()),
+                }
+            }
+            AuditEvent::FileWrite { path, bytes, .. } => write!(f, "wrote {} ({} bytes)", path.display(), bytes),
+            AuditEvent::ProviderCall {
+                provider,
+                model,
+                prompt_tokens,
+                completion_tokens,
+                error,
+                ..
+            } => {
+                write!(f, "{}/{} {} in, {} out", provider, model, prompt_tokens, completion_tokens)?;
+                match error {
+                    Some(error) => write!(f, " failed: {}", error),
+                    None => Ok(()),
+                }
+            }
+            AuditEvent::PolicyDecision { action, decision, rule } => {
+                let decision = match decision {
+                    Decision::Allow => "allowed",
+                    Decision::Confirmed => "confirmed",
+                    Decision::Deny => "denied",
+                };
+                write!(f, "{} {}", decision, action)?;
+                match rule {
+                    Some(rule) => write!(f, " ({})", rule),
+                    None => Ok(()),
+                }
+            }
+            AuditEvent::PromptScreened { action, findings, .. } => {
+                let findings: Vec<String> = findings.iter().map(ToString::to_string).collect();
+                write!(f, "prompt with {} ({})", findings.join(", "), action)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &str) -> AuditEvent {
+        AuditEvent::FileWrite {
+            path: PathBuf::from(path),
+            bytes: 3,
+            sha256: sha256_hex(b"abc"),
+        }
+    }
+
+    #[test]
+    fn test_chain_continues_across_opens() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit/audit.jsonl");
+
+        let log = AuditLog::open(&path).unwrap();
+        log.record(write("a.rs")).unwrap();
+        log.record(AuditEvent::PolicyDecision {
+            action: "run git push".to_string(),
+            decision: Decision::Deny,
+            rule: Some("security.commands.git.deny \"push*--force*\"".to_string()),
+        })
+        .unwrap();
+        drop(log);
+        AuditLog::open(&path).unwrap().record(write("b.rs")).unwrap();
+
+        let records = AuditLog::read(&path, None).unwrap();
+        assert_eq!(records.iter().map(|r| r.seq).collect::<Vec<_>>(), [1, 2, 3]);
+        assert_eq!(records[0].prev_hash, GENESIS_HASH);
+        assert_eq!(records[2].prev_hash, records[1].hash);
+        assert_eq!(records[2].event, write("b.rs"));
+
+        let verified = AuditLog::verify(&path).unwrap();
+        assert_eq!(verified.records, 3);
+        assert_eq!(verified.last_hash, records[2].hash);
+    }
+
+    #[test]
+    fn test_tampering_is_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let log = AuditLog::open(&path).unwrap();
+        for file in ["a.rs", "b.rs", "c.rs"] {
+            log.record(write(file)).unwrap();
+        }
+        let original = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = original.lines().collect();
+
+        let edited = original.replace("b.rs", "x.rs");
+        std::fs::write(&path, edited).unwrap();
+        let error = AuditLog::verify(&path).unwrap_err().to_string();
+        assert!(error.contains("line 2: contents do not match its hash"), "{}", error);
+        assert!(AuditLog::open(&path).is_err());
+
+        std::fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        let error = AuditLog::verify(&path).unwrap_err().to_string();
+        assert!(error.contains("line 2: expected record 2, found 3"), "{}", error);
+    }
+
+    #[test]
+    fn test_handles_sharing_a_file_keep_one_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let first = AuditLog::open(&path).unwrap();
+        let second = AuditLog::open(&path).unwrap();
+        first.record(write("a.rs")).unwrap();
+        second.record(write("b.rs")).unwrap();
+        first.record(write("c.rs")).unwrap();
+        second.record(write("d.rs")).unwrap();
+
+        let records = AuditLog::read(&path, None).unwrap();
+        assert_eq!(records.iter().map(|r| r.seq).collect::<Vec<_>>(), [1, 2, 3, 4]);
+        assert_eq!(records[3].event, write("d.rs"));
+        assert_eq!(AuditLog::verify(&path).unwrap().records, 4);
+
+        // Truncating behind a handle's back is not papered over
+        let original = std::fs::read_to_string(&path).unwrap();
+        let kept: String = original.lines().take(2).map(|l| format!("{}\n", l)).collect();
+        std::fs::write(&path, kept).unwrap();
+        let error = first.record(write("e.rs")).unwrap_err().to_string();
+        assert!(error.contains("line 2: record 3 was removed or changed"), "{}", error);
+    }
+
+    #[test]
+    fn test_last_line_is_read_from_the_end() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lines");
+        let last = |contents: &str| {
+            std::fs::write(&path, contents).unwrap();
+            last_line(&mut std::fs::File::open(&path).unwrap()).unwrap()
+        };
+
+        assert_eq!(last(""), None);
+        assert_eq!(last("only\n").as_deref(), Some("only"));
+        assert_eq!(last("first\nsecond").as_deref(), Some("second"));
+        // Lines spanning several chunks
+        let long = "x".repeat(5000);
+        assert_eq!(last(&format!("{}\n{}\n", long, long)), Some(long.clone()));
+        assert_eq!(last(&format!("a\n{}é\n", long)), Some(format!("{}é", long)));
+    }
+
+    #[test]
+    fn test_parse_since() {
+        assert_eq!(
+            parse_since("2026-03-01").unwrap(),
+            DateTime::parse_from_rfc3339("2026-03-01T00:00:00Z").unwrap()
+        );
+        let since = parse_since("2h").unwrap();
+        let age = Utc::now() - since;
+        assert!(age >= Duration::hours(2) && age < Duration::hours(2) + Duration::minutes(1));
+        assert!(parse_since("yesterday").is_err());
+        assert!(parse_since("99999999999d").is_err());
+        assert!(parse_since("9223372036854775807s").is_err());
+    }
+}
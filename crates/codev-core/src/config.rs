@@ -1,3 +1,122 @@
+//! Configuration management
+//!
+//! `ConfigManager` holds the configuration the engine is running with, and
+//! `ConfigWatcher` notices edits to the configuration files.
+
+use codev_shared::{CodevConfig, CodevError, ConfigLoader, Result};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, warn};
+
+/// Holds the active configuration
+pub struct ConfigManager {
+    current: RwLock<Arc<CodevConfig>>,
+}
+
+impl ConfigManager {
+    pub fn new(config: CodevConfig) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(config)),
+        }
+    }
+
+    /// Configuration in force at the time of the call
+    pub fn current(&self) -> Arc<CodevConfig> {
+        self.current.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Record a new active configuration
+    pub async fn update_config(&self, config: &CodevConfig) -> Result<()> {
+        *self.current.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(config.clone());
+        Ok(())
+    }
+}
+
+/// Reloads the configuration when one of its files changes
+///
+/// Every successfully loaded configuration is handed to a callback, which
+/// decides whether to apply it. Files that fail to parse are reported and
+/// otherwise ignored. Watching stops when the watcher is dropped.
+pub struct ConfigWatcher {
+    _watcher: RecommendedWatcher,
+    task: JoinHandle<()>,
+}
+
+impl ConfigWatcher {
+    /// Editors often save in several steps; wait for them to finish
+    const DEBOUNCE: Duration = Duration::from_millis(300);
+
+    /// Watch the files of `loader` and call `on_change` after each reload
+    pub fn spawn<F, Fut>(loader: ConfigLoader, on_change: F) -> Result<Self>
+    where
+        F: Fn(CodevConfig) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send,
+    {
+        let files = loader.files();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let watched = files.clone();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if let Ok(event) = event {
+                if event.paths.iter().any(|path| watched.contains(path)) {
+                    let _ = tx.send(());
+                }
+            }
+        })
+        .map_err(|e| CodevError::Config {
+            message: format!("cannot watch configuration files: {}", e),
+        })?;
+
+        // Directories rather than files, so that files replaced on save or
+        // created later are noticed
+        let mut dirs: Vec<&Path> = files.iter().filter_map(|f| f.parent()).filter(|d| d.is_dir()).collect();
+        dirs.sort();
+        dirs.dedup();
+        for dir in dirs {
+            watcher
+                .watch(dir, RecursiveMode::NonRecursive)
+                .map_err(|e| CodevError::Config {
+                    message: format!("cannot watch {}: {}", dir.display(), e),
+                })?;
+            debug!("Watching {} for configuration changes", dir.display());
+        }
+
+        let task = tokio::spawn(async move {
+            while rx.recv().await.is_some() {
+                tokio::time::sleep(Self::DEBOUNCE).await;
+                while rx.try_recv().is_ok() {}
+
+                match loader.load() {
+                    Ok(layered) => {
+                        for warning in layered.warnings() {
+                            warn!("{}", warning);
+                        }
+                        on_change(layered.config).await
+                    }
+                    Err(e) => error!("Ignoring configuration change that does not load: {}", e),
+                }
+            }
+        });
+
+        Ok(Self {
+            _watcher: watcher,
+            task,
+        })
+    }
+}
+
+impl Drop for ConfigWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LLMConfig {
     pub default_provider: String,
@@ -34,4 +153,36 @@ impl LLMConfig {
 
         keys
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codev_shared::ProviderId;
+
+    #[tokio::test]
+    async fn test_watcher_reloads_edited_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let user_file = dir.path().join("config.toml");
+        let loader = ConfigLoader::new()
+            .with_system_file(dir.path().join("system.toml"))
+            .with_user_file(&user_file)
+            .with_repository_dir(dir.path())
+            .with_environment(Vec::<(String, String)>::new());
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let _watcher = ConfigWatcher::spawn(loader, move |config| {
+            let _ = tx.send(config);
+            async {}
+        })
+        .unwrap();
+
+        std::fs::write(&user_file, "[ai.providers.ollama]\nmodel = \"llama3.1:8b\"\n").unwrap();
+
+        let config = tokio::time::timeout(Duration::from_secs(10), rx.recv())
+            .await
+            .expect("no reload")
+            .unwrap();
+        assert_eq!(config.ai.providers[&ProviderId::OLLAMA].model, "llama3.1:8b");
+    }
+}
@@ -2,12 +2,13 @@
 
 use crate::ai::AiEngine;
 use crate::analysis::ProjectAnalyzer;
-use crate::config::ConfigManager;
+use crate::audit::AuditLog;
+use crate::config::{ConfigManager, ConfigWatcher};
 use crate::project::ProjectManager;
-use crate::security::SecurityManger;
-use coeev_shared::{CodevConfig, CodevError, Result};
+use crate::security::{SecurityManager, SecurityPolicy};
+use codev_shared::{changed_keys, CodevConfig, ConfigLoader, Result};
 use std::sync::Arc;
-use tracing::{info, instrument};
+use tracing::{debug, error, info, instrument, warn};
 
 /// Main engine that coordinates all CoDev.rs components
 ///
@@ -27,20 +28,33 @@ pub struct CodevEngine {
     project_analyzer: Arc<ProjectAnalyzer>,
 
     /// Security manager for safe execution
-    security_manger: Arc<SecurityManger>,
+    security_manager: Arc<SecurityManager>,
 
-    /// Current configuration
-    config: CodevConfig,
+    /// Applies edits to the configuration files, when hot reload is enabled
+    config_watcher: Option<ConfigWatcher>,
 }
 
 impl CodevEngine {
-    /// Create a new engine with default configuration
+    /// Create a new engine from the configuration files and environment
+    ///
+    /// With `development.hot_reload`, later edits to the files are applied
+    /// while the engine runs.
     #[instrument]
     pub async fn new() -> Result<Self> {
         info!("Initializing CoDev.rs engine with default configuration");
 
-        let config = CodevConfig::load_with_env()?;
-        Self::with_config(config).await
+        let loader = ConfigLoader::new();
+        let layered = loader.load()?;
+        for warning in layered.warnings() {
+            warn!("{}", warning);
+        }
+        if layered.needs_migration() {
+            warn!("Run `codev config migrate --write` to update the configuration files");
+        }
+        let config = layered.config;
+        let mut engine = Self::with_config(config).await?;
+        engine.watch_config(loader)?;
+        Ok(engine)
     }
 
     /// Create a new engine with the provided configuration
@@ -48,20 +62,33 @@ impl CodevEngine {
     pub async fn with_config(config: CodevConfig) -> Result<Self> {
         info!("Initializing CoDev.rs engine with custom configuration");
 
-        // Validate configuration
-        config.validate()?;
+        // Validate configuration, loading the API keys only once
+        let credentials = config.ai.load_credentials();
+        config.validate_with_credentials(&credentials)?;
 
         // Initialize components
         let config_manager = Arc::new(
             ConfigManager::new(config.clone())
         );
 
+        let audit_log = match config.audit_log_path() {
+            Some(path) => Some(Arc::new(AuditLog::open(path)?)),
+            None => None,
+        };
+
         let security_manager = Arc::new(
-            SecurityManger::new(&config.security)?
+            SecurityManager::new(&config.security)?.with_audit_log(audit_log.clone())
         );
 
         let ai_engine = Arc::new(
-            AiEngine::new(&config.ai, config_manager.clone()).await?,
+            AiEngine::new(
+                &config.permitted_ai(),
+                &config.security,
+                credentials,
+                config_manager.clone(),
+                audit_log,
+            )
+            .await?,
         );
 
         let project_manager = Arc::new(
@@ -79,11 +106,35 @@ impl CodevEngine {
             ai_engine,
             project_manager,
             project_analyzer,
-            security_manger,
-            config,
+            security_manager,
+            config_watcher: None,
         })
     }
 
+    /// Apply edits to the files of `loader` while the engine runs
+    ///
+    /// Does nothing unless `development.hot_reload` is enabled. Edits that
+    /// fail to load or validate are logged and leave the engine unchanged.
+    pub fn watch_config(&mut self, loader: ConfigLoader) -> Result<()> {
+        let hot_reload = self.config().development.as_ref().is_some_and(|d| d.hot_reload);
+        if !hot_reload {
+            return Ok(());
+        }
+
+        let components = self.reloadable();
+        self.config_watcher = Some(ConfigWatcher::spawn(loader, move |config| {
+            let components = components.clone();
+            async move {
+                if let Err(e) = components.apply(config).await {
+                    error!("Rejected configuration change, keeping the current configuration: {}", e);
+                }
+            }
+        })?);
+
+        info!("Watching configuration files for changes");
+        Ok(())
+    }
+
     /// Get the configuration manager
     pub fn config_manager(&self) -> &ConfigManager {
         &self.config_manager
@@ -105,41 +156,31 @@ impl CodevEngine {
     }
 
     /// Get the security manager
-    pub fn security_manger(&self) -> &SecurityManger {
-        &self.security_manger
+    pub fn security_manager(&self) -> &SecurityManager {
+        &self.security_manager
     }
 
     /// Get the current configuration
-    pub fn config(&self) -> &CodevConfig {
-        &self.config
+    pub fn config(&self) -> Arc<CodevConfig> {
+        self.config_manager.current()
     }
 
     /// Update configuration at runtime
+    ///
+    /// AI providers and the security policy are swapped in place; requests
+    /// already running finish with the previous ones.
     #[instrument(skip(self, new_config))]
-    pub async fn update_config(&mut self, new_config: CodevConfig) -> Result<()> {
+    pub async fn update_config(&self, new_config: CodevConfig) -> Result<()> {
         info!("Updating CoDev.rs engine configuration");
+        self.reloadable().apply(new_config).await
+    }
 
-        // Validate new configuration
-        new_config.validate()?;
-
-        // Update components that support runtime config changes
-        if new_config.ai != self.config.ai {
-            info!("Updating AI engine configuration");
-            // Note: In a full implementation, we'd have a method to update AI config
-            // For now, we'll note that this requires reinitialisation
-        }
-
-        if new_config.security != self.config.security {
-            info!("Updating security manager configuration");
-            // Security changes might require component reinitialization
+    fn reloadable(&self) -> Reloadable {
+        Reloadable {
+            config_manager: self.config_manager.clone(),
+            ai_engine: self.ai_engine.clone(),
+            security_manager: self.security_manager.clone(),
         }
-
-        // Update the stored configuration
-        self.config = new_config;
-        self.config_manager.update_config(&self.config.clone()).await?;
-
-        info!("Configuration updated successfully");
-        Ok(())
     }
 
     /// Get health status of all components
@@ -149,7 +190,7 @@ impl CodevEngine {
 
         let ai_health = self.ai_engine.health_check().await;
         let project_health = self.project_manager.health_check().await;
-        let security_health = self.security_manger.health_check().await;
+        let security_health = self.security_manager.health_check().await;
 
         EngineHealth {
             overall: if ai_health.is_healthy() && project_health.is_healthy() && security_health.is_healthy() {
@@ -182,6 +223,66 @@ impl CodevEngine {
     }
 }
 
+/// Components that can be reconfigured while running
+#[derive(Clone)]
+struct Reloadable {
+    config_manager: Arc<ConfigManager>,
+    ai_engine: Arc<AiEngine>,
+    security_manager: Arc<SecurityManager>,
+}
+
+impl Reloadable {
+    /// Validate a new configuration and apply what changed
+    ///
+    /// Everything that can fail happens before the first swap, so a
+    /// rejected configuration leaves all components as they were.
+    async fn apply(&self, new_config: CodevConfig) -> Result<()> {
+        let credentials = new_config.ai.load_credentials();
+        new_config.validate_with_credentials(&credentials)?;
+
+        let changed = changed_keys(&self.config_manager.current(), &new_config)?;
+        if changed.is_empty() {
+            debug!("Configuration unchanged");
+            return Ok(());
+        }
+        info!("Configuration changed: {}", changed.join(", "));
+
+        let in_section = |section: &str| changed.iter().any(|key| key.starts_with(section));
+        let policy = if in_section("security.") {
+            Some(SecurityPolicy::from_config(&new_config.security)?)
+        } else {
+            None
+        };
+
+        if in_section("ai.")
+            || in_section("security.redaction.")
+            || in_section("security.residency")
+            || changed.iter().any(|key| key == "security.cloud_providers")
+        {
+            self.ai_engine
+                .reconfigure(&new_config.permitted_ai(), &new_config.security, credentials)?;
+        }
+        if let Some(policy) = policy {
+            self.security_manager.set_policy(policy);
+        }
+
+        let needs_restart: Vec<&str> = changed
+            .iter()
+            .map(String::as_str)
+            .filter(|key| {
+                !key.starts_with("ai.") && (!key.starts_with("security.") || key.starts_with("security.audit."))
+            })
+            .collect();
+        if !needs_restart.is_empty() {
+            warn!("Restart to apply: {}", needs_restart.join(", "));
+        }
+
+        self.config_manager.update_config(&new_config).await?;
+        info!("Configuration updated successfully");
+        Ok(())
+    }
+}
+
 /// Health status of the entire engine
 #[derive(Debug, Clone)]
 pub struct EngineHealth {
@@ -11,6 +11,7 @@
 
 pub mod ai;
 pub mod analysis;
+pub mod audit;
 pub mod config;
 pub mod engine;
 pub mod git;
@@ -23,6 +24,7 @@ pub use codev_shared::*;
 
 pub use ai::{AiEngine, LllManager, LlmProvider};
 pub use analysis::{CodeAnalyzer, ProjectAnalyzer};
+pub use audit::AuditLog;
 pub use config::ConfigManager;
 pub use engine::CodevEngine;
 pub use project::ProjectManager;
@@ -86,7 +88,7 @@ impl CoDev {
     }
 
     /// Get current configuration
-    pub fn config(&self) -> &CodevConfig {
+    pub fn config(&self) -> std::sync::Arc<CodevConfig> {
         self.engine.config()
     }
 }
@@ -0,0 +1,363 @@
+//! Command policy
+//!
+//! A program must be in `security.allowed_commands`. Its entry in
+//! `security.commands` then decides on the arguments (deny, then ask, then
+//! allow) and on the directories it may run in, and `security.command_env`
+//! decides which environment variables it sees. With
+//! `security.approval.commands` every command that is not refused needs
+//! confirmation.
+//!
+//! Rules for `git`, `cargo` and `npm` match from the subcommand on, so
+//! global options such as `git -C <dir>` or `cargo +nightly` in front of it
+//! do not get a command past them. Global options that set configuration,
+//! `git -c` and `cargo --config`, are refused: an alias or `core.sshCommand`
+//! set that way changes what runs without the arguments showing it.
+
+use codev_shared::{CommandRule, Result, SecurityConfig, SecurityError};
+use std::path::{Path, PathBuf};
+
+/// Programs whose rules match from the subcommand on, with the global
+/// options that take their value in the next argument
+const GLOBAL_OPTIONS: &[(&str, &[&str])] = &[
+    ("git", &["-C", "-c", "--git-dir", "--work-tree", "--namespace", "--config-env"]),
+    ("cargo", &["-C", "-Z", "--config", "--color"]),
+    ("npm", &["-w", "--workspace", "--prefix", "--registry", "--userconfig", "--cache", "--loglevel"]),
+];
+
+/// Global options that set configuration, by program
+const CONFIG_OPTIONS: &[(&str, &[&str])] = &[
+    ("git", &["-c", "--config-env"]),
+    ("cargo", &["--config"]),
+];
+
+/// Outcome for a command the policy does not refuse
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandDecision {
+    /// Run it
+    Allow,
+    /// Confirm with the user first
+    Ask { rule: String },
+}
+
+pub(crate) fn check(
+    config: &SecurityConfig,
+    program: &str,
+    args: &[String],
+    working_dir: &Path,
+) -> Result<CommandDecision> {
+    let deny = |rule: String| -> Result<CommandDecision> {
+        Err(SecurityError::CommandNotAllowed {
+            command: command_line(program, args),
+            rule,
+        }
+        .into())
+    };
+
+    let allow = || {
+        if config.approval.commands {
+            CommandDecision::Ask {
+                rule: "security.approval.commands".to_string(),
+            }
+        } else {
+            CommandDecision::Allow
+        }
+    };
+
+    if !config.allowed_commands.iter().any(|allowed| allowed == program) {
+        return deny("not in security.allowed_commands".to_string());
+    }
+    let subcommand = subcommand(program, args);
+    if let Some(option) = subcommand.config_option {
+        return deny(format!("{} sets configuration, which can change what runs", option));
+    }
+    let Some(rule) = config.commands.get(program) else {
+        return Ok(allow());
+    };
+    let key = format!("security.commands.{}", program);
+
+    if !rule.working_dirs.is_empty() {
+        let working_dir = canonical(working_dir);
+        if !rule.working_dirs.iter().any(|dir| working_dir.starts_with(canonical(dir))) {
+            return deny(format!(
+                "{} is outside {}.working_dirs",
+                working_dir.display(),
+                key
+            ));
+        }
+    }
+
+    // Every way of reading the options in front of the subcommand is checked
+    let lines = subcommand.lines;
+    if let Some(pattern) = lines.iter().find_map(|line| first_match(&rule.deny, line)) {
+        return deny(format!("matches {}.deny \"{}\"", key, pattern));
+    }
+    if let Some(pattern) = lines.iter().find_map(|line| first_match(&rule.ask, line)) {
+        return Ok(CommandDecision::Ask {
+            rule: format!("{}.ask \"{}\"", key, pattern),
+        });
+    }
+    if !rule.allow.is_empty() && !lines.iter().any(|line| first_match(&rule.allow, line).is_some()) {
+        return deny(format!("matches none of {}.allow", key));
+    }
+    Ok(allow())
+}
+
+/// Environment variables `program` may see
+pub(crate) fn filter_env(
+    config: &SecurityConfig,
+    program: &str,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Vec<(String, String)> {
+    let env = &config.command_env;
+    let extra = config.commands.get(program).map(|rule: &CommandRule| rule.env_allow.as_slice());
+
+    vars.into_iter()
+        .filter(|(name, _)| {
+            let allowed = first_match(&env.allow, name).is_some()
+                || extra.is_some_and(|extra| first_match(extra, name).is_some());
+            allowed && first_match(&env.deny, name).is_none()
+        })
+        .collect()
+}
+
+/// Match `text` against a pattern where `*` is any run of characters and
+/// `?` exactly one
+pub(crate) fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text it was matched against, to
+    // backtrack to when the rest does not match
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// How the arguments in front of the subcommand can be read
+struct Subcommand {
+    /// The arguments from the subcommand on, for every place it can start
+    lines: Vec<String>,
+
+    /// A global option among them that sets configuration
+    config_option: Option<String>,
+}
+
+/// Read the global options in front of the subcommand
+///
+/// Only programs in `GLOBAL_OPTIONS` have options skipped. An unknown
+/// option may or may not take the next argument as its value, so both
+/// readings are returned.
+fn subcommand(program: &str, args: &[String]) -> Subcommand {
+    let Some((_, takes_value)) = GLOBAL_OPTIONS.iter().find(|(name, _)| *name == program) else {
+        return Subcommand {
+            lines: vec![args.join(" ")],
+            config_option: None,
+        };
+    };
+    let sets_config: &[&str] = CONFIG_OPTIONS
+        .iter()
+        .find(|(name, _)| *name == program)
+        .map_or(&[], |(_, options)| options);
+
+    let mut reachable = vec![false; args.len() + 2];
+    reachable[0] = true;
+    let mut lines = Vec::new();
+    let mut config_option = None;
+    for start in 0..=args.len() {
+        if !reachable[start] {
+            continue;
+        }
+        let Some(option) = args.get(start).filter(|arg| arg.starts_with('-') || arg.starts_with('+')) else {
+            lines.push(args[start..].join(" "));
+            continue;
+        };
+
+        let name = option.split_once('=').map_or(option.as_str(), |(name, _)| name);
+        if sets_config.contains(&name) {
+            config_option.get_or_insert_with(|| name.to_string());
+        }
+        let known = takes_value.contains(&option.as_str());
+        let attached = option.contains('=') || option.starts_with('+');
+        if !known {
+            reachable[start + 1] = true;
+        }
+        if known || !attached {
+            reachable[(start + 2).min(args.len() + 1)] = true;
+        }
+    }
+    Subcommand { lines, config_option }
+}
+
+fn first_match<'a>(patterns: &'a [String], text: &str) -> Option<&'a str> {
+    patterns
+        .iter()
+        .find(|pattern| wildcard_match(pattern, text))
+        .map(String::as_str)
+}
+
+pub(crate) fn command_line(program: &str, args: &[String]) -> String {
+    std::iter::once(program)
+        .chain(args.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Resolve `..` and symlinks when the path exists
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    fn check_git(config: &SecurityConfig, line: &str) -> Result<CommandDecision> {
+        check(config, "git", &args(line), Path::new("."))
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("push*--force*", "push origin main --force"));
+        assert!(wildcard_match("LC_*", "LC_ALL"));
+        assert!(wildcard_match("?h", "sh"));
+        assert!(!wildcard_match("push*", "pull"));
+        assert!(!wildcard_match("*_API_KEY", "API_KEY_FILE"));
+    }
+
+    #[test]
+    fn test_default_git_rules() {
+        let config = SecurityConfig::default();
+
+        assert_eq!(check_git(&config, "status").unwrap(), CommandDecision::Allow);
+        assert!(matches!(check_git(&config, "push origin main").unwrap(), CommandDecision::Ask { .. }));
+
+        let error = check_git(&config, "push --force origin main").unwrap_err().to_string();
+        assert!(error.contains("security.commands.git.deny \"push*--force*\""), "{}", error);
+        assert!(check_git(&config, "push origin +main").is_err());
+        assert!(check(&config, "cargo", &args("publish"), Path::new(".")).is_err());
+    }
+
+    #[test]
+    fn test_global_options_before_subcommand() {
+        let config = SecurityConfig::default();
+
+        assert!(check_git(&config, "-C . push --force").is_err());
+        assert!(check_git(&config, "--git-dir=.git --no-pager push --delete origin main").is_err());
+        assert!(check(&config, "cargo", &args("+nightly publish"), Path::new(".")).is_err());
+        assert!(check(&config, "npm", &args("--registry https://registry.example publish"), Path::new(".")).is_err());
+        // Whether an unknown option takes a value or not, the push is seen
+        assert!(matches!(check_git(&config, "--frobnicate push origin").unwrap(), CommandDecision::Ask { .. }));
+
+        assert_eq!(check_git(&config, "-C . status").unwrap(), CommandDecision::Allow);
+        assert_eq!(check(&config, "cargo", &args("+nightly build"), Path::new(".")).unwrap(), CommandDecision::Allow);
+    }
+
+    #[test]
+    fn test_options_setting_configuration_are_refused() {
+        let config = SecurityConfig::default();
+        let cargo = |line: &str| check(&config, "cargo", &args(line), Path::new("."));
+
+        // Both reduce to the bare alias `p`, which no rule mentions
+        let alias = ["-c", "alias.p=push --force", "p"].map(String::from);
+        let error = check(&config, "git", &alias, Path::new(".")).unwrap_err().to_string();
+        assert!(error.contains("-c sets configuration"), "{}", error);
+        assert!(cargo("--config alias.p=\"publish\" p").is_err());
+        assert!(cargo("--config=alias.p=\"publish\" p").is_err());
+        assert!(check_git(&config, "-c core.sshCommand=/tmp/x fetch").is_err());
+        assert!(check_git(&config, "--config-env=core.pager=PAGER log").is_err());
+        // Behind an unknown option that might take it as its value
+        assert!(check_git(&config, "--frobnicate -c core.pager=less log").is_err());
+
+        // The same spelling after the subcommand is that subcommand's option
+        assert_eq!(check_git(&config, "commit -c HEAD").unwrap(), CommandDecision::Allow);
+    }
+
+    #[test]
+    fn test_approval_of_every_command() {
+        let config = SecurityConfig::preset(codev_shared::SecurityLevel::Paranoid);
+
+        assert_eq!(
+            check(&config, "cargo", &args("build"), Path::new(".")).unwrap(),
+            CommandDecision::Ask {
+                rule: "security.approval.commands".to_string()
+            }
+        );
+        assert!(check_git(&config, "push --force").is_err());
+        assert!(check(&config, "npm", &args("install"), Path::new(".")).is_err());
+    }
+
+    #[test]
+    fn test_allow_list_and_unknown_programs() {
+        let mut config = SecurityConfig::default();
+        config.commands.insert(
+            "python3".to_string(),
+            CommandRule {
+                allow: vec!["-m pytest*".to_string()],
+                ..CommandRule::default()
+            },
+        );
+
+        assert!(check(&config, "python3", &args("-m pytest -q"), Path::new(".")).is_ok());
+        let error = check(&config, "python3", &args("-c print(1)"), Path::new("."))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("matches none of security.commands.python3.allow"), "{}", error);
+
+        let error = check(&config, "curl", &[], Path::new(".")).unwrap_err().to_string();
+        assert!(error.contains("not in security.allowed_commands"), "{}", error);
+    }
+
+    #[test]
+    fn test_working_dirs() {
+        let workspace = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let mut config = SecurityConfig::default();
+        config.commands.get_mut("cargo").unwrap().working_dirs = vec![workspace.path().to_path_buf()];
+
+        let project = workspace.path().join("project");
+        std::fs::create_dir(&project).unwrap();
+        assert!(check(&config, "cargo", &args("build"), &project).is_ok());
+
+        let error = check(&config, "cargo", &args("build"), outside.path()).unwrap_err().to_string();
+        assert!(error.contains("outside security.commands.cargo.working_dirs"), "{}", error);
+        assert!(check(&config, "cargo", &args("build"), &project.join("..").join("..")).is_err());
+    }
+
+    #[test]
+    fn test_filter_env() {
+        let mut config = SecurityConfig::default();
+        config.commands.get_mut("cargo").unwrap().env_allow = vec!["SQLX_*".to_string()];
+        let vars = [
+            ("PATH", "/usr/bin"),
+            ("CARGO_HOME", "/cargo"),
+            ("SQLX_OFFLINE", "true"),
+            ("OPENAI_API_KEY", "sk-test"),
+            ("GITHUB_TOKEN", "ghp"),
+            ("EDITOR", "vim"),
+        ]
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+
+        let names = |env: Vec<(String, String)>| env.into_iter().map(|(name, _)| name).collect::<Vec<_>>();
+        assert_eq!(names(filter_env(&config, "cargo", vars.clone())), ["PATH", "CARGO_HOME", "SQLX_OFFLINE"]);
+        assert_eq!(names(filter_env(&config, "git", vars)), ["PATH", "CARGO_HOME"]);
+    }
+}
@@ -0,0 +1,498 @@
+//! File gateway
+//!
+//! Files read or written on behalf of the user or a model go through
+//! `FileGateway`, usually by way of `SecurityManager::read_file` and
+//! `write_file`; the engine's own files, such as the configuration and the
+//! audit log, do not. Paths are checked after resolving symlinks, so a link
+//! inside an allowed directory cannot reach outside it, and paths
+//! containing `..` are refused outright. Reads, writes and directory
+//! creation then walk the checked path without following symlinks, so a
+//! link swapped in after the check cannot redirect them.
+//! `security.file_access.forbidden_paths` win over the other lists; between
+//! `read_only_paths` and `write_allowed_paths` the most specific entry
+//! decides.
+
+use codev_shared::{FileAccessConfig, Result, SecurityError};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use tokio::io::AsyncReadExt;
+
+const KEY: &str = "security.file_access";
+
+/// Reads and writes files within `security.file_access`
+#[derive(Debug, Clone)]
+pub struct FileGateway {
+    forbidden: Vec<Entry>,
+    read_only: Vec<Entry>,
+    write_allowed: Vec<Entry>,
+    max_file_size: u64,
+    base: PathBuf,
+}
+
+/// A configured path and where it resolves to
+#[derive(Debug, Clone)]
+struct Entry {
+    rule: String,
+    path: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Read,
+    Write,
+}
+
+impl FileGateway {
+    /// Gateway resolving relative paths against the current directory
+    pub fn new(config: &FileAccessConfig) -> Self {
+        let base = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+        Self::with_base(config, base)
+    }
+
+    /// Gateway resolving relative paths against `base`
+    pub fn with_base(config: &FileAccessConfig, base: impl Into<PathBuf>) -> Self {
+        let base = real_path(&base.into());
+        let entries = |name: &str, paths: &[PathBuf]| -> Vec<Entry> {
+            paths
+                .iter()
+                .enumerate()
+                .map(|(index, path)| Entry {
+                    rule: format!("{}.{}[{}] \"{}\"", KEY, name, index, path.display()),
+                    path: real_path(&base.join(path)),
+                })
+                .collect()
+        };
+
+        Self {
+            forbidden: entries("forbidden_paths", &config.forbidden_paths),
+            read_only: entries("read_only_paths", &config.read_only_paths),
+            write_allowed: entries("write_allowed_paths", &config.write_allowed_paths),
+            max_file_size: config.max_file_size as u64,
+            base,
+        }
+    }
+
+    /// Where writing is allowed, with symlinks resolved
+    pub fn write_allowed_paths(&self) -> Vec<PathBuf> {
+        self.write_allowed.iter().map(|entry| entry.path.clone()).collect()
+    }
+
+    /// Paths that are read-only, with symlinks resolved
+    pub fn read_only_paths(&self) -> Vec<PathBuf> {
+        self.read_only.iter().map(|entry| entry.path.clone()).collect()
+    }
+
+    /// Check that `path` may be read and return where it resolves to
+    pub fn check_read(&self, path: &Path) -> Result<PathBuf> {
+        let real = self.check(path, Access::Read)?;
+        if let Ok(metadata) = std::fs::metadata(&real) {
+            if metadata.is_file() && metadata.len() > self.max_file_size {
+                return Err(self.too_large(path, metadata.len()));
+            }
+        }
+        Ok(real)
+    }
+
+    /// Check that `len` bytes may be written to `path` and return where it
+    /// resolves to
+    pub fn check_write(&self, path: &Path, len: u64) -> Result<PathBuf> {
+        let real = self.check(path, Access::Write)?;
+        if len > self.max_file_size {
+            return Err(self.too_large(path, len));
+        }
+        Ok(real)
+    }
+
+    /// Read a whole file
+    pub async fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        let real = self.check_read(path)?;
+        let file = tokio::task::spawn_blocking(move || open_no_follow(&real))
+            .await
+            .map_err(std::io::Error::other)?;
+        let file = tokio::fs::File::from_std(swapped_link(path, file)?);
+        let mut contents = Vec::new();
+        // The file may have grown since it was checked
+        file.take(self.max_file_size + 1)
+            .read_to_end(&mut contents)
+            .await?;
+        if contents.len() as u64 > self.max_file_size {
+            return Err(self.too_large(path, contents.len() as u64));
+        }
+        Ok(contents)
+    }
+
+    /// Read a whole file as UTF-8
+    pub async fn read_to_string(&self, path: &Path) -> Result<String> {
+        let contents = self.read(path).await?;
+        String::from_utf8(contents).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e).into())
+    }
+
+    /// Create or replace a file
+    pub async fn write(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        let real = self.check_write(path, contents.len() as u64)?;
+        let contents = contents.to_vec();
+        let written = tokio::task::spawn_blocking(move || create_no_follow(&real)?.write_all(&contents))
+            .await
+            .map_err(std::io::Error::other)?;
+        swapped_link(path, written)
+    }
+
+    /// Create a directory and its missing parents
+    pub async fn create_dir_all(&self, path: &Path) -> Result<()> {
+        let real = self.check_write(path, 0)?;
+        let created = tokio::task::spawn_blocking(move || create_dir_all_no_follow(&real))
+            .await
+            .map_err(std::io::Error::other)?;
+        swapped_link(path, created)
+    }
+
+    fn check(&self, path: &Path, access: Access) -> Result<PathBuf> {
+        if path.components().any(|c| c == Component::ParentDir) {
+            return Err(denied(path, "path contains \"..\"".to_string()));
+        }
+
+        let absolute = self.base.join(path);
+        // A link that exists but whose target does not cannot be followed
+        // safely: writing through it would create a file wherever it points
+        if std::fs::symlink_metadata(&absolute).is_ok() && absolute.canonicalize().is_err() {
+            return Err(denied(path, "symlink to a missing target".to_string()));
+        }
+        let real = real_path(&absolute);
+        let deny = |rule: &str| {
+            if real == absolute {
+                denied(path, rule.to_string())
+            } else {
+                denied(path, format!("resolves to {}, {}", real.display(), rule))
+            }
+        };
+
+        if let Some(entry) = self.forbidden.iter().find(|entry| real.starts_with(&entry.path)) {
+            return Err(deny(&entry.rule));
+        }
+        if access == Access::Read {
+            return Ok(real);
+        }
+
+        let read_only = most_specific(&self.read_only, &real);
+        let write_allowed = most_specific(&self.write_allowed, &real);
+        match (read_only, write_allowed) {
+            (Some(read_only), Some(write_allowed)) if depth(write_allowed) > depth(read_only) => Ok(real),
+            (Some(read_only), _) => Err(deny(&read_only.rule)),
+            (None, Some(_)) => Ok(real),
+            (None, None) => Err(deny(&format!("outside {}.write_allowed_paths", KEY))),
+        }
+    }
+
+    fn too_large(&self, path: &Path, len: u64) -> codev_shared::CodevError {
+        denied(
+            path,
+            format!("{} bytes is over {}.max_file_size of {}", len, KEY, self.max_file_size),
+        )
+    }
+}
+
+fn denied(path: &Path, rule: String) -> codev_shared::CodevError {
+    SecurityError::FileAccessDenied {
+        path: path.display().to_string(),
+        rule,
+    }
+    .into()
+}
+
+/// Report a symlink met while walking a checked path as a denial
+fn swapped_link<T>(path: &Path, result: std::io::Result<T>) -> Result<T> {
+    match result {
+        #[cfg(unix)]
+        Err(e) if e.raw_os_error() == Some(libc::ELOOP) => {
+            Err(denied(path, "a symlink appeared after the check".to_string()))
+        }
+        result => Ok(result?),
+    }
+}
+
+fn most_specific<'a>(entries: &'a [Entry], path: &Path) -> Option<&'a Entry> {
+    entries
+        .iter()
+        .filter(|entry| path.starts_with(&entry.path))
+        .max_by_key(|entry| depth(entry))
+}
+
+fn depth(entry: &Entry) -> usize {
+    entry.path.components().count()
+}
+
+/// Open the directory at a checked path without following a symlink in
+/// any of its components
+///
+/// The path has no symlinks left, so walking down from the root with
+/// `O_NOFOLLOW` only fails if one was put in since.
+#[cfg(unix)]
+fn open_dir_no_follow(path: &Path) -> std::io::Result<std::os::fd::OwnedFd> {
+    use std::os::fd::AsRawFd;
+
+    let root = std::ffi::OsStr::new("/");
+    let mut dir = open_at(libc::AT_FDCWD, root, libc::O_DIRECTORY | libc::O_RDONLY)?;
+    for component in path.components() {
+        if let Component::Normal(component) = component {
+            dir = open_at(dir.as_raw_fd(), component, libc::O_DIRECTORY | libc::O_RDONLY | libc::O_NOFOLLOW)?;
+        }
+    }
+    Ok(dir)
+}
+
+/// Open a file at a checked path with `flags`, following no symlink
+#[cfg(unix)]
+fn open_file_no_follow(path: &Path, flags: libc::c_int) -> std::io::Result<std::fs::File> {
+    use std::os::fd::AsRawFd;
+
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(std::io::ErrorKind::InvalidInput.into());
+    };
+    let dir = open_dir_no_follow(parent)?;
+    Ok(open_at(dir.as_raw_fd(), name, flags | libc::O_NOFOLLOW)?.into())
+}
+
+/// Open a checked path for reading without following a symlink
+#[cfg(unix)]
+fn open_no_follow(path: &Path) -> std::io::Result<std::fs::File> {
+    open_file_no_follow(path, libc::O_RDONLY)
+}
+
+/// Open a checked path for writing, creating it, without following a
+/// symlink
+#[cfg(unix)]
+fn create_no_follow(path: &Path) -> std::io::Result<std::fs::File> {
+    open_file_no_follow(path, libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC)
+}
+
+/// Create a checked directory and its missing parents without following a
+/// symlink
+#[cfg(unix)]
+fn create_dir_all_no_follow(path: &Path) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let root = std::ffi::OsStr::new("/");
+    let mut dir = open_at(libc::AT_FDCWD, root, libc::O_DIRECTORY | libc::O_RDONLY)?;
+    for component in path.components() {
+        let Component::Normal(component) = component else { continue };
+        let flags = libc::O_DIRECTORY | libc::O_RDONLY | libc::O_NOFOLLOW;
+        dir = match open_at(dir.as_raw_fd(), component, flags) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let name = c_name(component)?;
+                // SAFETY: `dir` is an open directory and `name` a C string
+                if unsafe { libc::mkdirat(dir.as_raw_fd(), name.as_ptr(), 0o777) } < 0 {
+                    let e = std::io::Error::last_os_error();
+                    if e.kind() != std::io::ErrorKind::AlreadyExists {
+                        return Err(e);
+                    }
+                }
+                open_at(dir.as_raw_fd(), component, flags)?
+            }
+            result => result?,
+        };
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn open_at(
+    dir: libc::c_int,
+    name: &std::ffi::OsStr,
+    flags: libc::c_int,
+) -> std::io::Result<std::os::fd::OwnedFd> {
+    use std::os::fd::FromRawFd;
+
+    let name = c_name(name)?;
+    let fd = unsafe { libc::openat(dir, name.as_ptr(), flags | libc::O_CLOEXEC, 0o666 as libc::c_uint) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: `openat` returned a new descriptor that nothing else owns
+    Ok(unsafe { std::os::fd::OwnedFd::from_raw_fd(fd) })
+}
+
+#[cfg(unix)]
+fn c_name(name: &std::ffi::OsStr) -> std::io::Result<std::ffi::CString> {
+    use std::os::unix::ffi::OsStrExt;
+
+    std::ffi::CString::new(name.as_bytes()).map_err(|_| std::io::ErrorKind::InvalidInput.into())
+}
+
+#[cfg(not(unix))]
+fn open_no_follow(path: &Path) -> std::io::Result<std::fs::File> {
+    std::fs::File::open(path)
+}
+
+#[cfg(not(unix))]
+fn create_no_follow(path: &Path) -> std::io::Result<std::fs::File> {
+    std::fs::File::create(path)
+}
+
+#[cfg(not(unix))]
+fn create_dir_all_no_follow(path: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(path)
+}
+
+/// Resolve symlinks in the part of `path` that exists and append the rest
+fn real_path(path: &Path) -> PathBuf {
+    let mut existing = path;
+    let mut rest = Vec::new();
+    loop {
+        if let Ok(mut real) = existing.canonicalize() {
+            for component in rest.iter().rev() {
+                match component {
+                    Component::ParentDir => {
+                        real.pop();
+                    }
+                    Component::Normal(name) => real.push(name),
+                    _ => {}
+                }
+            }
+            return real;
+        }
+        match (existing.parent(), existing.components().next_back()) {
+            (Some(parent), Some(last)) => {
+                rest.push(last);
+                existing = parent;
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixture {
+        root: tempfile::TempDir,
+        gateway: FileGateway,
+    }
+
+    /// `root/project` is writable except for `root/project/vendor`, which
+    /// is read-only except for `root/project/vendor/patches`;
+    /// `root/project/secrets` is forbidden and `root/outside` is readable
+    fn fixture() -> Fixture {
+        let root = tempfile::tempdir().unwrap();
+        for dir in ["project/vendor/patches", "project/secrets", "outside"] {
+            std::fs::create_dir_all(root.path().join(dir)).unwrap();
+        }
+        let config = FileAccessConfig {
+            read_only_paths: vec![PathBuf::from("project/vendor")],
+            write_allowed_paths: vec![PathBuf::from("project"), PathBuf::from("project/vendor/patches")],
+            forbidden_paths: vec![PathBuf::from("project/secrets")],
+            max_file_size: 16,
+        };
+        let gateway = FileGateway::with_base(&config, root.path());
+        Fixture { root, gateway }
+    }
+
+    fn error(result: Result<PathBuf>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_precedence_between_lists() {
+        let Fixture { root: _root, gateway } = fixture();
+        let write = |path: &str| gateway.check_write(Path::new(path), 1);
+
+        assert!(write("project/src/main.rs").is_ok());
+        assert!(write("project/vendor/patches/fix.patch").is_ok());
+        assert!(gateway.check_read(Path::new("project/vendor/lib.rs")).is_ok());
+
+        let message = error(write("project/vendor/lib.rs"));
+        assert!(message.contains("security.file_access.read_only_paths[0] \"project/vendor\""), "{}", message);
+        let message = error(gateway.check_read(Path::new("project/secrets/key")));
+        assert!(message.contains("security.file_access.forbidden_paths[0]"), "{}", message);
+        let message = error(write("outside/file"));
+        assert!(message.contains("outside security.file_access.write_allowed_paths"), "{}", message);
+    }
+
+    #[test]
+    fn test_parent_components_are_refused() {
+        let Fixture { root: _root, gateway } = fixture();
+
+        let message = error(gateway.check_read(Path::new("project/../outside/file")));
+        assert!(message.contains("path contains \"..\""), "{}", message);
+        assert!(gateway.check_write(Path::new("project/src/../../outside/file"), 1).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_escapes() {
+        let Fixture { root, gateway } = fixture();
+        let project = root.path().join("project");
+        std::os::unix::fs::symlink(root.path().join("outside"), project.join("out")).unwrap();
+        std::os::unix::fs::symlink(project.join("secrets"), project.join("keys")).unwrap();
+        std::os::unix::fs::symlink(root.path().join("outside/missing"), project.join("dangling")).unwrap();
+
+        let message = error(gateway.check_write(Path::new("project/out/file"), 1));
+        assert!(message.contains("resolves to"), "{}", message);
+        assert!(message.contains("outside security.file_access.write_allowed_paths"), "{}", message);
+        let message = error(gateway.check_read(Path::new("project/keys/key")));
+        assert!(message.contains("forbidden_paths[0]"), "{}", message);
+        let message = error(gateway.check_write(Path::new("project/dangling"), 1));
+        assert!(message.contains("symlink to a missing target"), "{}", message);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_write_does_not_follow_swapped_symlinks() {
+        let Fixture { root, gateway } = fixture();
+        gateway.write(Path::new("project/notes"), b"ok").await.unwrap();
+        assert_eq!(std::fs::read(root.path().join("project/notes")).unwrap(), b"ok");
+
+        // Links put in place between the check and the write
+        let file = gateway.check_write(Path::new("project/file"), 1).unwrap();
+        std::os::unix::fs::symlink(root.path().join("outside/target"), &file).unwrap();
+        assert!(create_no_follow(&file).is_err());
+        let nested = gateway.check_write(Path::new("project/dir/file"), 1).unwrap();
+        std::os::unix::fs::symlink(root.path().join("outside"), root.path().join("project/dir")).unwrap();
+        assert!(create_no_follow(&nested).is_err());
+        assert!(!root.path().join("outside/target").exists());
+        assert!(!root.path().join("outside/file").exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_read_and_mkdir_do_not_follow_swapped_symlinks() {
+        let Fixture { root, gateway } = fixture();
+        std::fs::write(root.path().join("project/secrets/key"), "key").unwrap();
+
+        // Links into a forbidden directory put in place after the check
+        let file = gateway.check_read(Path::new("project/notes")).unwrap();
+        std::os::unix::fs::symlink(root.path().join("project/secrets/key"), &file).unwrap();
+        assert!(open_no_follow(&file).is_err());
+        let nested = gateway.check_read(Path::new("project/link/key")).unwrap();
+        let link = root.path().join("project/link");
+        std::os::unix::fs::symlink(root.path().join("project/secrets"), link).unwrap();
+        assert!(open_no_follow(&nested).is_err());
+
+        let dir = gateway.check_write(Path::new("project/made/deeper"), 0).unwrap();
+        let link = root.path().join("project/made");
+        std::os::unix::fs::symlink(root.path().join("outside"), link).unwrap();
+        assert!(create_dir_all_no_follow(&dir).is_err());
+        assert!(!root.path().join("outside/deeper").exists());
+
+        let dir = gateway.check_write(Path::new("project/fresh/deeper"), 0).unwrap();
+        create_dir_all_no_follow(&dir).unwrap();
+        create_dir_all_no_follow(&dir).unwrap();
+        assert!(root.path().join("project/fresh/deeper").is_dir());
+    }
+
+    #[tokio::test]
+    async fn test_size_limits_and_io() {
+        let Fixture { root, gateway } = fixture();
+        let file = Path::new("project/new/notes.txt");
+
+        gateway.create_dir_all(Path::new("project/new")).await.unwrap();
+        gateway.write(file, b"short").await.unwrap();
+        assert_eq!(gateway.read_to_string(file).await.unwrap(), "short");
+
+        let message = gateway.write(file, &[b'x'; 17]).await.unwrap_err().to_string();
+        assert!(message.contains("17 bytes is over security.file_access.max_file_size of 16"), "{}", message);
+
+        std::fs::write(root.path().join("outside/big"), [b'x'; 32]).unwrap();
+        assert!(gateway.read(Path::new("outside/big")).await.is_err());
+    }
+}
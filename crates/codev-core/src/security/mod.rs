@@ -0,0 +1,280 @@
+//! Security manager
+//!
+//! Holds the policy that commands and file accesses are checked against.
+//! The policy can be replaced while running; checks already in progress
+//! finish against the policy they started with. Decisions and the actions
+//! that follow are recorded in the audit log; an action whose decision
+//! cannot be recorded is refused.
+
+mod commands;
+mod files;
+mod sandbox;
+
+pub use commands::CommandDecision;
+pub use files::FileGateway;
+pub use sandbox::Sandbox;
+
+pub(crate) use commands::wildcard_match;
+
+use crate::audit::{sha256_hex, AuditEvent, AuditLog, Decision};
+use crate::engine::ComponentHealth;
+use codev_shared::{CommandResult, Result, SecurityConfig, SecurityError, SecurityLevel};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use tracing::info;
+
+/// Security policy derived from `SecurityConfig`
+#[derive(Debug, Clone)]
+pub struct SecurityPolicy {
+    config: SecurityConfig,
+}
+
+impl SecurityPolicy {
+    /// Build a policy from configuration
+    pub fn from_config(config: &SecurityConfig) -> Result<Self> {
+        Ok(Self {
+            config: config.clone(),
+        })
+    }
+
+    /// Security level the policy enforces
+    pub fn level(&self) -> SecurityLevel {
+        self.config.default_level
+    }
+
+    /// Configuration the policy was built from
+    pub fn config(&self) -> &SecurityConfig {
+        &self.config
+    }
+
+    /// Check a command against the allowlist and its rule
+    ///
+    /// Refusals are `SecurityError::CommandNotAllowed` naming the rule that
+    /// matched. `CommandDecision::Ask` means the user must confirm first.
+    pub fn check_command(&self, program: &str, args: &[String], working_dir: &Path) -> Result<CommandDecision> {
+        commands::check(&self.config, program, args, working_dir)
+    }
+
+    /// Environment variables a command may see, out of `vars`
+    pub fn command_env(
+        &self,
+        program: &str,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Vec<(String, String)> {
+        commands::filter_env(&self.config, program, vars)
+    }
+
+    /// Runner for commands that passed the policy
+    ///
+    /// Sandboxed commands may only write where `file_access` allows it.
+    pub fn sandbox(&self) -> Sandbox {
+        let files = self.files();
+        Sandbox::new(&self.config.sandbox, self.config.sandbox_enabled)
+            .with_paths(files.write_allowed_paths(), files.read_only_paths())
+    }
+
+    /// Gateway for reading and writing files under `file_access`
+    pub fn files(&self) -> FileGateway {
+        FileGateway::new(&self.config.file_access)
+    }
+}
+
+/// Enforces the security policy for the rest of the engine
+pub struct SecurityManager {
+    policy: RwLock<Arc<SecurityPolicy>>,
+    audit_log: Option<Arc<AuditLog>>,
+}
+
+impl SecurityManager {
+    /// Create the security manager from configuration
+    pub fn new(config: &SecurityConfig) -> Result<Self> {
+        Ok(Self {
+            policy: RwLock::new(Arc::new(SecurityPolicy::from_config(config)?)),
+            audit_log: None,
+        })
+    }
+
+    /// Record decisions and actions in `log`
+    pub fn with_audit_log(mut self, log: Option<Arc<AuditLog>>) -> Self {
+        self.audit_log = log;
+        self
+    }
+
+    /// Current policy
+    ///
+    /// Callers keep the returned policy for the whole operation so that a
+    /// reload cannot change the rules halfway through.
+    pub fn policy(&self) -> Arc<SecurityPolicy> {
+        self.policy.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Replace the policy with one built from `config`
+    ///
+    /// The old policy stays in force if the configuration is rejected.
+    pub fn update_policy(&self, config: &SecurityConfig) -> Result<()> {
+        self.set_policy(SecurityPolicy::from_config(config)?);
+        Ok(())
+    }
+
+    /// Replace the policy
+    pub fn set_policy(&self, policy: SecurityPolicy) {
+        info!("Security policy updated (level {:?})", policy.level());
+        *self.policy.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(policy);
+    }
+
+    /// Check a command against the policy and run it
+    ///
+    /// The command sees only the environment variables the policy allows.
+    /// Commands matching an `ask` rule are refused unless `confirmed`.
+    pub async fn run_command(
+        &self,
+        program: &str,
+        args: &[String],
+        working_dir: &Path,
+        confirmed: bool,
+    ) -> Result<CommandResult> {
+        let policy = self.policy();
+        let command = commands::command_line(program, args);
+        let checked = match policy.check_command(program, args, working_dir) {
+            Ok(CommandDecision::Ask { rule }) if !confirmed => Err(SecurityError::CommandNotAllowed {
+                command: command.clone(),
+                rule: format!("needs confirmation, matches {}", rule),
+            }
+            .into()),
+            checked => checked,
+        };
+        let (decision, rule) = match &checked {
+            Ok(CommandDecision::Allow) => (Decision::Allow, None),
+            Ok(CommandDecision::Ask { rule }) => (Decision::Confirmed, Some(rule.clone())),
+            Err(e) => (Decision::Deny, Some(e.to_string())),
+        };
+        self.record(AuditEvent::PolicyDecision {
+            action: format!("run {}", command),
+            decision,
+            rule,
+        })?;
+        checked?;
+
+        let env = std::env::vars_os().filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)));
+        let env = policy.command_env(program, env);
+        let result = policy.sandbox().run(program, args, working_dir, env).await;
+
+        if let Some(log) = &self.audit_log {
+            log.record_or_warn(AuditEvent::Command {
+                program: program.to_string(),
+                args: args.to_vec(),
+                working_dir: working_dir.to_path_buf(),
+                exit_code: result.as_ref().ok().map(|result| result.exit_code),
+                error: result.as_ref().err().map(ToString::to_string),
+            });
+        }
+        result
+    }
+
+    /// Read a file the policy allows reading
+    pub async fn read_file(&self, path: &Path) -> Result<Vec<u8>> {
+        self.policy().files().read(path).await
+    }
+
+    /// Write a file the policy allows writing
+    ///
+    /// With `approval.file_writes`, writes are refused unless `confirmed`.
+    pub async fn write_file(&self, path: &Path, contents: &[u8], confirmed: bool) -> Result<()> {
+        let policy = self.policy();
+        let files = policy.files();
+        let checked = files.check_write(path, contents.len() as u64).and_then(|_| {
+            if policy.config.approval.file_writes && !confirmed {
+                return Err(SecurityError::FileAccessDenied {
+                    path: path.display().to_string(),
+                    rule: "needs confirmation, security.approval.file_writes".to_string(),
+                }
+                .into());
+            }
+            Ok(())
+        });
+        let (decision, rule) = match &checked {
+            Ok(()) if policy.config.approval.file_writes => {
+                (Decision::Confirmed, Some("security.approval.file_writes".to_string()))
+            }
+            Ok(()) => (Decision::Allow, None),
+            Err(e) => (Decision::Deny, Some(e.to_string())),
+        };
+        self.record(AuditEvent::PolicyDecision {
+            action: format!("write {}", path.display()),
+            decision,
+            rule,
+        })?;
+        checked?;
+
+        files.write(path, contents).await?;
+        if let Some(log) = &self.audit_log {
+            log.record_or_warn(AuditEvent::FileWrite {
+                path: path.to_path_buf(),
+                bytes: contents.len() as u64,
+                sha256: sha256_hex(contents),
+            });
+        }
+        Ok(())
+    }
+
+    /// Record a decision before acting on it
+    fn record(&self, event: AuditEvent) -> Result<()> {
+        match &self.audit_log {
+            Some(log) => log.record(event),
+            None => Ok(()),
+        }
+    }
+
+    /// Check the health of the security manager
+    pub async fn health_check(&self) -> ComponentHealth {
+        ComponentHealth::Healthy
+    }
+
+    /// Stop background work
+    pub async fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_policy_keeps_earlier_snapshots() {
+        let manager = SecurityManager::new(&SecurityConfig::default()).unwrap();
+        let before = manager.policy();
+
+        let config = SecurityConfig {
+            allowed_commands: vec!["git".to_string()],
+            ..SecurityConfig::default()
+        };
+        manager.update_policy(&config).unwrap();
+
+        let here = Path::new(".");
+        assert!(before.check_command("cargo", &[], here).is_ok());
+        assert!(manager.policy().check_command("cargo", &[], here).is_err());
+        assert!(manager.policy().check_command("git", &[], here).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_actions_are_audited() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = Arc::new(AuditLog::open(dir.path().join("audit.jsonl")).unwrap());
+        let mut config = SecurityConfig::default();
+        config.file_access.write_allowed_paths = vec![dir.path().to_path_buf()];
+        let manager = SecurityManager::new(&config).unwrap().with_audit_log(Some(log.clone()));
+
+        let args = ["push".to_string(), "--force".to_string()];
+        assert!(manager.run_command("git", &args, dir.path(), true).await.is_err());
+        manager.write_file(&dir.path().join("notes.txt"), b"hi", false).await.unwrap();
+
+        let records = AuditLog::read(log.path(), None).unwrap();
+        let events: Vec<String> = records.iter().map(|record| record.event.to_string()).collect();
+        assert_eq!(events.len(), 3, "{:?}", events);
+        assert!(events[0].starts_with("denied run git push --force ("), "{}", events[0]);
+        assert!(events[1].starts_with("allowed write "), "{}", events[1]);
+        assert!(events[2].ends_with("notes.txt (2 bytes)"), "{}", events[2]);
+        assert_eq!(AuditLog::verify(log.path()).unwrap().records, 3);
+    }
+}
@@ -0,0 +1,587 @@
+//! Sandboxed command execution
+//!
+//! On Linux a sandboxed command gets its own user, mount and, unless
+//! `network_access` is set, network namespace. The file system is mounted
+//! read-only apart from the writable paths of `security.file_access`, `/tmp`
+//! is replaced by an empty tmpfs or by `temp_dir`, resource limits cap
+//! memory and CPU time, and a seccomp filter refuses syscalls that builds
+//! and tests never need.
+//! When `user_id` or `group_id` is set CoDev must run as root, and the
+//! command runs as that user instead of in a user namespace.
+//!
+//! The sandbox fails closed: if it cannot be set up the command does not run.
+
+use codev_shared::{CodevError, CommandResult, Result, SandboxConfig, SecurityError};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+/// Runs commands under `SandboxConfig`
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    config: SandboxConfig,
+    enabled: bool,
+    write_paths: Vec<PathBuf>,
+    read_only_paths: Vec<PathBuf>,
+}
+
+impl Sandbox {
+    /// Create a runner; when `enabled` is false commands only get the timeout
+    ///
+    /// Commands cannot write anywhere but `/tmp` until writable paths are
+    /// given with `with_paths`.
+    pub fn new(config: &SandboxConfig, enabled: bool) -> Self {
+        Self {
+            config: config.clone(),
+            enabled,
+            write_paths: Vec::new(),
+            read_only_paths: Vec::new(),
+        }
+    }
+
+    /// Keep `write` writable, except for the `read_only` paths inside them
+    pub fn with_paths(mut self, write: Vec<PathBuf>, read_only: Vec<PathBuf>) -> Self {
+        self.write_paths = write;
+        self.read_only_paths = read_only;
+        self
+    }
+
+    /// Run a command with exactly the environment in `env`
+    ///
+    /// Exceeding `max_cpu_time` is `SecurityError::ExecutionTimeout` and
+    /// running out of memory under `max_memory` is
+    /// `SecurityError::MemoryLimitExceeded`; other failures are returned as
+    /// an unsuccessful `CommandResult`.
+    pub async fn run(
+        &self,
+        program: &str,
+        args: &[String],
+        working_dir: &Path,
+        env: Vec<(String, String)>,
+    ) -> Result<CommandResult> {
+        let mut command = tokio::process::Command::new(program);
+        command
+            .args(args)
+            .current_dir(working_dir)
+            .env_clear()
+            .envs(env)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        if self.enabled {
+            self.confine(&mut command)?;
+        }
+
+        let child = command.spawn().map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => CodevError::CommandExecution {
+                command: program.to_string(),
+                error: e.to_string(),
+            },
+            _ if self.enabled => SecurityError::SandboxUnavailable {
+                reason: format!("could not start {}: {}", program, e),
+            }
+            .into(),
+            _ => e.into(),
+        })?;
+
+        // Dropping the future on timeout kills the child
+        let output = match self.config.max_cpu_time {
+            Some(seconds) => tokio::time::timeout(Duration::from_secs(seconds), child.wait_with_output())
+                .await
+                .map_err(|_| SecurityError::ExecutionTimeout)??,
+            None => child.wait_with_output().await?,
+        };
+
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        let exit_code = match output.status.code() {
+            Some(code) => code,
+            None => {
+                let signal = signal(&output.status);
+                if self.enabled && signal == Some(SIGXCPU) {
+                    return Err(SecurityError::ExecutionTimeout.into());
+                }
+                128 + signal.unwrap_or(0)
+            }
+        };
+
+        if exit_code != 0 && self.enabled && self.config.max_memory.is_some() && out_of_memory(&stderr) {
+            return Err(SecurityError::MemoryLimitExceeded.into());
+        }
+        Ok(CommandResult::new(stdout, stderr, exit_code))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn confine(&self, command: &mut tokio::process::Command) -> Result<()> {
+        let confinement = linux::Confinement::prepare(&self.config, &self.write_paths, &self.read_only_paths)?;
+        // SAFETY: `apply` only makes syscalls on data prepared above; it
+        // does not allocate or take locks in the forked child.
+        unsafe {
+            command.pre_exec(move || confinement.apply());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn confine(&self, _command: &mut tokio::process::Command) -> Result<()> {
+        Err(SecurityError::SandboxUnavailable {
+            reason: "sandboxing is only supported on Linux".to_string(),
+        }
+        .into())
+    }
+}
+
+const SIGXCPU: i32 = 24;
+
+#[cfg(unix)]
+fn signal(status: &std::process::ExitStatus) -> Option<i32> {
+    std::os::unix::process::ExitStatusExt::signal(status)
+}
+
+#[cfg(not(unix))]
+fn signal(_status: &std::process::ExitStatus) -> Option<i32> {
+    None
+}
+
+/// Whether a failed command reports an allocation failure
+///
+/// Hitting the data limit makes allocations fail rather than killing the
+/// process, so this is recognised from the runtimes' messages.
+fn out_of_memory(stderr: &str) -> bool {
+    const MESSAGES: &[&str] = &[
+        "memory allocation of",
+        "Cannot allocate memory",
+        "out of memory",
+        "MemoryError",
+        "std::bad_alloc",
+        "heap out of memory",
+    ];
+    MESSAGES.iter().any(|message| stderr.contains(message))
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use codev_shared::{Result, SandboxConfig, SecurityError};
+    use seccompiler::{BpfProgram, SeccompAction, SeccompFilter, TargetArch};
+    use std::collections::BTreeMap;
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+
+    #[cfg(target_env = "gnu")]
+    type Resource = libc::__rlimit_resource_t;
+    #[cfg(not(target_env = "gnu"))]
+    type Resource = libc::c_int;
+
+    /// Syscalls refused with `EPERM`
+    const DENIED_SYSCALLS: &[libc::c_long] = &[
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_chroot,
+        libc::SYS_unshare,
+        libc::SYS_setns,
+        libc::SYS_kexec_load,
+        libc::SYS_kexec_file_load,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_reboot,
+        libc::SYS_swapon,
+        libc::SYS_swapoff,
+        libc::SYS_bpf,
+        libc::SYS_perf_event_open,
+        libc::SYS_keyctl,
+        libc::SYS_add_key,
+        libc::SYS_request_key,
+        libc::SYS_userfaultfd,
+        libc::SYS_open_by_handle_at,
+        libc::SYS_acct,
+        libc::SYS_syslog,
+    ];
+
+    /// Everything the child needs, built before forking
+    pub(super) struct Confinement {
+        namespaces: libc::c_int,
+        /// `uid_map` and `gid_map` contents when using a user namespace
+        id_maps: Option<(String, String)>,
+        /// Ids to switch to when running as root
+        switch_to: (Option<libc::gid_t>, Option<libc::uid_t>),
+        temp_dir: Option<CString>,
+        /// Paths bound onto themselves before the rest is made read-only
+        writable: Vec<CString>,
+        /// Mount points to remount read-only, with the flags to keep
+        read_only_mounts: Vec<(CString, libc::c_ulong)>,
+        /// Read-only paths inside writable ones, with the flags to keep
+        read_only_paths: Vec<(CString, libc::c_ulong)>,
+        limits: Vec<(Resource, libc::rlim_t, libc::rlim_t)>,
+        filter: BpfProgram,
+    }
+
+    impl Confinement {
+        pub(super) fn prepare(config: &SandboxConfig, write: &[PathBuf], read_only: &[PathBuf]) -> Result<Self> {
+            let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+            let switch = config.user_id.is_some() || config.group_id.is_some();
+            if switch && uid != 0 {
+                return Err(unavailable("sandbox.user_id and sandbox.group_id require running as root"));
+            }
+
+            let mut namespaces = libc::CLONE_NEWNS;
+            if !config.network_access {
+                namespaces |= libc::CLONE_NEWNET;
+            }
+            // Root can create the other namespaces itself and then drops to
+            // the configured ids; everyone else needs a user namespace
+            let id_maps = if switch {
+                None
+            } else {
+                namespaces |= libc::CLONE_NEWUSER;
+                Some((format!("{} {} 1\n", uid, uid), format!("{} {} 1\n", gid, gid)))
+            };
+
+            let temp_dir = match &config.temp_dir {
+                Some(dir) => Some(
+                    CString::new(dir.as_os_str().as_bytes())
+                        .map_err(|_| unavailable("sandbox.temp_dir contains a NUL byte"))?,
+                ),
+                None => None,
+            };
+
+            // `/tmp` is mounted over, so what is below it stays as it is
+            let write: Vec<&PathBuf> = write.iter().filter(|path| path.exists()).collect();
+            let kept = |path: &Path| path.starts_with("/tmp") || write.iter().any(|dir| path.starts_with(dir));
+            let read_only_mounts = mount_points()
+                .map_err(|e| unavailable(&format!("cannot list mounts: {}", e)))?
+                .into_iter()
+                .filter(|point| !kept(point))
+                .map(|point| Ok((c_path(&point)?, mount_flags(&point))))
+                .collect::<Result<_>>()?;
+            let read_only_paths = read_only
+                .iter()
+                .filter(|path| path.exists() && kept(path) && !path.starts_with("/tmp"))
+                .map(|path| Ok((c_path(path)?, mount_flags(path))))
+                .collect::<Result<_>>()?;
+            let writable = write.iter().map(|path| c_path(path)).collect::<Result<_>>()?;
+
+            // Unlike the address space, the data limit leaves room for the
+            // large reservations that runtimes make without using them
+            let mut limits = vec![(libc::RLIMIT_CORE, 0, 0)];
+            if let Some(bytes) = config.max_memory {
+                limits.push((libc::RLIMIT_DATA, bytes as libc::rlim_t, bytes as libc::rlim_t));
+            }
+            if let Some(seconds) = config.max_cpu_time {
+                // SIGXCPU at the soft limit, SIGKILL a second later
+                let seconds = seconds as libc::rlim_t;
+                limits.push((libc::RLIMIT_CPU, seconds, seconds + 1));
+            }
+
+            Ok(Self {
+                namespaces,
+                id_maps,
+                switch_to: (config.group_id, config.user_id),
+                temp_dir,
+                writable,
+                read_only_mounts,
+                read_only_paths,
+                limits,
+                filter: seccomp_filter()?,
+            })
+        }
+
+        /// Runs in the forked child before `exec`
+        pub(super) fn apply(&self) -> io::Result<()> {
+            for (resource, soft, hard) in &self.limits {
+                let limit = libc::rlimit {
+                    rlim_cur: *soft,
+                    rlim_max: *hard,
+                };
+                check(unsafe { libc::setrlimit(*resource, &limit) })?;
+            }
+
+            check(unsafe { libc::unshare(self.namespaces) })?;
+            if let Some((uid_map, gid_map)) = &self.id_maps {
+                write_file(c"/proc/self/setgroups", b"deny")?;
+                write_file(c"/proc/self/uid_map", uid_map.as_bytes())?;
+                write_file(c"/proc/self/gid_map", gid_map.as_bytes())?;
+            }
+
+            // Keep mounts made here out of the parent namespace
+            check(unsafe {
+                libc::mount(
+                    std::ptr::null(),
+                    c"/".as_ptr(),
+                    std::ptr::null(),
+                    libc::MS_REC | libc::MS_PRIVATE,
+                    std::ptr::null(),
+                )
+            })?;
+            for path in &self.writable {
+                bind(path)?;
+            }
+            check(unsafe {
+                match &self.temp_dir {
+                    Some(dir) => libc::mount(
+                        dir.as_ptr(),
+                        c"/tmp".as_ptr(),
+                        std::ptr::null(),
+                        libc::MS_BIND | libc::MS_REC,
+                        std::ptr::null(),
+                    ),
+                    None => libc::mount(
+                        c"tmpfs".as_ptr(),
+                        c"/tmp".as_ptr(),
+                        c"tmpfs".as_ptr(),
+                        libc::MS_NOSUID | libc::MS_NODEV,
+                        std::ptr::null(),
+                    ),
+                }
+            })?;
+
+            // Mount points the command cannot reach need not be changed
+            for (point, flags) in &self.read_only_mounts {
+                match remount_read_only(point, *flags) {
+                    Err(e) if matches!(e.raw_os_error(), Some(libc::ENOENT | libc::EACCES)) => {}
+                    result => result?,
+                }
+            }
+            for (path, flags) in &self.read_only_paths {
+                bind(path)?;
+                remount_read_only(path, *flags)?;
+            }
+
+            let (gid, uid) = self.switch_to;
+            if gid.is_some() || uid.is_some() {
+                check(unsafe { libc::setgroups(0, std::ptr::null()) })?;
+            }
+            if let Some(gid) = gid {
+                check(unsafe { libc::setgid(gid) })?;
+            }
+            if let Some(uid) = uid {
+                check(unsafe { libc::setuid(uid) })?;
+            }
+
+            // Last, since it also forbids the mounts above
+            // Formatting the error would allocate, which is not safe here
+            seccompiler::apply_filter(&self.filter).map_err(|_| io::Error::last_os_error())
+        }
+    }
+
+    fn bind(path: &CString) -> io::Result<()> {
+        check(unsafe {
+            libc::mount(
+                path.as_ptr(),
+                path.as_ptr(),
+                std::ptr::null(),
+                libc::MS_BIND | libc::MS_REC,
+                std::ptr::null(),
+            )
+        })
+    }
+
+    /// Make a mount read-only, keeping the flags a user namespace may not
+    /// clear
+    fn remount_read_only(point: &CString, flags: libc::c_ulong) -> io::Result<()> {
+        check(unsafe {
+            libc::mount(
+                std::ptr::null(),
+                point.as_ptr(),
+                std::ptr::null(),
+                libc::MS_REMOUNT | libc::MS_BIND | libc::MS_RDONLY | flags,
+                std::ptr::null(),
+            )
+        })
+    }
+
+    /// Mount points of this process
+    fn mount_points() -> io::Result<Vec<PathBuf>> {
+        let mountinfo = std::fs::read_to_string("/proc/self/mountinfo")?;
+        Ok(mountinfo
+            .lines()
+            .filter_map(|line| line.split(' ').nth(4))
+            .map(|point| PathBuf::from(unescape(point)))
+            .collect())
+    }
+
+    /// Undo the octal escapes of `/proc/self/mountinfo`, e.g. `\040`
+    fn unescape(field: &str) -> String {
+        let mut bytes = Vec::with_capacity(field.len());
+        let mut rest = field.as_bytes();
+        while let Some((&byte, tail)) = rest.split_first() {
+            let code = tail.get(..3).and_then(|digits| std::str::from_utf8(digits).ok());
+            match code.and_then(|digits| u8::from_str_radix(digits, 8).ok()) {
+                Some(decoded) if byte == b'\\' => {
+                    bytes.push(decoded);
+                    rest = &tail[3..];
+                }
+                _ => {
+                    bytes.push(byte);
+                    rest = tail;
+                }
+            }
+        }
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    /// `nosuid`, `nodev` and `noexec` of the mount holding `path`
+    fn mount_flags(path: &Path) -> libc::c_ulong {
+        let Ok(path) = c_path(path) else { return 0 };
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } < 0 {
+            return 0;
+        }
+        // The `ST_` values are those of the matching `MS_` flags
+        stat.f_flag & (libc::ST_NOSUID | libc::ST_NODEV | libc::ST_NOEXEC)
+    }
+
+    fn c_path(path: &Path) -> Result<CString> {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| unavailable(&format!("{} contains a NUL byte", path.display())))
+    }
+
+    fn seccomp_filter() -> Result<BpfProgram> {
+        let arch = TargetArch::try_from(std::env::consts::ARCH)
+            .map_err(|e| unavailable(&format!("seccomp: {}", e)))?;
+        // An empty rule list matches every call of the syscall. `c_long`
+        // is only `i64` on 64-bit targets.
+        #[allow(clippy::unnecessary_cast)]
+        let rules: BTreeMap<i64, Vec<_>> = DENIED_SYSCALLS.iter().map(|nr| (*nr as i64, Vec::new())).collect();
+        let filter = SeccompFilter::new(
+            rules,
+            SeccompAction::Allow,
+            SeccompAction::Errno(libc::EPERM as u32),
+            arch,
+        )
+        .map_err(|e| unavailable(&format!("seccomp: {}", e)))?;
+        filter.try_into().map_err(|e: seccompiler::BackendError| unavailable(&format!("seccomp: {}", e)))
+    }
+
+    fn write_file(path: &std::ffi::CStr, content: &[u8]) -> io::Result<()> {
+        let fd = unsafe { libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC) };
+        check(fd)?;
+        let written = unsafe { libc::write(fd, content.as_ptr().cast(), content.len()) };
+        unsafe { libc::close(fd) };
+        if written < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn check(result: libc::c_int) -> io::Result<()> {
+        if result < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn unavailable(reason: &str) -> codev_shared::CodevError {
+        SecurityError::SandboxUnavailable {
+            reason: reason.to_string(),
+        }
+        .into()
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> (String, Vec<String>) {
+        ("sh".to_string(), vec!["-c".to_string(), script.to_string()])
+    }
+
+    async fn run(config: &SandboxConfig, script: &str) -> Result<CommandResult> {
+        let (program, args) = sh(script);
+        let env = vec![("PATH".to_string(), "/usr/bin:/bin".to_string())];
+        Sandbox::new(config, true).run(&program, &args, Path::new("/"), env).await
+    }
+
+    /// Whether this machine allows the namespaces the sandbox needs
+    async fn supported() -> bool {
+        match run(&SandboxConfig::default(), "true").await {
+            Err(e) if e.to_string().contains("Sandbox unavailable") => {
+                eprintln!("skipped, no namespaces on this machine: {}", e);
+                false
+            }
+            _ => true,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_confinement() {
+        if !supported().await {
+            return;
+        }
+        let config = SandboxConfig::default();
+
+        // Only the environment given is passed on
+        let result = run(&config, "echo hello; echo ${HOME:-unset}").await.unwrap();
+        assert!(result.success);
+        assert_eq!(result.stdout, "hello\nunset\n");
+
+        // Only the loopback interface exists in the network namespace
+        let result = run(&config, "tail -n +3 /proc/net/dev").await.unwrap();
+        assert!(result.stdout.lines().all(|line| line.trim_start().starts_with("lo:")), "{}", result.stdout);
+
+        let result = run(&config, "ulimit -d; grep Seccomp: /proc/self/status").await.unwrap();
+        assert_eq!(result.stdout, "1048576\nSeccomp:\t2\n");
+
+        let result = run(&config, "ls -A /tmp | wc -l").await.unwrap();
+        assert_eq!(result.stdout.trim(), "0");
+    }
+
+    #[tokio::test]
+    async fn test_only_write_paths_are_writable() {
+        if !supported().await {
+            return;
+        }
+        // Outside `/tmp`, which the sandbox replaces
+        let workspace = tempfile::tempdir_in("/var/tmp").unwrap();
+        let outside = tempfile::tempdir_in("/var/tmp").unwrap();
+        let vendor = workspace.path().join("vendor");
+        std::fs::create_dir(&vendor).unwrap();
+        let sandbox = Sandbox::new(&SandboxConfig::default(), true)
+            .with_paths(vec![workspace.path().to_path_buf()], vec![vendor.clone()]);
+
+        async fn write_to(sandbox: &Sandbox, dir: &Path) -> CommandResult {
+            let (program, args) = sh(&format!("echo x > {}/file && echo written", dir.display()));
+            sandbox.run(&program, &args, Path::new("/"), Vec::new()).await.unwrap()
+        }
+        assert_eq!(write_to(&sandbox, workspace.path()).await.stdout, "written\n");
+        for dir in [outside.path(), vendor.as_path()] {
+            let result = write_to(&sandbox, dir).await;
+            assert!(result.stderr.contains("Read-only file system"), "{}: {}", dir.display(), result.stderr);
+        }
+        assert!(!outside.path().join("file").exists());
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        if !supported().await {
+            return;
+        }
+        let config = SandboxConfig {
+            max_cpu_time: Some(1),
+            ..SandboxConfig::default()
+        };
+        let error = run(&config, "sleep 5").await.unwrap_err().to_string();
+        assert!(error.contains(&SecurityError::ExecutionTimeout.to_string()), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_failures_are_results() {
+        let (program, args) = sh("echo oops >&2; exit 3");
+        let result = Sandbox::new(&SandboxConfig::default(), false)
+            .run(&program, &args, Path::new("/"), Vec::new())
+            .await
+            .unwrap();
+        assert!(!result.success);
+        assert_eq!(result.exit_code, 3);
+        assert_eq!(result.stderr, "oops\n");
+    }
+}
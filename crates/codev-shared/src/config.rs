@@ -1,14 +1,22 @@
 //! Configuration management for CoDev.rs
 
+use crate::credentials::{CredentialSources, Credentials};
 use crate::error::{ConfigError, Result};
-use crate::types::{Environment, ProviderId, SecurityLevel};
+use crate::migration::CURRENT_CONFIG_VERSION;
+use crate::types::{Environment, ModelTarget, ProviderId, ProviderKind, RedactionAction, SecurityLevel};
+use schemars::JsonSchema;
+use secrecy::SecretString;
 use serde::{ Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::{ Path, PathBuf};
 
 /// Main configuration structure for CoDev.rs
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct CodevConfig {
+    /// Version of the file format, see `migration`
+    #[serde(default = "current_config_version")]
+    pub config_version: u32,
+
     /// Application environment
     pub environment: Environment,
 
@@ -26,10 +34,41 @@ pub struct CodevConfig {
 
     /// Workspace settings
     pub workspace: WorkspaceConfig,
+
+    /// Profile applied over this configuration, see `profiles`
+    #[serde(default)]
+    pub profile: Option<String>,
+
+    /// Named sets of overrides, e.g. `[profiles.offline.ai]`
+    #[serde(default)]
+    pub profiles: HashMap<String, ConfigProfile>,
+}
+
+/// Overrides applied when a profile is selected
+///
+/// Each section holds any subset of the keys of the matching section of
+/// `CodevConfig`, merged over it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigProfile {
+    /// Overrides of `ai`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<serde_json::Map<String, serde_json::Value>>")]
+    pub ai: Option<toml::Table>,
+
+    /// Overrides of `security`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<serde_json::Map<String, serde_json::Value>>")]
+    pub security: Option<toml::Table>,
+
+    /// Overrides of `logging`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<serde_json::Map<String, serde_json::Value>>")]
+    pub logging: Option<toml::Table>,
 }
 
 /// AI provider configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct AiConfig {
     /// Default provider to use
     pub default_provider: ProviderId,
@@ -45,11 +84,76 @@ pub struct AiConfig {
 
     /// Environment-specific provider preferences
     pub environment_providers: Option<HashMap<String, Vec<ProviderId>>>,
+
+    /// Ollama-specific settings
+    #[serde(default)]
+    pub ollama: OllamaConfig,
+
+    /// Logical model names mapped to provider/model pairs, tried in order
+    ///
+    /// An alias can be used wherever a model name is accepted, e.g.
+    /// `smart = ["claude/claude-3-5-sonnet-latest", "ollama/codellama:13b"]`.
+    #[serde(default)]
+    pub aliases: HashMap<String, Vec<ModelTarget>>,
+
+    /// HTTP transport shared by all providers
+    #[serde(default)]
+    pub http: HttpConfig,
+
+    /// Where API keys are read from besides environment variables
+    #[serde(default)]
+    pub credentials: CredentialsConfig,
+}
+
+/// HTTP transport configuration
+///
+/// `HTTPS_PROXY`, `HTTP_PROXY` and `NO_PROXY` are honoured unless a proxy
+/// is configured here.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// Proxy URL for all provider traffic
+    pub proxy: Option<String>,
+
+    /// Comma-separated hosts that bypass `proxy`, like `NO_PROXY`
+    pub no_proxy: Option<String>,
+
+    /// PEM bundle of extra root certificates, e.g. a corporate CA
+    pub ca_bundle: Option<PathBuf>,
+
+    /// PEM client certificate for mutual TLS
+    pub client_cert: Option<PathBuf>,
+
+    /// PEM private key for `client_cert`
+    pub client_key: Option<PathBuf>,
+
+    /// Time allowed to establish a connection
+    pub connect_timeout_seconds: u64,
+
+    /// Time allowed between two reads, so long streams are not cut off
+    pub read_timeout_seconds: u64,
+}
+
+/// API key sources besides environment variables
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct CredentialsConfig {
+    /// TOML file mapping provider names to keys, e.g. `openai = "sk-..."`
+    /// (default: `credentials.toml` in the user config directory). Ignored
+    /// unless only its owner can read it.
+    pub file: Option<PathBuf>,
+
+    /// Read key variables from `.env` in the current directory
+    pub dotenv: bool,
 }
 
 /// Configuration for a specific AI provider
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ProviderConfig {
+    /// API spoken by the provider (inferred for built-in provider names)
+    #[serde(default)]
+    pub kind: Option<ProviderKind>,
+
     /// Whether this provider is enabled
     pub enabled: bool,
 
@@ -65,15 +169,82 @@ pub struct ProviderConfig {
     /// Custom endpoint (for self-hosted models)
     pub endpoint: Option<String>,
 
-    /// Timeout for requests in seconds
+    /// Additional endpoints to balance requests over (self-hosted models)
+    #[serde(default)]
+    pub endpoints: Vec<String>,
+
+    /// Timeout in seconds for requests other than generation, which is
+    /// bounded by `http.read_timeout_seconds` instead
     pub timeout_seconds: Option<u64>,
 
     /// Maximum retries on failure
     pub max_retries: Option<u32>,
+
+    /// Environment variable holding the API key (for custom providers)
+    #[serde(default)]
+    pub api_key_env: Option<String>,
+
+    /// Command printing the API key, e.g. `pass show openai`
+    #[serde(default)]
+    pub credential_command: Option<String>,
+
+    /// Cost per token in USD, for cost estimates
+    #[serde(default)]
+    pub cost_per_token: Option<f64>,
+}
+
+impl AiConfig {
+    /// Get API keys with the source each one came from
+    pub fn load_credentials(&self) -> Credentials {
+        Credentials::load(self, &CredentialSources::from_config(self))
+    }
+
+    /// Get API keys from the environment, credential commands and files
+    pub fn load_api_keys(&self) -> HashMap<ProviderId, SecretString> {
+        self.load_credentials().into_keys()
+    }
+
+    /// Whether every endpoint of provider `id` is on this machine
+    ///
+    /// Ollama-kind providers without their own endpoint use
+    /// `ollama.endpoint`; other providers without one talk to the vendor's
+    /// cloud API.
+    pub fn is_local(&self, id: &ProviderId) -> bool {
+        let Some(provider) = self.providers.get(id) else {
+            return false;
+        };
+        let mut endpoints: Vec<&str> = provider.endpoint.iter().chain(&provider.endpoints).map(String::as_str).collect();
+        if endpoints.is_empty() && provider.resolved_kind(id) == Some(ProviderKind::Ollama) {
+            endpoints.push(&self.ollama.endpoint);
+        }
+        !endpoints.is_empty() && endpoints.iter().all(|endpoint| is_loopback(endpoint))
+    }
+}
+
+/// Whether the host of an endpoint URL is the local machine
+fn is_loopback(endpoint: &str) -> bool {
+    let rest = endpoint.split_once("://").map_or(endpoint, |(_, rest)| rest);
+    let authority = rest.split('/').next().unwrap_or_default();
+    let host_port = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    let host = match host_port.strip_prefix('[') {
+        Some(bracketed) => bracketed.split(']').next().unwrap_or_default(),
+        None => host_port.split(':').next().unwrap_or_default(),
+    };
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+impl ProviderConfig {
+    /// Configured kind, or the kind of the built-in provider with this id
+    pub fn resolved_kind(&self, id: &ProviderId) -> Option<ProviderKind> {
+        self.kind.or_else(|| ProviderKind::default_for(id))
+    }
 }
 
 /// Ollama-specific configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct OllamaConfig {
     /// Ollama API endpoint
     pub endpoint: String,
@@ -89,10 +260,19 @@ pub struct OllamaConfig {
 
     /// Keep alive duration in seconds
     pub keep_alive: Option<u64>,
+
+    /// Load the routed models when the engine starts
+    pub preload_models: bool,
+
+    /// Unload models that have not been used for this many seconds
+    pub idle_unload_seconds: Option<u64>,
 }
 
 /// Ollama model configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// Entries are Ollama model names, or an alias or `provider/model` to route
+/// a task to another provider.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct OllamaModels {
     /// Model for code generation
     pub code_generation: String,
@@ -104,62 +284,227 @@ pub struct OllamaModels {
     pub analysis: String,
 }
 
+impl OllamaModels {
+    /// All distinct models referenced by the routing table
+    pub fn all(&self) -> Vec<&str> {
+        let mut models = vec![
+            self.code_generation.as_str(),
+            self.chat.as_str(),
+            self.analysis.as_str(),
+        ];
+        models.sort_unstable();
+        models.dedup();
+        models
+    }
+}
+
 /// Security configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// `default_level` selects a preset (see `SecurityConfig::preset`) that
+/// fills in every key the configuration files do not set.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct SecurityConfig {
     /// Default security level
     pub default_level: SecurityLevel,
 
-    /// Whether sandbox is enabled
+    /// Whether commands run in the sandbox
+    #[serde(default)]
+    pub sandbox_enabled: bool,
+
+    /// Sandbox limits and isolation
     pub sandbox: SandboxConfig,
 
     /// Allowed commands whitelist
     pub allowed_commands: Vec<String>,
 
+    /// Argument, environment and directory rules for allowed commands,
+    /// keyed by program name
+    #[serde(default = "default_command_rules")]
+    pub commands: HashMap<String, CommandRule>,
+
+    /// Environment variables passed to commands
+    #[serde(default)]
+    pub command_env: CommandEnvConfig,
+
     /// File access restrictions
     pub file_access: FileAccessConfig,
+
+    /// Whether prompts may be sent to providers outside this machine
+    #[serde(default = "default_true")]
+    pub cloud_providers: bool,
+
+    /// Confirmations required before acting
+    #[serde(default)]
+    pub approval: ApprovalConfig,
+
+    /// Record of commands, file writes, provider calls and policy decisions
+    #[serde(default)]
+    pub audit: AuditConfig,
+
+    /// Scanning of prompts for secrets before they are sent
+    #[serde(default)]
+    pub redaction: RedactionConfig,
+
+    /// Providers allowed for prompts built from certain files or
+    /// repositories
+    #[serde(default)]
+    pub residency: Vec<ResidencyRule>,
+}
+
+/// Providers a prompt may go to when it touches matching files or
+/// repositories
+///
+/// Patterns use `*` for any run of characters, including `/`, and `?` for
+/// one character. Relative path patterns are resolved against the current
+/// directory and `~/` against the home directory. When several rules apply,
+/// only providers allowed by all of them are used, and a prompt no allowed
+/// provider can take is not sent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ResidencyRule {
+    /// Files the rule covers, e.g. `~/work/payments/*`
+    #[serde(default)]
+    pub paths: Vec<String>,
+
+    /// Repository remote URLs the rule covers, e.g. `*github.com*acme/payments*`
+    #[serde(default)]
+    pub remotes: Vec<String>,
+
+    /// Providers matching prompts may go to
+    #[serde(default = "default_residency_providers")]
+    pub providers: Vec<ProviderId>,
+}
+
+/// Secret scanning of outgoing prompts
+///
+/// Prompts are scanned for well-known API key formats, PEM private keys,
+/// `.env`-style secret assignments, passwords in URLs, e-mail addresses,
+/// high-entropy strings and `patterns`. Local providers always receive the
+/// prompt as it is unless `action` is `block`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct RedactionConfig {
+    /// Whether prompts are scanned
+    pub enabled: bool,
+
+    /// What happens to a prompt with findings
+    pub action: RedactionAction,
+
+    /// Additional regular expressions; when a pattern has a capture group,
+    /// only the group is treated as secret
+    pub patterns: Vec<String>,
+
+    /// Bits of entropy per character above which a token of 24 or more
+    /// letters and digits counts as a secret; 0 turns the check off
+    pub entropy_threshold: f64,
+
+    /// Whether e-mail addresses are treated as personal data
+    pub emails: bool,
+}
+
+/// Audit log settings, read at start-up
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct AuditConfig {
+    /// Whether actions are recorded
+    pub enabled: bool,
+
+    /// Log file (default: `audit.jsonl` in `workspace.data_dir`)
+    pub path: Option<PathBuf>,
+}
+
+/// Confirmations required before acting, beyond `ask` command rules
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct ApprovalConfig {
+    /// Every command needs confirmation
+    pub commands: bool,
+
+    /// Every file write needs confirmation
+    pub file_writes: bool,
+}
+
+/// Rules for one allowed command
+///
+/// Patterns are matched against the arguments joined by spaces; `*` matches
+/// anything and `?` one character. `deny` is checked first, then `ask`,
+/// then `allow`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct CommandRule {
+    /// Arguments that are always refused, e.g. `push*--force*`
+    pub deny: Vec<String>,
+
+    /// Arguments that need confirmation before running
+    pub ask: Vec<String>,
+
+    /// Arguments that are allowed; when set, anything else is refused
+    pub allow: Vec<String>,
+
+    /// Variables passed through in addition to `command_env.allow`
+    pub env_allow: Vec<String>,
+
+    /// Directories the command may run in, including their subdirectories
+    pub working_dirs: Vec<PathBuf>,
+}
+
+/// Environment filtering for commands
+///
+/// Only variables matching `allow` are passed on, and never those matching
+/// `deny`, so API keys and tokens do not leak into child processes.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct CommandEnvConfig {
+    /// Variable name patterns passed to every command
+    pub allow: Vec<String>,
+
+    /// Variable name patterns never passed on, even when allowed
+    pub deny: Vec<String>,
 }
 
 /// Sandbox security configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct SandboxConfig {
     /// Maximum memory usage in bytes
     pub max_memory: Option<usize>,
 
-    /// Maximum CPU time in seconds
+    /// Maximum CPU time in seconds, also used as the wall-clock timeout
     pub max_cpu_time: Option<u64>,
 
     /// Network access allowed
     pub network_access: bool,
 
-    /// Temporary directory for sandbox
+    /// Directory mounted as `/tmp` in the sandbox (default: an empty tmpfs)
     pub temp_dir: Option<PathBuf>,
 
-    /// User ID for sandbox execution
+    /// User ID to run commands as (requires running as root)
     pub user_id: Option<u32>,
 
-    /// Group ID for sandbox execution
+    /// Group ID to run commands as (requires running as root)
     pub group_id: Option<u32>,
 }
 
 /// File access configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// Relative paths are resolved against the current directory. Forbidden
+/// paths win over both other lists; otherwise the most specific of
+/// `read_only_paths` and `write_allowed_paths` decides.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct FileAccessConfig {
     /// Paths that are read-only
-    pub read_only_path: Vec<PathBuf>,
+    pub read_only_paths: Vec<PathBuf>,
 
-    /// Paths where writing is allowed
+    /// Paths where writing is allowed; writes anywhere else are refused
     pub write_allowed_paths: Vec<PathBuf>,
 
-    /// Paths that are completely forbidden
+    /// Paths that can be neither read nor written
     pub forbidden_paths: Vec<PathBuf>,
 
-    /// Maximum file size in bytes
+    /// Maximum size in bytes of a file that is read or written
     pub max_file_size: usize,
 }
 
 /// Development-specific configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct DevelopmentConfig {
     /// Enable hot reload
     pub hot_reload: bool,
@@ -175,7 +520,7 @@ pub struct DevelopmentConfig {
 }
 
 /// Logging configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct LoggingConfig {
     /// Log level (trace, debug, info, warn, error)
     pub level: String,
@@ -197,11 +542,15 @@ pub struct LoggingConfig {
 }
 
 /// Workspace configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct WorkspaceConfig {
     /// Default workspace directory
     pub default_path: PathBuf,
 
+    /// Directory for persistent data such as caches and history
+    #[serde(default = "default_data_dir")]
+    pub data_dir: PathBuf,
+
     /// Auto-detect project type
     pub auto_detect_project: bool,
 
@@ -212,15 +561,30 @@ pub struct WorkspaceConfig {
     pub max_project_size: Option<usize>,
 }
 
+fn current_config_version() -> u32 {
+    CURRENT_CONFIG_VERSION
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_residency_providers() -> Vec<ProviderId> {
+    vec![ProviderId::OLLAMA]
+}
+
 impl Default for CodevConfig {
     fn default() -> Self {
         Self {
+            config_version: CURRENT_CONFIG_VERSION,
             environment: Environment::Development,
             ai: AiConfig::default(),
             security: SecurityConfig::default(),
             development: Some(DevelopmentConfig::default()),
             logging: LoggingConfig::default(),
             workspace: WorkspaceConfig::default(),
+            profile: None,
+            profiles: HashMap::new(),
         }
     }
 }
@@ -231,44 +595,111 @@ impl Default for AiConfig {
 
         // Ollama configuration
         providers.insert(
-            ProviderId::Ollama,
+            ProviderId::OLLAMA,
             ProviderConfig {
+                kind: Some(ProviderKind::Ollama),
                 enabled: true,
                 model: "codellama:7b".to_string(),
                 max_tokens: Some(4096),
                 temperature: Some(0.1),
                 endpoint: Some("http://localhost:11434".to_string()),
+                endpoints: Vec::new(),
                 timeout_seconds: Some(60),
                 max_retries: Some(3),
+                api_key_env: None,
+                credential_command: None,
+                cost_per_token: None,
             }
         );
 
         // Mistral configuration
         providers.insert(
-            ProviderId::Mistral,
+            ProviderId::MISTRAL,
             ProviderConfig {
+                kind: Some(ProviderKind::OpenaiCompatible),
                 enabled: false, // Requires API key
                 model: "mistral-medium".to_string(),
                 max_tokens: Some(4096),
                 temperature: Some(0.1),
                 endpoint: None,
+                endpoints: Vec::new(),
                 timeout_seconds: Some(60),
                 max_retries: Some(3),
+                api_key_env: None,
+                credential_command: None,
+                cost_per_token: None,
             }
         );
 
         Self {
-            default_provider: ProviderId::Ollama,
+            default_provider: ProviderId::OLLAMA,
             fallback_chain: vec![
-                ProviderId::Ollama,
-                ProviderId::Mistral,
-                ProviderId::Claude,
-                ProviderId::OpenAI,
-                ProviderId::Gemini,
+                ProviderId::OLLAMA,
+                ProviderId::MISTRAL,
+                ProviderId::CLAUDE,
+                ProviderId::OPENAI,
+                ProviderId::GEMINI,
             ],
             auto_detect_environment: true,
             providers,
             environment_providers: None,
+            ollama: OllamaConfig::default(),
+            aliases: HashMap::from([(
+                "local".to_string(),
+                vec![ModelTarget {
+                    provider: ProviderId::OLLAMA,
+                    model: "codellama:7b".to_string(),
+                }],
+            )]),
+            http: HttpConfig::default(),
+            credentials: CredentialsConfig::default(),
+        }
+    }
+}
+
+impl Default for CredentialsConfig {
+    fn default() -> Self {
+        Self {
+            file: None,
+            dotenv: true,
+        }
+    }
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            proxy: None,
+            no_proxy: None,
+            ca_bundle: None,
+            client_cert: None,
+            client_key: None,
+            connect_timeout_seconds: 10,
+            read_timeout_seconds: 300, // Model loading can stall the first token
+        }
+    }
+}
+
+impl Default for OllamaConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: "http://localhost:11434".to_string(),
+            auto_install_models: false,
+            models: OllamaModels::default(),
+            max_content_length: 4096,
+            keep_alive: None, // Use the server's OLLAMA_KEEP_ALIVE
+            preload_models: true,
+            idle_unload_seconds: None,
+        }
+    }
+}
+
+impl Default for OllamaModels {
+    fn default() -> Self {
+        Self {
+            code_generation: "codellama:7b".to_string(),
+            chat: "llama2:7b".to_string(),
+            analysis: "codellama:7b".to_string(),
         }
     }
 }
@@ -289,7 +720,121 @@ impl Default for SecurityConfig {
                 "python3".to_string(),
                 "go".to_string(),
             ],
+            commands: default_command_rules(),
+            command_env: CommandEnvConfig::default(),
             file_access: FileAccessConfig::default(),
+            cloud_providers: true,
+            approval: ApprovalConfig::default(),
+            audit: AuditConfig::default(),
+            redaction: RedactionConfig::default(),
+            residency: Vec::new(),
+        }
+    }
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            action: RedactionAction::Redact,
+            patterns: Vec::new(),
+            entropy_threshold: 4.0,
+            emails: true,
+        }
+    }
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            path: None,
+        }
+    }
+}
+
+impl SecurityConfig {
+    /// Policy bundle of a security level
+    ///
+    /// | | Development | Production | Paranoid |
+    /// |---|---|---|---|
+    /// | sandbox | off | on | on |
+    /// | network in the sandbox | - | off | off |
+    /// | memory / CPU time | 1 GB / 10 min | 1 GB / 10 min | 512 MB / 2 min |
+    /// | allowed commands | cargo, rustc, git, npm, node, python, python3, go | same | cargo, rustc, git |
+    /// | writable paths | current directory, `/tmp`, `/var/tmp` | current directory | current directory |
+    /// | confirmation | `ask` rules | `ask` rules | every command and file write |
+    /// | cloud providers | yes | yes | no |
+    ///
+    /// Development is the same as `SecurityConfig::default()`. When loading
+    /// configuration, keys set in any layer override the preset one by one.
+    pub fn preset(level: SecurityLevel) -> Self {
+        let development = Self::default();
+        match level {
+            SecurityLevel::Development => development,
+            SecurityLevel::Production => Self {
+                default_level: level,
+                sandbox_enabled: true,
+                file_access: FileAccessConfig {
+                    write_allowed_paths: vec![PathBuf::from(".")],
+                    ..development.file_access.clone()
+                },
+                ..development
+            },
+            SecurityLevel::Paranoid => Self {
+                default_level: level,
+                sandbox_enabled: true,
+                sandbox: SandboxConfig {
+                    max_memory: Some(512 * 1024 * 1024),
+                    max_cpu_time: Some(120),
+                    network_access: false,
+                    ..development.sandbox.clone()
+                },
+                allowed_commands: vec!["cargo".to_string(), "rustc".to_string(), "git".to_string()],
+                file_access: FileAccessConfig {
+                    write_allowed_paths: vec![PathBuf::from(".")],
+                    ..development.file_access.clone()
+                },
+                cloud_providers: false,
+                approval: ApprovalConfig {
+                    commands: true,
+                    file_writes: true,
+                },
+                ..development
+            },
+        }
+    }
+}
+
+pub(crate) fn default_command_rules() -> HashMap<String, CommandRule> {
+    let deny = |patterns: &[&str]| CommandRule {
+        deny: patterns.iter().map(|p| p.to_string()).collect(),
+        ..CommandRule::default()
+    };
+
+    HashMap::from([
+        (
+            "git".to_string(),
+            CommandRule {
+                ask: vec!["push*".to_string()],
+                ..deny(&["push*--force*", "push* -f*", "push* +*", "push*--mirror*", "push*--delete*"])
+            },
+        ),
+        ("cargo".to_string(), deny(&["publish*", "login*", "owner*", "yank*"])),
+        ("npm".to_string(), deny(&["publish*", "unpublish*", "login*", "adduser*", "token*"])),
+    ])
+}
+
+impl Default for CommandEnvConfig {
+    fn default() -> Self {
+        let patterns = |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect();
+        Self {
+            allow: patterns(&[
+                "PATH", "HOME", "USER", "LOGNAME", "SHELL", "TERM", "LANG", "LC_*", "TZ", "TMPDIR",
+                "CARGO_*", "RUST*", "GIT_*", "NODE_*", "NPM_CONFIG_*", "PYTHON*", "VIRTUAL_ENV",
+                "GOPATH", "GOROOT", "GOFLAGS", "GOCACHE", "GOMODCACHE", "GOPROXY",
+            ]),
+            deny: patterns(&["*_API_KEY", "*TOKEN*", "*SECRET*", "*PASSWORD*", "AWS_*", "GIT_ASKPASS"]),
         }
     }
 }
@@ -310,21 +855,34 @@ impl Default for SandboxConfig {
 impl Default for FileAccessConfig {
     fn default() -> Self {
         Self {
-            read_only_path: vec![
+            read_only_paths: vec![
                 PathBuf::from("/usr"),
                 PathBuf::from("/bin"),
                 PathBuf::from("/sbin"),
                 PathBuf::from("/lib"),
             ],
             write_allowed_paths: vec![
+                PathBuf::from("."),
                 PathBuf::from("/tmp"),
                 PathBuf::from("/var/tmp"),
             ],
+            forbidden_paths: default_forbidden_paths(),
             max_file_size: 100 * 1024 * 1024, // 100MB
         }
     }
 }
 
+fn default_forbidden_paths() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = ["/etc/shadow", "/etc/gshadow", "/etc/sudoers", "/proc", "/sys", "/dev"]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+    if let Some(home) = dirs::home_dir() {
+        paths.extend([".ssh", ".gnupg", ".aws", ".kube", ".docker"].iter().map(|dir| home.join(dir)));
+    }
+    paths
+}
+
 impl Default for DevelopmentConfig {
     fn default() -> Self {
         Self {
@@ -349,10 +907,17 @@ impl Default for LoggingConfig {
     }
 }
 
+fn default_data_dir() -> PathBuf {
+    dirs::data_dir()
+        .map(|dir| dir.join("codev"))
+        .unwrap_or_else(|| PathBuf::from("./data"))
+}
+
 impl Default for WorkspaceConfig {
     fn default() -> Self {
         Self {
             default_path: PathBuf::from("./workspace"),
+            data_dir: default_data_dir(),
             auto_detect_project: true,
             ignore_patterns: vec![
                 ".git".to_string(),
@@ -375,7 +940,15 @@ impl CodevConfig {
                 path: path.as_ref().display().to_string(),
             })?;
 
-        toml::from_str(&content)
+        let mut table: toml::Table = content.parse()
+            .map_err(|e: toml::de::Error| ConfigError::InvalidFormat {
+                message: e.to_string(),
+            })?;
+
+        // Files from older versions are upgraded before parsing
+        crate::migration::migrate_table(&mut table)?;
+
+        Self::deserialize(toml::Value::Table(table))
             .map_err(|e| ConfigError::InvalidFormat {
                 message: e.to_string(),
             })
@@ -393,91 +966,67 @@ impl CodevConfig {
             .map_err(Into::into)
     }
 
-    /// Load configuration with environment variable overrides
+    /// Load configuration from defaults, config files and environment variables
     pub fn load_with_env() -> Result<Self> {
-        let mut config = Self::default();
-
-        // Override with environment variables
-        if let Ok(env) = std::env::var("CODEV_ENV") {
-            config.environment = match env.as_str() {
-                "development" => Environment::Development,
-                "production" => Environment::Production,
-                "testing" => Environment::Testing,
-                _ => Environment::Development,
-            };
-        }
+        Ok(crate::loader::ConfigLoader::new().load()?.config)
+    }
 
-        // Override default provider
-        if let Ok(provider) = std::env::var("CODEV_AI_PROVIDER") {
-            match provider.as_str() {
-                "ollama" => config.ai.default_provider = ProviderId::Ollama,
-                "openai" => config.ai.default_provider = ProviderId::OpenAI,
-                "claude" => config.ai.default_provider = ProviderId::Claude,
-                "mistral" => config.ai.default_provider = ProviderId::Mistral,
-                "gemini" => config.ai.default_provider = ProviderId::Gemini,
-                _ => {}
-            }
-        }
+    /// Audit log file, `None` when auditing is off
+    pub fn audit_log_path(&self) -> Option<PathBuf> {
+        let audit = &self.security.audit;
+        audit.enabled.then(|| {
+            audit
+                .path
+                .clone()
+                .unwrap_or_else(|| self.workspace.data_dir.join("audit.jsonl"))
+        })
+    }
 
-        // Override Ollama endpoint
-        if let Ok(endpoint) = std::env::var("OLLAMA_ENDPOINT") {
-            if let Some(ollama_config) = config.ai.providers.get_mut(&ProviderId::Ollama) {
-                ollama_config.endpoint = Some(endpoint);
+    /// AI configuration with the providers the security policy rules out
+    /// disabled
+    pub fn permitted_ai(&self) -> AiConfig {
+        let mut ai = self.ai.clone();
+        if !self.security.cloud_providers {
+            let cloud: Vec<ProviderId> = ai.providers.keys().filter(|id| !self.ai.is_local(id)).cloned().collect();
+            for id in cloud {
+                if let Some(provider) = ai.providers.get_mut(&id) {
+                    provider.enabled = false;
+                }
             }
         }
-
-        Ok(config)
+        ai
     }
 
-    /// Get API keys from environment variables
-    pub fn load_api_keys(&self) -> HashMap<ProviderId, String> {
-        let mut keys = HashMap::new();
-
-        // Load API keys from environment variables only (never from config files)
-        if let Ok(key) = std::env::var("OPENAI_API_KEY") {
-            keys.insert(ProviderId::OpenAI, key);
-        }
-        if let Ok(key) = std::env::var("ANTHROPIC_API_KEY") {
-            keys.insert(ProviderId::Claude, key);
-        }
-        if let Ok(key) = std::env::var("MISTRAL_API_KEY") {
-            keys.insert(ProviderId::Mistral, key);
-        }
-        if let Ok(key) = std::env::var("GOOGLE_API_KEY") {
-            keys.insert(ProviderId::Gemini, key);
-        }
-
-        keys
+    /// Get API keys from the environment, credential commands and files
+    pub fn load_api_keys(&self) -> HashMap<ProviderId, SecretString> {
+        self.ai.load_api_keys()
     }
 
-    /// Validate configuration
+    /// Validate configuration, failing with every error found
+    ///
+    /// Warnings are not fatal; use `check` to see them.
     pub fn validate(&self) -> Result<()> {
-        // Validate AI configuration
-        if self.ai.providers.is_empty() {
-            return Err(ConfigError::InvalidValue {
-                key: "ai.providers".to_string(),
-                value: "empty".to_string(),
-            }.into());
-        }
-
-        // Validate workspace path
-        if !self.workspace.default_path.exists() {
-            return Err(ConfigError::InvalidValue {
-                key: "workspace.default.path".to_string(),
-                value: self.workspace.default_path.display().to_string(),
-            }.into());
-        }
+        self.validate_with_credentials(&self.ai.load_credentials())
+    }
 
-        // Validate security settings
-        if let Some(max_memory) = self.security.sandbox.max_memory {
-            if max_memory == 0 {
-                return Err(ConfigError::InvalidValue {
-                    key: "security.sandbox.max_memory".to_string(),
-                    value: "0".to_string(),
-                }.into())
-            }
+    /// Validate configuration given credentials already loaded, so that
+    /// credential commands do not run again
+    pub fn validate_with_credentials(&self, credentials: &Credentials) -> Result<()> {
+        let issues: Vec<_> = self
+            .check_with_credentials(credentials)
+            .into_iter()
+            .filter(|issue| issue.is_error())
+            .collect();
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid { issues }.into())
         }
+    }
 
-        Ok(())
+    /// JSON Schema of the configuration file, for editor completion
+    pub fn json_schema() -> serde_json::Value {
+        let schema = schemars::schema_for!(CodevConfig);
+        serde_json::to_value(schema).unwrap_or_default()
     }
 }
\ No newline at end of file
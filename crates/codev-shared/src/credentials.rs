@@ -0,0 +1,478 @@
+//! API key loading
+//!
+//! Keys never live in config files. Each provider's key is taken from the
+//! first source that has it:
+//!
+//! 1. its environment variable (`api_key_env`, or the built-in name such as
+//!    `OPENAI_API_KEY`)
+//! 2. its `credential_command`, e.g. `pass show openai`
+//! 3. the credentials file, which must not be readable by other users
+//! 4. a `.env` file in the current directory
+//!
+//! Keys are held as `SecretString`, which is redacted from `Debug` output
+//! and cannot be serialized.
+
+use crate::config::AiConfig;
+use crate::types::ProviderId;
+use secrecy::SecretString;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Name of the credentials file in the user config directory
+pub const CREDENTIALS_FILE: &str = "credentials.toml";
+
+/// Built-in providers, which have a key variable even when not configured
+const BUILTIN_PROVIDERS: &[ProviderId] = &[
+    ProviderId::OPENAI,
+    ProviderId::CLAUDE,
+    ProviderId::MISTRAL,
+    ProviderId::GEMINI,
+];
+
+/// Where an API key was found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CredentialSource {
+    Environment(String),
+    Command(String),
+    File(PathBuf),
+    DotEnv(PathBuf),
+}
+
+impl fmt::Display for CredentialSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CredentialSource::Environment(var) => write!(f, "environment variable {}", var),
+            CredentialSource::Command(command) => write!(f, "command `{}`", command),
+            CredentialSource::File(path) => write!(f, "credentials file {}", path.display()),
+            CredentialSource::DotEnv(path) => write!(f, ".env file {}", path.display()),
+        }
+    }
+}
+
+/// An API key and where it came from
+#[derive(Debug, Clone)]
+pub struct Credential {
+    pub key: SecretString,
+    pub source: CredentialSource,
+}
+
+/// A source that was configured but could not be used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CredentialProblem {
+    /// Dotted config key of the source, e.g. `ai.providers.openai.credential_command`
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for CredentialProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.key, self.message)
+    }
+}
+
+/// Places to look for API keys besides provider commands
+#[derive(Debug, Clone, Default)]
+pub struct CredentialSources {
+    pub environment: HashMap<String, String>,
+    pub file: Option<PathBuf>,
+    pub dotenv: Option<PathBuf>,
+}
+
+impl CredentialSources {
+    /// The process environment and the files named in `config`
+    pub fn from_config(config: &AiConfig) -> Self {
+        let environment = std::env::vars_os()
+            .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+            .collect();
+        let file = config
+            .credentials
+            .file
+            .clone()
+            .or_else(|| dirs::config_dir().map(|dir| dir.join("codev").join(CREDENTIALS_FILE)));
+        let dotenv = config
+            .credentials
+            .dotenv
+            .then(|| std::env::current_dir().ok().map(|dir| dir.join(".env")))
+            .flatten();
+
+        Self {
+            environment,
+            file,
+            dotenv,
+        }
+    }
+}
+
+/// API keys found for each provider
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    found: HashMap<ProviderId, Credential>,
+    problems: Vec<CredentialProblem>,
+}
+
+impl Credentials {
+    /// Look up the key of every built-in and configured provider
+    pub fn load(config: &AiConfig, sources: &CredentialSources) -> Self {
+        let mut credentials = Self::default();
+        let file = sources
+            .file
+            .as_deref()
+            .and_then(|path| credentials.read_file(path))
+            .unwrap_or_default();
+        let dotenv = sources
+            .dotenv
+            .as_deref()
+            .and_then(|path| credentials.read_dotenv(path))
+            .unwrap_or_default();
+
+        // Providers with invalid names are a configuration error; their
+        // credential commands are not run
+        let mut ids: Vec<&ProviderId> = BUILTIN_PROVIDERS
+            .iter()
+            .chain(config.providers.keys())
+            .filter(|id| id.as_str().parse::<ProviderId>().is_ok())
+            .collect();
+        ids.sort();
+        ids.dedup();
+
+        for id in ids {
+            let provider = config.providers.get(id);
+            let var = provider
+                .and_then(|provider| provider.api_key_env.clone())
+                .or_else(|| builtin_key_var(id).map(str::to_string));
+
+            let mut found = var.as_ref().and_then(|var| {
+                let key = sources.environment.get(var).filter(|key| !key.is_empty())?;
+                Some(Credential {
+                    key: SecretString::from(key.clone()),
+                    source: CredentialSource::Environment(var.clone()),
+                })
+            });
+            if found.is_none() {
+                if let Some(command) = provider.and_then(|provider| provider.credential_command.as_ref()) {
+                    found = credentials.run_command(id, command);
+                }
+            }
+            if found.is_none() {
+                found = file.get(id.as_str()).map(|(key, path)| Credential {
+                    key: SecretString::from(key.clone()),
+                    source: CredentialSource::File(path.clone()),
+                });
+            }
+            if found.is_none() {
+                found = var.and_then(|var| dotenv.get(&var)).map(|(key, path)| Credential {
+                    key: SecretString::from(key.clone()),
+                    source: CredentialSource::DotEnv(path.clone()),
+                });
+            }
+
+            if let Some(credential) = found {
+                credentials.found.insert(id.clone(), credential);
+            }
+        }
+        credentials
+    }
+
+    /// Key and source for a provider
+    pub fn get(&self, id: &ProviderId) -> Option<&Credential> {
+        self.found.get(id)
+    }
+
+    /// Every key found, by provider
+    pub fn iter(&self) -> impl Iterator<Item = (&ProviderId, &Credential)> {
+        self.found.iter()
+    }
+
+    /// Sources that were configured but failed
+    pub fn problems(&self) -> &[CredentialProblem] {
+        &self.problems
+    }
+
+    /// Keys by provider, dropping their sources
+    pub fn into_keys(self) -> HashMap<ProviderId, SecretString> {
+        self.found.into_iter().map(|(id, credential)| (id, credential.key)).collect()
+    }
+
+    fn problem(&mut self, key: impl Into<String>, message: impl Into<String>) {
+        self.problems.push(CredentialProblem {
+            key: key.into(),
+            message: message.into(),
+        });
+    }
+
+    /// Run a provider's `credential_command`; its first output line is the key
+    fn run_command(&mut self, id: &ProviderId, command: &str) -> Option<Credential> {
+        let key = format!("ai.providers.{}.credential_command", id);
+        let output = shell(command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output();
+
+        // Only stderr is reported: stdout may hold part of the key
+        match output {
+            Ok(output) if output.status.success() => {
+                let stdout = String::from_utf8_lossy(&output.stdout);
+                match stdout.lines().next().map(str::trim).filter(|line| !line.is_empty()) {
+                    Some(line) => Some(Credential {
+                        key: SecretString::from(line.to_string()),
+                        source: CredentialSource::Command(command.to_string()),
+                    }),
+                    None => {
+                        self.problem(key, format!("`{}` printed no key", command));
+                        None
+                    }
+                }
+            }
+            Ok(output) => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                let message = match stderr.lines().next().map(str::trim).filter(|line| !line.is_empty()) {
+                    Some(reason) => format!("`{}` failed ({}): {}", command, output.status, reason),
+                    None => format!("`{}` failed ({})", command, output.status),
+                };
+                self.problem(key, message);
+                None
+            }
+            Err(e) => {
+                self.problem(key, format!("could not run `{}`: {}", command, e));
+                None
+            }
+        }
+    }
+
+    /// Provider keys from the credentials file, e.g. `openai = "sk-..."`
+    fn read_file(&mut self, path: &Path) -> Option<HashMap<String, (String, PathBuf)>> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                self.problem("ai.credentials.file", format!("cannot read {}: {}", path.display(), e));
+                return None;
+            }
+        };
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(path).map(|m| m.permissions().mode()).unwrap_or(0);
+            if mode & 0o077 != 0 {
+                self.problem(
+                    "ai.credentials.file",
+                    format!(
+                        "{} is accessible by other users and was ignored (run `chmod 600 {}`)",
+                        path.display(),
+                        path.display()
+                    ),
+                );
+                return None;
+            }
+        }
+
+        let table: toml::Table = match content.parse() {
+            Ok(table) => table,
+            Err(_) => {
+                // The parse error quotes the offending line, which may be a key
+                self.problem("ai.credentials.file", format!("{} is not valid TOML", path.display()));
+                return None;
+            }
+        };
+
+        let mut keys = HashMap::new();
+        for (name, value) in table {
+            match value {
+                toml::Value::String(key) => {
+                    keys.insert(name, (key, path.to_path_buf()));
+                }
+                _ => self.problem(
+                    "ai.credentials.file",
+                    format!("{} in {} must be a string", name, path.display()),
+                ),
+            }
+        }
+        Some(keys)
+    }
+
+    /// Variables from a `.env` file
+    fn read_dotenv(&mut self, path: &Path) -> Option<HashMap<String, (String, PathBuf)>> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                self.problem("ai.credentials.dotenv", format!("cannot read {}: {}", path.display(), e));
+                return None;
+            }
+        };
+
+        Some(
+            parse_dotenv(&content)
+                .into_iter()
+                .map(|(name, value)| (name, (value, path.to_path_buf())))
+                .collect(),
+        )
+    }
+}
+
+/// Environment variable `AiConfig::load_api_keys` reads for a built-in provider
+pub(crate) fn builtin_key_var(id: &ProviderId) -> Option<&'static str> {
+    match id.as_str() {
+        "openai" => Some("OPENAI_API_KEY"),
+        "claude" => Some("ANTHROPIC_API_KEY"),
+        "mistral" => Some("MISTRAL_API_KEY"),
+        "gemini" => Some("GOOGLE_API_KEY"),
+        _ => None,
+    }
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+/// Parse `NAME=value` lines, allowing `export`, quotes and comments
+fn parse_dotenv(content: &str) -> Vec<(String, String)> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                return None;
+            }
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (name, value) = line.split_once('=')?;
+            let value = value.trim();
+
+            let value = match value.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let rest = &value[1..];
+                    rest.find(quote).map_or(rest, |end| &rest[..end])
+                }
+                _ => value.split(" #").next().unwrap_or_default().trim_end(),
+            };
+            Some((name.trim().to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::ExposeSecret;
+
+    fn sources(dir: &Path) -> CredentialSources {
+        CredentialSources {
+            environment: HashMap::new(),
+            file: Some(dir.join(CREDENTIALS_FILE)),
+            dotenv: Some(dir.join(".env")),
+        }
+    }
+
+    fn write_private(path: &Path, content: &str) {
+        std::fs::write(path, content).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_sources_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sources = sources(dir.path());
+        sources.environment.insert("OPENAI_API_KEY".to_string(), "from-env".to_string());
+        write_private(&dir.path().join(CREDENTIALS_FILE), "openai = \"from-file\"\nclaude = \"from-file\"\n");
+        std::fs::write(
+            dir.path().join(".env"),
+            "# keys\nexport ANTHROPIC_API_KEY=from-dotenv\nMISTRAL_API_KEY=\"from-dotenv\" # quoted\n",
+        )
+        .unwrap();
+
+        let credentials = Credentials::load(&AiConfig::default(), &sources);
+        let openai = credentials.get(&ProviderId::OPENAI).unwrap();
+        assert_eq!(openai.key.expose_secret(), "from-env");
+        assert_eq!(openai.source, CredentialSource::Environment("OPENAI_API_KEY".to_string()));
+
+        let claude = credentials.get(&ProviderId::CLAUDE).unwrap();
+        assert_eq!(claude.key.expose_secret(), "from-file");
+        assert!(matches!(claude.source, CredentialSource::File(_)));
+
+        let mistral = credentials.get(&ProviderId::MISTRAL).unwrap();
+        assert_eq!(mistral.key.expose_secret(), "from-dotenv");
+        assert!(matches!(mistral.source, CredentialSource::DotEnv(_)));
+
+        assert!(credentials.get(&ProviderId::GEMINI).is_none());
+        assert!(credentials.problems().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_credential_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = AiConfig::default();
+        let mistral = config.providers.get_mut(&ProviderId::MISTRAL).unwrap();
+        mistral.credential_command = Some("echo from-command".to_string());
+
+        let credentials = Credentials::load(&config, &sources(dir.path()));
+        let credential = credentials.get(&ProviderId::MISTRAL).unwrap();
+        assert_eq!(credential.key.expose_secret(), "from-command");
+        assert_eq!(credential.source, CredentialSource::Command("echo from-command".to_string()));
+
+        let mistral = config.providers.get_mut(&ProviderId::MISTRAL).unwrap();
+        mistral.credential_command = Some("echo secret; echo locked >&2; exit 1".to_string());
+        let credentials = Credentials::load(&config, &sources(dir.path()));
+        assert!(credentials.get(&ProviderId::MISTRAL).is_none());
+        let problem = &credentials.problems()[0];
+        assert_eq!(problem.key, "ai.providers.mistral.credential_command");
+        assert!(problem.message.contains("locked"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_invalid_provider_names_run_no_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("ran");
+        let mut config = AiConfig::default();
+        let mut provider = config.providers[&ProviderId::MISTRAL].clone();
+        provider.credential_command = Some(format!("touch {}", marker.display()));
+        config.providers.insert(ProviderId::new("x.y"), provider);
+
+        let credentials = Credentials::load(&config, &sources(dir.path()));
+        assert!(credentials.get(&ProviderId::new("x.y")).is_none());
+        assert!(!marker.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shared_credentials_file_is_ignored() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(CREDENTIALS_FILE);
+        std::fs::write(&path, "openai = \"sk-test\"\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        let credentials = Credentials::load(&AiConfig::default(), &sources(dir.path()));
+        assert!(credentials.get(&ProviderId::OPENAI).is_none());
+        assert_eq!(credentials.problems()[0].key, "ai.credentials.file");
+    }
+
+    #[test]
+    fn test_keys_are_redacted() {
+        let credential = Credential {
+            key: SecretString::from("sk-test".to_string()),
+            source: CredentialSource::Environment("OPENAI_API_KEY".to_string()),
+        };
+        assert!(!format!("{:?}", credential).contains("sk-test"));
+    }
+}
@@ -2,6 +2,7 @@
 
 use thiserror::Error;
 use crate::types::ProviderId;
+use crate::validation::ConfigIssue;
 
 /// Main error type for CoDev.rs operations
 #[derive(Debug, Error)]
@@ -65,7 +66,7 @@ pub enum LlmError {
     NoProviderAvailable,
 
     #[error("Provider not found: {provider}")]
-    ProviderNotFound { provider: String },
+    ProviderNotFound { provider: ProviderId },
 
     #[error("API key missing for provider: {provider}")]
     ApiKeyMissing { provider: ProviderId },
@@ -99,11 +100,11 @@ pub enum LlmError {
 /// Security-related errors
 #[derive(Debug, Error)]
 pub enum SecurityError {
-    #[error("Command not allowed: {command}")]
-    CommandNotAllowed { command: String },
+    #[error("Command not allowed: {command} ({rule})")]
+    CommandNotAllowed { command: String, rule: String },
 
-    #[error("File access denied: {path}")]
-    FileAccessDenied { path: String },
+    #[error("File access denied: {path} ({rule})")]
+    FileAccessDenied { path: String, rule: String },
 
     #[error("Network access denied")]
     NetworkAccessDenied,
@@ -117,6 +118,15 @@ pub enum SecurityError {
     #[error("Sandbox escape attempt detected")]
     SandboxEscape,
 
+    #[error("Sandbox unavailable: {reason}")]
+    SandboxUnavailable { reason: String },
+
+    #[error("Prompt withheld: {reason} ({rule})")]
+    PromptWithheld { reason: String, rule: String },
+
+    #[error("Audit log tampered at line {line}: {reason}")]
+    AuditLogTampered { line: usize, reason: String },
+
     #[error("Privilege escalation attempt detected")]
     PrivilegeEscalation,
 
@@ -144,6 +154,9 @@ pub enum ConfigError {
 
     #[error("Permission denied accessing config: {path}")]
     PermissionDenied { path: String },
+
+    #[error("Invalid configuration: {}", .issues.iter().map(ToString::to_string).collect::<Vec<_>>().join("; "))]
+    Invalid { issues: Vec<ConfigIssue> },
 }
 
 /// Project analysis errors
@@ -184,12 +197,12 @@ impl From<LlmError> for CodevError {
             }
             LlmError::NoProviderAvailable => {
                 CodevError::LlmProvider {
-                    provider: ProviderId::Ollama, // Default fallback
+                    provider: ProviderId::OLLAMA, // Default fallback
                     message: "No provider available".to_string(),
                 }
             }
             _ => CodevError::LlmProvider {
-                provider: ProviderId::Ollama,
+                provider: ProviderId::OLLAMA,
                 message: error.to_string(),
             }
         }
@@ -4,13 +4,21 @@
 //! This crate provides the foundation typs used across all CoDev components.
 
 pub mod config;
+pub mod credentials;
 pub mod error;
+pub mod loader;
+pub mod migration;
 pub mod types;
+pub mod validation;
 
 // Re-export commonly used types
 pub use config::*;
+pub use credentials::{Credential, CredentialProblem, CredentialSource, Credentials};
 pub use error::*;
+pub use loader::{changed_keys, ConfigLoader, ConfigOrigin, LayeredConfig};
+pub use migration::CURRENT_CONFIG_VERSION;
 pub use types::*;
+pub use validation::{ConfigIssue, Severity};
 
 /// Version information for CoDev.rs
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -0,0 +1,307 @@
+//! Configuration file migrations
+//!
+//! Every configuration file carries a `config_version`; files without one
+//! are version 0. When the file format changes, the version is bumped and a
+//! migration describing the change is added to `MIGRATIONS`. Older files are
+//! upgraded in memory when loaded, and `codev config migrate --write`
+//! rewrites them on disk with their comments intact.
+
+use crate::error::{ConfigError, Result};
+use std::path::Path;
+use toml::Table;
+use toml_edit::DocumentMut;
+
+/// Version of the configuration format written by this build
+pub const CURRENT_CONFIG_VERSION: u32 = 1;
+
+/// Key holding the format version of a file
+pub const VERSION_KEY: &str = "config_version";
+
+/// Sections that can also appear inside `[profiles.<name>]`
+pub(crate) const PROFILE_SECTIONS: &[&str] = &["ai", "security", "logging"];
+
+/// A change to the file format
+enum Change {
+    /// A key was renamed or moved
+    Rename { from: &'static str, to: &'static str },
+}
+
+/// Changes that upgrade a file to `version`
+struct Migration {
+    version: u32,
+    changes: &'static [Change],
+}
+
+/// All migrations, oldest first
+const MIGRATIONS: &[Migration] = &[Migration {
+    version: 1,
+    changes: &[Change::Rename {
+        from: "security.file_access.read_only_path",
+        to: "security.file_access.read_only_paths",
+    }],
+}];
+
+/// A parsed configuration file that migrations can edit
+trait ConfigTree {
+    fn version(&self) -> Option<i64>;
+    fn set_version(&mut self, version: u32);
+    fn profile_names(&self) -> Vec<String>;
+    /// Move the value at `from` to `to`, returning whether anything moved
+    fn rename(&mut self, from: &[&str], to: &[&str]) -> bool;
+}
+
+impl ConfigTree for Table {
+    fn version(&self) -> Option<i64> {
+        self.get(VERSION_KEY).and_then(toml::Value::as_integer)
+    }
+
+    fn set_version(&mut self, version: u32) {
+        self.insert(VERSION_KEY.to_string(), toml::Value::Integer(version.into()));
+    }
+
+    fn profile_names(&self) -> Vec<String> {
+        self.get("profiles")
+            .and_then(toml::Value::as_table)
+            .map(|profiles| profiles.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn rename(&mut self, from: &[&str], to: &[&str]) -> bool {
+        let Some((last, parents)) = from.split_last() else {
+            return false;
+        };
+        let mut table = &mut *self;
+        for part in parents {
+            match table.get_mut(*part).and_then(toml::Value::as_table_mut) {
+                Some(child) => table = child,
+                None => return false,
+            }
+        }
+        let Some(value) = table.remove(*last) else {
+            return false;
+        };
+
+        let Some((last, parents)) = to.split_last() else {
+            return false;
+        };
+        let mut table = &mut *self;
+        for part in parents {
+            let child = table
+                .entry(part.to_string())
+                .or_insert_with(|| toml::Value::Table(Table::new()));
+            match child.as_table_mut() {
+                Some(child) => table = child,
+                None => return false,
+            }
+        }
+        table.insert(last.to_string(), value);
+        true
+    }
+}
+
+impl ConfigTree for DocumentMut {
+    fn version(&self) -> Option<i64> {
+        self.get(VERSION_KEY).and_then(toml_edit::Item::as_integer)
+    }
+
+    fn set_version(&mut self, version: u32) {
+        self[VERSION_KEY] = toml_edit::value(i64::from(version));
+    }
+
+    fn profile_names(&self) -> Vec<String> {
+        self.get("profiles")
+            .and_then(toml_edit::Item::as_table_like)
+            .map(|profiles| profiles.iter().map(|(name, _)| name.to_string()).collect())
+            .unwrap_or_default()
+    }
+
+    fn rename(&mut self, from: &[&str], to: &[&str]) -> bool {
+        let Some((last, parents)) = from.split_last() else {
+            return false;
+        };
+        let mut table: &mut dyn toml_edit::TableLike = self.as_table_mut();
+        for part in parents {
+            match table.get_mut(part).and_then(toml_edit::Item::as_table_like_mut) {
+                Some(child) => table = child,
+                None => return false,
+            }
+        }
+        // The comment above a key belongs to the key, so carry it over
+        let decor = table.get_key_value(last).map(|(key, _)| key.leaf_decor().clone());
+        let Some(item) = table.remove(last) else {
+            return false;
+        };
+
+        let Some((last, parents)) = to.split_last() else {
+            return false;
+        };
+        let mut table: &mut dyn toml_edit::TableLike = self.as_table_mut();
+        for part in parents {
+            if table.get(part).is_none() {
+                let mut child = toml_edit::Table::new();
+                child.set_implicit(true);
+                table.insert(part, toml_edit::Item::Table(child));
+            }
+            match table.get_mut(part).and_then(toml_edit::Item::as_table_like_mut) {
+                Some(child) => table = child,
+                None => return false,
+            }
+        }
+        table.insert(last, item);
+        if let (Some(decor), Some(mut key)) = (decor, table.key_mut(last)) {
+            *key.leaf_decor_mut() = decor;
+        }
+        true
+    }
+}
+
+/// Upgrade a parsed file in memory, returning a note for every change made
+pub fn migrate_table(table: &mut Table) -> Result<Vec<String>> {
+    migrate(table)
+}
+
+/// Upgrade a file while keeping its comments and layout
+pub fn migrate_document(document: &mut DocumentMut) -> Result<Vec<String>> {
+    migrate(document)
+}
+
+/// Upgrade a file on disk, returning the changes it needs
+///
+/// The file is only rewritten when `write` is set. Comments and layout are
+/// preserved. An empty list means the file is up to date.
+pub fn migrate_file(path: &Path, write: bool) -> Result<Vec<String>> {
+    let content = std::fs::read_to_string(path).map_err(|_| ConfigError::FileNotFound {
+        path: path.display().to_string(),
+    })?;
+    let mut document: DocumentMut = content.parse().map_err(|e: toml_edit::TomlError| ConfigError::InvalidFormat {
+        message: format!("{}: {}", path.display(), e),
+    })?;
+
+    let unversioned = document.version().is_none();
+    let mut notes = migrate_document(&mut document)?;
+    if unversioned {
+        notes.push(format!("{} set to {}", VERSION_KEY, CURRENT_CONFIG_VERSION));
+    }
+
+    if write && !notes.is_empty() {
+        std::fs::write(path, document.to_string())?;
+    }
+    Ok(notes)
+}
+
+fn migrate(tree: &mut dyn ConfigTree) -> Result<Vec<String>> {
+    let version = match tree.version() {
+        None => 0,
+        Some(version) if (0..=i64::from(CURRENT_CONFIG_VERSION)).contains(&version) => version as u32,
+        Some(version) => {
+            return Err(ConfigError::InvalidValue {
+                key: VERSION_KEY.to_string(),
+                value: format!(
+                    "{} (this build supports up to {})",
+                    version, CURRENT_CONFIG_VERSION
+                ),
+            }
+            .into())
+        }
+    };
+
+    let mut notes = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|m| m.version > version) {
+        for change in migration.changes {
+            match change {
+                Change::Rename { from, to } => {
+                    for (from, to) in with_profiles(tree, from, to) {
+                        let from_parts: Vec<&str> = from.split('.').collect();
+                        let to_parts: Vec<&str> = to.split('.').collect();
+                        if tree.rename(&from_parts, &to_parts) {
+                            notes.push(format!(
+                                "{} was renamed to {} in config_version {}",
+                                from, to, migration.version
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    tree.set_version(CURRENT_CONFIG_VERSION);
+    Ok(notes)
+}
+
+/// The key itself, plus its copies inside each profile
+fn with_profiles(tree: &dyn ConfigTree, from: &str, to: &str) -> Vec<(String, String)> {
+    let mut keys = vec![(from.to_string(), to.to_string())];
+    let section = from.split('.').next().unwrap_or_default();
+    if PROFILE_SECTIONS.contains(&section) {
+        for name in tree.profile_names() {
+            keys.push((format!("profiles.{}.{}", name, from), format!("profiles.{}.{}", name, to)));
+        }
+    }
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD_FILE: &str = r#"# Team settings
+[security.file_access]
+# Never touch system directories
+read_only_path = ["/usr", "/opt"]
+max_file_size = 1024
+
+[profiles.laptop.security.file_access]
+read_only_path = ["/usr"]
+"#;
+
+    #[test]
+    fn test_migrate_table() {
+        let mut table: Table = OLD_FILE.parse().unwrap();
+        let notes = migrate_table(&mut table).unwrap();
+
+        assert_eq!(notes.len(), 2);
+        let access = &table["security"]["file_access"];
+        assert!(access.get("read_only_path").is_none());
+        assert_eq!(access["read_only_paths"].as_array().unwrap().len(), 2);
+        assert!(table["profiles"]["laptop"]["security"]["file_access"].get("read_only_paths").is_some());
+        assert_eq!(table[VERSION_KEY].as_integer(), Some(i64::from(CURRENT_CONFIG_VERSION)));
+
+        // Already migrated files are left alone
+        assert!(migrate_table(&mut table).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_migrate_document_keeps_comments() {
+        let mut document: DocumentMut = OLD_FILE.parse().unwrap();
+        let notes = migrate_document(&mut document).unwrap();
+        let migrated = document.to_string();
+
+        assert_eq!(notes.len(), 2);
+        assert!(migrated.contains("# Team settings"));
+        assert!(migrated.contains("# Never touch system directories\nread_only_paths"));
+        assert!(migrated.contains("read_only_paths = [\"/usr\", \"/opt\"]"));
+        assert!(!migrated.contains("read_only_path ="));
+        assert!(migrated.contains("config_version = 1"));
+    }
+
+    #[test]
+    fn test_newer_version_is_rejected() {
+        let mut table: Table = "config_version = 99".parse().unwrap();
+        assert!(migrate_table(&mut table).is_err());
+    }
+
+    #[test]
+    fn test_migrate_file_only_writes_when_asked() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, OLD_FILE).unwrap();
+
+        let notes = migrate_file(&path, false).unwrap();
+        assert_eq!(notes.len(), 3);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), OLD_FILE);
+
+        migrate_file(&path, true).unwrap();
+        assert!(migrate_file(&path, false).unwrap().is_empty());
+    }
+}
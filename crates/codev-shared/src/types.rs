@@ -1,6 +1,8 @@
 //! Core types used throughout CoDev.rs
 
+use schemars::JsonSchema;
 use serde::{ Deserilalize, Serialize};
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::path::PathBuf;
 
@@ -54,7 +56,7 @@ impl Language {
 }
 
 /// Environment where CoDev.rs is running
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
 pub enum Environment {
     Development,
     Production,
@@ -62,33 +64,212 @@ pub enum Environment {
 }
 
 /// Security levels for sandbox execution
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
 pub enum SecurityLevel {
     Development, // Full access for local development
     Production, // Restricted sandbox
     Paranoid, // Maximum isolation
 }
 
-/// LLM provider identifiers
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
-pub enum ProviderId {
-    Ollama,
-    OpenAI,
-    Claude,
-    Mistral,
-    Gemini,
+/// What happens to a prompt in which secrets were found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum RedactionAction {
+    /// Cloud providers receive a copy with the secrets replaced
+    Redact,
+    /// The prompt is not sent at all
+    Block,
+    /// The prompt is sent unchanged, but only to local providers
+    Local,
+}
+
+impl std::fmt::Display for RedactionAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RedactionAction::Redact => write!(f, "redact"),
+            RedactionAction::Block => write!(f, "block"),
+            RedactionAction::Local => write!(f, "local"),
+        }
+    }
+}
+
+/// LLM provider identifier
+///
+/// Built-in providers have constants, but any name can be configured in
+/// `AiConfig.providers`. Names are case-insensitive.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ProviderId(Cow<'static, str>);
+
+impl ProviderId {
+    pub const OLLAMA: ProviderId = ProviderId(Cow::Borrowed("ollama"));
+    pub const OPENAI: ProviderId = ProviderId(Cow::Borrowed("openai"));
+    pub const CLAUDE: ProviderId = ProviderId(Cow::Borrowed("claude"));
+    pub const MISTRAL: ProviderId = ProviderId(Cow::Borrowed("mistral"));
+    pub const GEMINI: ProviderId = ProviderId(Cow::Borrowed("gemini"));
+
+    /// Create an identifier from a provider name
+    pub fn new(name: &str) -> Self {
+        ProviderId(Cow::Owned(name.trim().to_lowercase()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
 }
 
 impl std::fmt::Display for ProviderId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::str::FromStr for ProviderId {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let name = s.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(format!("invalid provider name: {:?}", s));
+        }
+        Ok(ProviderId::new(name))
+    }
+}
+
+impl TryFrom<String> for ProviderId {
+    type Error = String;
+
+    fn try_from(name: String) -> std::result::Result<Self, Self::Error> {
+        name.parse()
+    }
+}
+
+impl From<ProviderId> for String {
+    fn from(id: ProviderId) -> Self {
+        id.0.into_owned()
+    }
+}
+
+impl JsonSchema for ProviderId {
+    fn schema_name() -> String {
+        "ProviderId".to_string()
+    }
+
+    fn json_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        string_schema("^[A-Za-z0-9_-]+$", "Provider name, e.g. ollama or claude")
+    }
+}
+
+/// API spoken by a provider
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProviderKind {
+    /// Ollama's native API
+    Ollama,
+    /// OpenAI chat completions, also served by Mistral, Gemini and most gateways
+    OpenaiCompatible,
+    /// Anthropic messages API
+    Anthropic,
+}
+
+impl ProviderKind {
+    /// Kind of a built-in provider, used when the configuration does not set one
+    pub fn default_for(id: &ProviderId) -> Option<Self> {
+        match id.as_str() {
+            "ollama" => Some(ProviderKind::Ollama),
+            "openai" | "mistral" | "gemini" => Some(ProviderKind::OpenaiCompatible),
+            "claude" => Some(ProviderKind::Anthropic),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ProviderKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ProviderId::Ollama => write!(f, "ollama"),
-            ProviderId::OpenAI => write!(f, "Openai"),
-            ProviderId::Claude => write!(f, "claude"),
-            ProviderId::Mistral => write!(f, "mistral"),
-            ProviderId::Gemini => write!(f, "gemini"),
+            ProviderKind::Ollama => write!(f, "ollama"),
+            ProviderKind::OpenaiCompatible => write!(f, "openai-compatible"),
+            ProviderKind::Anthropic => write!(f, "anthropic"),
+        }
+    }
+}
+
+/// A model on a specific provider, written `provider/model`
+///
+/// Only the first `/` separates the two, so model names such as
+/// `hf.co/org/model` keep their slashes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ModelTarget {
+    pub provider: ProviderId,
+    pub model: String,
+}
+
+impl std::fmt::Display for ModelTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.provider, self.model)
+    }
+}
+
+impl std::str::FromStr for ModelTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (provider, model) = s
+            .split_once('/')
+            .ok_or_else(|| format!("expected provider/model, got {:?}", s))?;
+        let model = model.trim();
+        if model.is_empty() {
+            return Err(format!("missing model name in {:?}", s));
         }
+        Ok(ModelTarget {
+            provider: provider.parse()?,
+            model: model.to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for ModelTarget {
+    type Error = String;
+
+    fn try_from(s: String) -> std::result::Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<ModelTarget> for String {
+    fn from(target: ModelTarget) -> Self {
+        target.to_string()
+    }
+}
+
+impl JsonSchema for ModelTarget {
+    fn schema_name() -> String {
+        "ModelTarget".to_string()
+    }
+
+    fn json_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        string_schema("^[A-Za-z0-9_-]+/.+$", "Model on a provider, written provider/model")
+    }
+}
+
+/// Schema of a string type checked by a pattern
+fn string_schema(pattern: &str, description: &str) -> schemars::schema::Schema {
+    use schemars::schema::{InstanceType, Metadata, SchemaObject, StringValidation};
+
+    SchemaObject {
+        instance_type: Some(InstanceType::String.into()),
+        string: Some(Box::new(StringValidation {
+            pattern: Some(pattern.to_string()),
+            ..Default::default()
+        })),
+        metadata: Some(Box::new(Metadata {
+            description: Some(description.to_string()),
+            ..Default::default()
+        })),
+        ..Default::default()
     }
+    .into()
 }
 
 /// Health status of a component
@@ -113,7 +294,7 @@ impl CommandResult {
         Self {
             stdout,
             stderr,
-            success: exit_code = 0,
+            success: exit_code == 0,
             exit_code,
         }
     }
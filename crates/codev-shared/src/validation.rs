@@ -0,0 +1,487 @@
+//! Configuration validation
+//!
+//! `CodevConfig::check` walks the whole configuration and returns every
+//! problem it finds, each tagged with the dotted key it is about, instead of
+//! stopping at the first one.
+
+use crate::config::{default_command_rules, CodevConfig, FileAccessConfig, RedactionConfig};
+use crate::credentials::{builtin_key_var, Credentials};
+use crate::types::{ProviderId, ProviderKind, SecurityLevel};
+use secrecy::SecretString;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// How bad a configuration issue is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Works, but probably not as intended
+    Warning,
+    /// CoDev cannot run with this configuration
+    Error,
+}
+
+/// A problem found in the configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    pub severity: Severity,
+    /// Dotted key path, e.g. `ai.providers.claude.temperature`
+    pub key: String,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    fn error(key: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            key: key.into(),
+            message: message.into(),
+        }
+    }
+
+    fn warning(key: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            key: key.into(),
+            message: message.into(),
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}: {}: {}", severity, self.key, self.message)
+    }
+}
+
+impl CodevConfig {
+    /// Every issue in the configuration, using the API keys that can be loaded
+    ///
+    /// Key sources that are configured but fail are reported as warnings.
+    pub fn check(&self) -> Vec<ConfigIssue> {
+        self.check_with_credentials(&self.ai.load_credentials())
+    }
+
+    /// Every issue in the configuration, given credentials already loaded
+    pub fn check_with_credentials(&self, credentials: &Credentials) -> Vec<ConfigIssue> {
+        let mut issues: Vec<ConfigIssue> = credentials
+            .problems()
+            .iter()
+            .map(|problem| ConfigIssue::warning(problem.key.clone(), problem.message.clone()))
+            .collect();
+        issues.extend(self.check_with_keys(&credentials.clone().into_keys()));
+        issues.sort_by(|a, b| b.severity.cmp(&a.severity).then_with(|| a.key.cmp(&b.key)));
+        issues
+    }
+
+    /// Every issue in the configuration, given the API keys available
+    pub fn check_with_keys(&self, api_keys: &HashMap<ProviderId, SecretString>) -> Vec<ConfigIssue> {
+        let mut issues = Vec::new();
+        self.check_providers(api_keys, &mut issues);
+        self.check_references(&mut issues);
+        check_file_access(&self.security.file_access, &mut issues);
+        check_redaction(&self.security.redaction, &mut issues);
+
+        // The built-in rules stay in place when a level allows fewer commands
+        let builtin = default_command_rules();
+        for (program, rule) in &self.security.commands {
+            if !self.security.allowed_commands.contains(program) && builtin.get(program) != Some(rule) {
+                issues.push(ConfigIssue::warning(
+                    format!("security.commands.{}", program),
+                    "has rules but is not in security.allowed_commands, so it never runs",
+                ));
+            }
+        }
+
+        if !self.security.sandbox_enabled && self.security.default_level != SecurityLevel::Development {
+            issues.push(ConfigIssue::warning(
+                "security.sandbox_enabled",
+                format!("is off although security.default_level is {:?}", self.security.default_level),
+            ));
+        }
+
+        if self.ai.ollama.idle_unload_seconds == Some(0) {
+            issues.push(ConfigIssue::error(
+                "ai.ollama.idle_unload_seconds",
+                "must be greater than 0",
+            ));
+        }
+
+        if self.security.sandbox.max_memory == Some(0) {
+            issues.push(ConfigIssue::error(
+                "security.sandbox.max_memory",
+                "must be greater than 0",
+            ));
+        }
+
+        if let Some(issue) = check_workspace(&self.workspace.default_path) {
+            issues.push(issue);
+        }
+
+        // Profiles that are not selected would otherwise go unchecked
+        for name in self.profiles.keys() {
+            if let Err(e) = crate::loader::with_profile_applied(self, name) {
+                issues.push(ConfigIssue::error(format!("profiles.{}", name), e.to_string()));
+            }
+        }
+
+        issues.sort_by(|a, b| b.severity.cmp(&a.severity).then_with(|| a.key.cmp(&b.key)));
+        issues
+    }
+
+    fn check_providers(&self, api_keys: &HashMap<ProviderId, SecretString>, issues: &mut Vec<ConfigIssue>) {
+        let ai = &self.ai;
+        if ai.providers.is_empty() {
+            issues.push(ConfigIssue::error("ai.providers", "no provider is configured"));
+        }
+
+        for (id, provider) in &ai.providers {
+            let key = format!("ai.providers.{}", id);
+            if let Err(message) = id.as_str().parse::<ProviderId>() {
+                issues.push(ConfigIssue::error(key, message));
+                continue;
+            }
+            let Some(kind) = provider.resolved_kind(id) else {
+                issues.push(ConfigIssue::error(
+                    format!("{}.kind", key),
+                    "required for providers other than the built-in ones",
+                ));
+                continue;
+            };
+
+            if let Some(temperature) = provider.temperature {
+                // Anthropic rejects temperatures above 1.0
+                let max = if kind == ProviderKind::Anthropic { 1.0 } else { 2.0 };
+                if !(0.0..=max).contains(&temperature) {
+                    issues.push(ConfigIssue::error(
+                        format!("{}.temperature", key),
+                        format!("{} is outside 0.0..={:.1}", temperature, max),
+                    ));
+                }
+            }
+
+            let names_key_source = provider.api_key_env.is_some() || provider.credential_command.is_some();
+            if provider.enabled && needs_api_key(id, kind, names_key_source) && !api_keys.contains_key(id) {
+                let var = provider.api_key_env.clone().or_else(|| builtin_key_var(id).map(str::to_string));
+                let message = match var {
+                    Some(var) => format!("enabled but no API key found in {}", var),
+                    None => "enabled but no API key found".to_string(),
+                };
+                // Without its key the provider is skipped, which only breaks
+                // things when it is the default
+                if *id == ai.default_provider {
+                    issues.push(ConfigIssue::error(key, message));
+                } else {
+                    issues.push(ConfigIssue::warning(key, message));
+                }
+            }
+        }
+    }
+
+    fn check_references(&self, issues: &mut Vec<ConfigIssue>) {
+        let ai = &self.ai;
+        match ai.providers.get(&ai.default_provider) {
+            None => issues.push(ConfigIssue::error(
+                "ai.default_provider",
+                format!("'{}' is not configured in ai.providers", ai.default_provider),
+            )),
+            Some(provider) if !provider.enabled => issues.push(ConfigIssue::error(
+                "ai.default_provider",
+                format!("'{}' is disabled", ai.default_provider),
+            )),
+            Some(_) => {}
+        }
+
+        let unconfigured = |id: &ProviderId| !ai.providers.contains_key(id);
+        for (index, id) in ai.fallback_chain.iter().enumerate() {
+            if unconfigured(id) {
+                issues.push(ConfigIssue::warning(
+                    format!("ai.fallback_chain[{}]", index),
+                    format!("'{}' is not configured in ai.providers and will be skipped", id),
+                ));
+            }
+        }
+
+        for (environment, ids) in ai.environment_providers.iter().flatten() {
+            for (index, id) in ids.iter().enumerate() {
+                if unconfigured(id) {
+                    issues.push(ConfigIssue::warning(
+                        format!("ai.environment_providers.{}[{}]", environment, index),
+                        format!("'{}' is not configured in ai.providers", id),
+                    ));
+                }
+            }
+        }
+
+        for (alias, targets) in &ai.aliases {
+            for (index, target) in targets.iter().enumerate() {
+                if unconfigured(&target.provider) {
+                    issues.push(ConfigIssue::warning(
+                        format!("ai.aliases.{}[{}]", alias, index),
+                        format!("'{}' is not configured in ai.providers", target.provider),
+                    ));
+                }
+            }
+        }
+
+        for (index, rule) in self.security.residency.iter().enumerate() {
+            let key = format!("security.residency[{}]", index);
+            if rule.paths.is_empty() && rule.remotes.is_empty() {
+                issues.push(ConfigIssue::error(key.clone(), "needs paths or remotes to match"));
+            }
+            if rule.providers.is_empty() {
+                issues.push(ConfigIssue::warning(
+                    format!("{}.providers", key),
+                    "is empty, so matching prompts are never sent",
+                ));
+            }
+            for (provider_index, id) in rule.providers.iter().enumerate() {
+                if unconfigured(id) {
+                    issues.push(ConfigIssue::warning(
+                        format!("{}.providers[{}]", key, provider_index),
+                        format!("'{}' is not configured in ai.providers", id),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Whether a provider cannot work without an API key
+///
+/// Custom OpenAI-compatible endpoints are often self-hosted and keyless, so
+/// they only need one when they name a variable or command for it.
+fn needs_api_key(id: &ProviderId, kind: ProviderKind, names_key_source: bool) -> bool {
+    match kind {
+        ProviderKind::Ollama => false,
+        ProviderKind::Anthropic => true,
+        ProviderKind::OpenaiCompatible => names_key_source || builtin_key_var(id).is_some(),
+    }
+}
+
+/// Report contradictory and unreachable file access rules
+///
+/// A forbidden path nested in an allowed one is a deliberate carve-out, but
+/// the same path in two lists contradicts itself, and a path nested in a
+/// forbidden one can never be reached.
+fn check_file_access(access: &FileAccessConfig, issues: &mut Vec<ConfigIssue>) {
+    let lists: [(&str, &[PathBuf]); 3] = [
+        ("read_only_paths", &access.read_only_paths),
+        ("write_allowed_paths", &access.write_allowed_paths),
+        ("forbidden_paths", &access.forbidden_paths),
+    ];
+
+    for (i, (name, paths)) in lists.iter().enumerate() {
+        for (index, path) in paths.iter().enumerate() {
+            let key = format!("security.file_access.{}[{}]", name, index);
+            for (other_name, other_paths) in &lists[i + 1..] {
+                if other_paths.contains(path) {
+                    issues.push(ConfigIssue::error(
+                        key.clone(),
+                        format!("{} is also listed in {}", path.display(), other_name),
+                    ));
+                }
+            }
+
+            if *name != "forbidden_paths" {
+                if let Some(forbidden) = access.forbidden_paths.iter().find(|f| path != *f && path.starts_with(f)) {
+                    issues.push(ConfigIssue::warning(
+                        key,
+                        format!("{} is inside forbidden path {}", path.display(), forbidden.display()),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Check that the redaction patterns compile
+fn check_redaction(redaction: &RedactionConfig, issues: &mut Vec<ConfigIssue>) {
+    for (index, pattern) in redaction.patterns.iter().enumerate() {
+        if let Err(e) = regex::Regex::new(pattern) {
+            // Syntax errors span several lines, ending with the reason
+            let message = e.to_string();
+            let reason = message.lines().last().unwrap_or_default().trim_start_matches("error: ");
+            issues.push(ConfigIssue::error(
+                format!("security.redaction.patterns[{}]", index),
+                format!("is not a valid regular expression: {}", reason),
+            ));
+        }
+    }
+
+    if redaction.entropy_threshold < 0.0 {
+        issues.push(ConfigIssue::error(
+            "security.redaction.entropy_threshold",
+            "must not be negative",
+        ));
+    }
+}
+
+/// Check that the workspace exists or can be created
+fn check_workspace(path: &Path) -> Option<ConfigIssue> {
+    const KEY: &str = "workspace.default_path";
+
+    if path.exists() {
+        if path.is_dir() {
+            return None;
+        }
+        return Some(ConfigIssue::error(KEY, format!("{} is not a directory", path.display())));
+    }
+
+    // The nearest existing ancestor decides whether it can be created
+    let ancestor = path.ancestors().skip(1).find(|p| p.as_os_str().is_empty() || p.exists());
+    let writable = match ancestor {
+        Some(dir) if dir.as_os_str().is_empty() => std::env::current_dir()
+            .ok()
+            .and_then(|cwd| cwd.metadata().ok())
+            .is_some_and(|m| !m.permissions().readonly()),
+        Some(dir) => dir.metadata().is_ok_and(|m| m.is_dir() && !m.permissions().readonly()),
+        None => false,
+    };
+
+    if writable {
+        Some(ConfigIssue::warning(
+            KEY,
+            format!("{} does not exist and will be created", path.display()),
+        ))
+    } else {
+        Some(ConfigIssue::error(
+            KEY,
+            format!("{} does not exist and cannot be created", path.display()),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ProviderConfig, ResidencyRule};
+
+    fn config(workspace: &Path) -> CodevConfig {
+        let mut config = CodevConfig::default();
+        config.workspace.default_path = workspace.to_path_buf();
+        config
+    }
+
+    fn keys(issues: &[ConfigIssue]) -> Vec<&str> {
+        issues.iter().map(|issue| issue.key.as_str()).collect()
+    }
+
+    #[test]
+    fn test_default_config_has_no_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let issues = config(dir.path()).check_with_keys(&HashMap::new());
+
+        assert!(!issues.iter().any(ConfigIssue::is_error), "{:?}", issues);
+        // claude, openai and gemini are in the default chain but not configured
+        assert!(keys(&issues).contains(&"ai.fallback_chain[2]"));
+    }
+
+    #[test]
+    fn test_reports_every_issue() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config(&dir.path().join("missing/deeper"));
+        std::fs::write(dir.path().join("missing"), "").unwrap();
+
+        let ollama = config.ai.providers.get_mut(&ProviderId::OLLAMA).unwrap();
+        ollama.temperature = Some(3.0);
+        let gateway = ollama.clone();
+        config.ai.providers.insert(ProviderId::new("my gateway"), gateway);
+        config.ai.providers.insert(
+            ProviderId::CLAUDE,
+            ProviderConfig {
+                kind: None,
+                enabled: true,
+                model: "claude-3-5-sonnet-latest".to_string(),
+                max_tokens: None,
+                temperature: Some(1.5),
+                endpoint: None,
+                endpoints: Vec::new(),
+                timeout_seconds: None,
+                max_retries: None,
+                api_key_env: None,
+                credential_command: None,
+                cost_per_token: None,
+            },
+        );
+        config.ai.default_provider = ProviderId::CLAUDE;
+        config.ai.ollama.idle_unload_seconds = Some(0);
+        config.security.file_access.forbidden_paths = vec![PathBuf::from("/tmp"), PathBuf::from("/usr")];
+        config.security.file_access.write_allowed_paths.push(PathBuf::from("/usr/local"));
+        config.security.redaction.patterns = vec!["internal-[0-9]+".to_string(), "token=(".to_string()];
+        config.security.residency.push(ResidencyRule {
+            paths: Vec::new(),
+            remotes: Vec::new(),
+            providers: vec![ProviderId::new("lab-ollama")],
+        });
+
+        let issues = config.check_with_keys(&HashMap::new());
+        let errors: Vec<&str> = issues.iter().filter(|i| i.is_error()).map(|i| i.key.as_str()).collect();
+
+        assert_eq!(
+            errors,
+            vec![
+                "ai.ollama.idle_unload_seconds",
+                "ai.providers.claude",
+                "ai.providers.claude.temperature",
+                "ai.providers.my gateway",
+                "ai.providers.ollama.temperature",
+                "security.file_access.read_only_paths[0]",
+                "security.file_access.write_allowed_paths[1]",
+                "security.redaction.patterns[1]",
+                "security.residency[0]",
+                "workspace.default_path",
+            ]
+        );
+        assert!(keys(&issues).contains(&"security.file_access.write_allowed_paths[3]"));
+        assert!(keys(&issues).contains(&"security.residency[0].providers[0]"));
+
+        let error = config.validate().unwrap_err().to_string();
+        assert!(error.contains("ai.providers.claude.temperature"), "{}", error);
+        assert!(
+            error.contains("security.redaction.patterns[1]: is not a valid regular expression: unclosed group"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_api_key_satisfies_provider() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config(dir.path());
+        config.ai.providers.get_mut(&ProviderId::MISTRAL).unwrap().enabled = true;
+
+        let issues = config.check_with_keys(&HashMap::new());
+        assert!(keys(&issues).contains(&"ai.providers.mistral"));
+
+        let api_keys = HashMap::from([(ProviderId::MISTRAL, SecretString::from("key".to_string()))]);
+        let issues = config.check_with_keys(&api_keys);
+        assert!(!keys(&issues).contains(&"ai.providers.mistral"));
+    }
+
+    #[test]
+    fn test_broken_profile_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = config(dir.path());
+        let mut ai = toml::Table::new();
+        ai.insert("default_provider".to_string(), toml::Value::Integer(3));
+        config.profiles.insert(
+            "cloud".to_string(),
+            crate::config::ConfigProfile {
+                ai: Some(ai),
+                ..Default::default()
+            },
+        );
+
+        let issues = config.check_with_keys(&HashMap::new());
+        assert!(keys(&issues).contains(&"profiles.cloud"), "{:?}", issues);
+    }
+}